@@ -0,0 +1,305 @@
+//Uses
+use crate::auth::SharedPolicy;
+use crate::directory_index::civil_time;
+use crate::listing::{resolve_share_path, share_path, walk_share, ShareMap, WalkEntry};
+use crate::manifest::encode_uri_component;
+use crate::types::LinkedPath;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc;
+use warp::filters::BoxedFilter;
+use warp::http::{header, Response, StatusCode};
+use warp::hyper::body::{Body, Bytes};
+use warp::{Filter, Rejection, Reply};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, DateTime, ZipWriter};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+// Chunks waiting for a slow client before the archive writer blocks
+const CHANNEL_CHUNKS: usize = 16;
+// Deflating these again only costs time
+const COMPRESSED_EXTENSIONS: [&str; 24] = [
+    "zip", "gz", "tgz", "bz2", "xz", "zst", "7z", "rar", "jpg", "jpeg", "png", "gif", "webp",
+    "heic", "avif", "mp3", "m4a", "aac", "ogg", "opus", "flac", "mp4", "mkv", "webm",
+];
+
+#[derive(Clone, Copy)]
+enum ArchiveFormat {
+    Zip,
+    TarGz,
+}
+
+impl ArchiveFormat {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "zip" => Some(ArchiveFormat::Zip),
+            "tar.gz" | "tgz" => Some(ArchiveFormat::TarGz),
+            _ => None,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::TarGz => "tar.gz",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "application/zip",
+            ArchiveFormat::TarGz => "application/gzip",
+        }
+    }
+}
+
+// `GET /<share>/<dir>?archive=zip|tar.gz` streaming the folder as an archive
+// while it is being read, so memory use doesn't grow with the tree. Requests
+// without `archive` fall through to the file routes.
+pub fn archive_route(
+    shares: ShareMap,
+    access: SharedPolicy,
+) -> BoxedFilter<(warp::reply::Response,)> {
+    warp::get()
+        .and(share_path(shares, access))
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(
+            |share: LinkedPath, relative: String, query: HashMap<String, String>| async move {
+                let format = match query.get("archive") {
+                    Some(format) => ArchiveFormat::parse(format),
+                    None => return Err(warp::reject::not_found()),
+                };
+                // Answered here, a rejection would lose to the routes behind
+                let Some(format) = format else {
+                    let reply = warp::reply::with_status(
+                        "Unsupported archive format, use zip or tar.gz",
+                        StatusCode::BAD_REQUEST,
+                    );
+                    return Ok(reply.into_response());
+                };
+                let resolve_share = share.clone();
+                let resolve_relative = relative.clone();
+                let dir = tauri::async_runtime::spawn_blocking(move || {
+                    resolve_share_path(&resolve_share, &resolve_relative)
+                })
+                .await
+                .ok()
+                .flatten()
+                .filter(|dir| dir.is_dir())
+                .ok_or_else(warp::reject::not_found)?;
+
+                // Entries sit inside a folder named like the one downloaded
+                let base = relative
+                    .split('/')
+                    .rfind(|segment| !segment.is_empty())
+                    .unwrap_or(&share.name)
+                    .to_string();
+                Ok::<_, Rejection>(stream_archive(share, dir, base, format))
+            },
+        )
+        .boxed()
+}
+
+fn stream_archive(
+    share: LinkedPath,
+    dir: PathBuf,
+    base: String,
+    format: ArchiveFormat,
+) -> warp::reply::Response {
+    let filename = format!("{}.{}", base, format.extension());
+    let (chunk_sender, mut chunks) = mpsc::channel(CHANNEL_CHUNKS);
+    let writer = tauri::async_runtime::spawn_blocking(move || {
+        let mut out = ChannelWriter::new(chunk_sender);
+        match format {
+            ArchiveFormat::Zip => write_zip(&share, &dir, &base, &mut out)?,
+            ArchiveFormat::TarGz => write_tar_gz(&share, &dir, &base, &mut out)?,
+        }
+        out.flush()
+    });
+
+    let (mut sender, body) = Body::channel();
+    tauri::async_runtime::spawn(async move {
+        while let Some(chunk) = chunks.recv().await {
+            // Dropping the receiver stops the writer once the client is gone
+            if sender.send_data(chunk).await.is_err() {
+                return;
+            }
+        }
+        match writer.await {
+            Ok(Ok(())) => {}
+            // The client must not mistake a cut off archive for a complete one
+            Ok(Err(e)) => {
+                eprintln!("Failed to write archive: {}", e);
+                sender.abort();
+            }
+            Err(e) => {
+                eprintln!("Failed to write archive: {}", e);
+                sender.abort();
+            }
+        }
+    });
+
+    let mut response = Response::new(body);
+    *response.status_mut() = StatusCode::OK;
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static(format.content_type()),
+    );
+    if let Ok(disposition) = header::HeaderValue::from_str(&content_disposition(&filename)) {
+        headers.insert(header::CONTENT_DISPOSITION, disposition);
+    }
+    response
+}
+
+// ASCII fallback for old clients plus the exact name as RFC 5987 `filename*`
+fn content_disposition(filename: &str) -> String {
+    let fallback: String = filename
+        .chars()
+        .map(|c| {
+            if c.is_ascii_graphic() && c != '"' && c != '\\' || c == ' ' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback,
+        encode_uri_component(filename)
+    )
+}
+
+fn write_zip<W: Write>(share: &LinkedPath, dir: &Path, base: &str, out: W) -> io::Result<()> {
+    let mut zip = ZipWriter::new_stream(out);
+    // add_directory() leaves out the data descriptor streamed entries
+    // announce, which unzip rejects, so folders are written as empty entries
+    let folder_options = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Stored)
+        .unix_permissions(0o755);
+    zip.start_file(format!("{}/", base), folder_options)
+        .map_err(io::Error::other)?;
+    walk_share(share, dir, &mut |entry: WalkEntry| {
+        let name = format!("{}/{}", base, entry.relative);
+        let modified = zip_time(&entry.metadata);
+        if entry.metadata.is_dir() {
+            let options = folder_options.last_modified_time(modified);
+            return zip
+                .start_file(format!("{}/", name), options)
+                .map_err(io::Error::other);
+        }
+
+        // Files that vanished or can't be read are left out
+        let Ok(mut file) = File::open(&entry.path) else {
+            return Ok(());
+        };
+        let compression = if is_compressed(&entry.relative) {
+            CompressionMethod::Stored
+        } else {
+            CompressionMethod::Deflated
+        };
+        let options = SimpleFileOptions::default()
+            .last_modified_time(modified)
+            .compression_method(compression)
+            .large_file(entry.metadata.len() >= u32::MAX as u64);
+        zip.start_file(name, options).map_err(io::Error::other)?;
+        io::copy(&mut file, &mut zip)?;
+        Ok(())
+    })?;
+    zip.finish().map_err(io::Error::other)?;
+    Ok(())
+}
+
+fn write_tar_gz<W: Write>(share: &LinkedPath, dir: &Path, base: &str, out: W) -> io::Result<()> {
+    let mut tar = tar::Builder::new(GzEncoder::new(out, Compression::fast()));
+    tar.append_dir(base, dir)?;
+    walk_share(share, dir, &mut |entry: WalkEntry| {
+        let name = format!("{}/{}", base, entry.relative);
+        if entry.metadata.is_dir() {
+            return tar.append_dir(name, &entry.path);
+        }
+        let Ok(mut file) = File::open(&entry.path) else {
+            return Ok(());
+        };
+        tar.append_file(name, &mut file)
+    })?;
+    tar.into_inner()?.finish()?;
+    Ok(())
+}
+
+fn is_compressed(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            COMPRESSED_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+        })
+}
+
+// ZIP stores local time without a zone, UTC is the closest to portable.
+// Times the format can't hold fall back to its 1980 epoch.
+fn zip_time(metadata: &std::fs::Metadata) -> DateTime {
+    let secs = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let (year, month, day, hour, minute, second) = civil_time(secs);
+    u16::try_from(year)
+        .ok()
+        .and_then(|year| {
+            DateTime::from_date_and_time(
+                year,
+                month as u8,
+                day as u8,
+                hour as u8,
+                minute as u8,
+                // Stored in 2 second steps
+                second.min(58) as u8,
+            )
+            .ok()
+        })
+        .unwrap_or_default()
+}
+
+// Hands what the archive writers produce to the response body in chunks,
+// blocking while the client is behind
+struct ChannelWriter {
+    buf: Vec<u8>,
+    sender: mpsc::Sender<Bytes>,
+}
+
+impl ChannelWriter {
+    fn new(sender: mpsc::Sender<Bytes>) -> Self {
+        ChannelWriter {
+            buf: Vec::with_capacity(CHUNK_SIZE),
+            sender,
+        }
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::replace(&mut self.buf, Vec::with_capacity(CHUNK_SIZE));
+        self.sender
+            .blocking_send(Bytes::from(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client disconnected"))
+    }
+}
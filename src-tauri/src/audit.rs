@@ -0,0 +1,71 @@
+//Uses
+use crate::config::config_dir;
+use crate::types::{AuditEntry, FileError};
+use std::collections::VecDeque;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+pub const AUDIT_LOG_FILE_NAME: &str = "audit.log";
+// The log moves to audit.log.1 once it grows past this, replacing the old one
+const MAX_LOG_SIZE: u64 = 10 * 1024 * 1024;
+const DEFAULT_READ_LIMIT: usize = 200;
+// More than a full log holds
+const MAX_READ_LIMIT: usize = 100_000;
+
+// Serializes appends from concurrent requests so lines don't interleave
+lazy_static::lazy_static! {
+    static ref AUDIT_LOG: Mutex<()> = Mutex::new(());
+}
+
+fn audit_log_path() -> PathBuf {
+    config_dir().join(AUDIT_LOG_FILE_NAME)
+}
+
+// Appends one JSON line. Failing to audit doesn't fail the write itself,
+// it has already happened by the time it is recorded.
+pub fn record(entry: &AuditEntry) {
+    let _guard = AUDIT_LOG.lock().unwrap();
+    if let Err(e) = append(entry) {
+        eprintln!("Failed to write audit log: {}", e);
+    }
+}
+
+fn append(entry: &AuditEntry) -> Result<(), FileError> {
+    let path = audit_log_path();
+    if fs::metadata(&path).is_ok_and(|metadata| metadata.len() >= MAX_LOG_SIZE) {
+        fs::rename(&path, path.with_extension("log.1"))?;
+    }
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    file.write_all(&line)?;
+    Ok(())
+}
+
+// The newest `limit` entries, oldest first. Lines that don't parse are skipped.
+#[tauri::command]
+pub fn get_audit_log(limit: Option<usize>) -> Result<Vec<AuditEntry>, FileError> {
+    let limit = limit.unwrap_or(DEFAULT_READ_LIMIT).min(MAX_READ_LIMIT);
+    let file = match fs::File::open(audit_log_path()) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    // Grows with the entries actually read
+    let mut entries = VecDeque::with_capacity(limit.min(DEFAULT_READ_LIMIT));
+    for line in BufReader::new(file).lines() {
+        let Ok(entry) = serde_json::from_str::<AuditEntry>(&line?) else {
+            continue;
+        };
+        if entries.len() == limit {
+            entries.pop_front();
+        }
+        if limit > 0 {
+            entries.push_back(entry);
+        }
+    }
+    Ok(entries.into())
+}
@@ -0,0 +1,543 @@
+//Uses
+use crate::types::{AccessGrant, FileError, GrantSecret, Network};
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use base64::Engine;
+use rand::RngCore;
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex as StdMutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::Semaphore;
+use warp::http::StatusCode;
+use warp::{Filter, Rejection, Reply};
+
+pub type SharedPolicy = Arc<RwLock<AccessPolicy>>;
+
+// Verified Basic credentials remembered per network
+const MAX_VERIFIED_BASIC: usize = 256;
+// Argon2 runs at once across all servers, each takes about 19 MiB
+const MAX_PARALLEL_VERIFICATIONS: usize = 4;
+// Wrong passwords for a username before it has to wait between attempts
+const FREE_FAILURES: u32 = 3;
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+// Policies of networks that have been served, so grant changes reach
+// running servers without a restart
+lazy_static::lazy_static! {
+    static ref ACTIVE_POLICIES: StdMutex<HashMap<String, SharedPolicy>> =
+        StdMutex::new(HashMap::new());
+    static ref VERIFICATIONS: Semaphore = Semaphore::new(MAX_PARALLEL_VERIFICATIONS);
+}
+
+#[derive(Debug)]
+pub struct Unauthorized {
+    // Ask browsers for a username and password
+    basic: bool,
+}
+
+impl warp::reject::Reject for Unauthorized {}
+
+#[derive(Debug)]
+pub struct Forbidden;
+
+impl warp::reject::Reject for Forbidden {}
+
+// Who may read a network's shares
+#[derive(Default)]
+pub struct AccessPolicy {
+    // Internet networks' own token, grants access to every share
+    owner_token: Option<String>,
+    grants: Vec<AccessGrant>,
+    // Basic credentials that already passed Argon2, by grant id, so browsers
+    // resending them with every request don't pay for a hash each time
+    verified_basic: StdMutex<HashMap<String, String>>,
+    // Recent wrong passwords by username. Only usernames of password grants
+    // are hashed for, so this can't grow past them.
+    failed_basic: StdMutex<HashMap<String, FailedLogins>>,
+}
+
+struct FailedLogins {
+    count: u32,
+    // No password is checked for the username before then
+    retry_at: Instant,
+}
+
+// Result of checking a request against the policy without hashing anything
+enum Credentials {
+    Checked(Option<Access>),
+    // Basic credentials that weren't verified before, with the (grant id,
+    // password hash) of each grant for their username
+    Unverified {
+        encoded: String,
+        username: String,
+        password: String,
+        candidates: Vec<(String, String)>,
+    },
+}
+
+// What an authenticated request may read
+#[derive(Clone)]
+pub struct Access {
+    // The grant the request authenticated with, None for the owner token and
+    // open networks
+    pub grant_id: Option<String>,
+    // None allows every share
+    shares: Option<HashSet<String>>,
+}
+
+impl Access {
+    fn all() -> Self {
+        Access {
+            grant_id: None,
+            shares: None,
+        }
+    }
+
+    pub fn allows(&self, share: &str) -> bool {
+        self.shares
+            .as_ref()
+            .is_none_or(|shares| shares.contains(share))
+    }
+}
+
+impl AccessPolicy {
+    pub fn for_network(network: &Network) -> Self {
+        let owner_token = match network {
+            Network::InternetNetwork { access_token, .. } if !access_token.is_empty() => {
+                Some(access_token.clone())
+            }
+            _ => None,
+        };
+        AccessPolicy {
+            owner_token,
+            grants: network.access_grants().to_vec(),
+            verified_basic: StdMutex::new(HashMap::new()),
+            failed_basic: StdMutex::new(HashMap::new()),
+        }
+    }
+
+    // Revoked and expired grants still count, a network doesn't open up
+    // again when its last invite runs out
+    pub fn is_open(&self) -> bool {
+        self.owner_token.is_none() && self.grants.is_empty()
+    }
+
+    fn uses_passwords(&self) -> bool {
+        self.grants
+            .iter()
+            .any(|grant| matches!(grant.secret, GrantSecret::Password { .. }))
+    }
+
+    fn check(&self, authorization: Option<&str>, query_token: Option<&str>) -> Credentials {
+        if self.is_open() {
+            return Credentials::Checked(Some(Access::all()));
+        }
+
+        let bearer = authorization
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .or(query_token);
+        if let Some(bearer) = bearer {
+            if let Some(owner_token) = &self.owner_token {
+                if constant_time_eq(bearer, owner_token) {
+                    return Credentials::Checked(Some(Access::all()));
+                }
+            }
+            return Credentials::Checked(
+                self.active_grants()
+                    .find(|grant| match &grant.secret {
+                        GrantSecret::Token { token } => constant_time_eq(bearer, token),
+                        GrantSecret::Password { .. } => false,
+                    })
+                    .map(grant_access),
+            );
+        }
+
+        match authorization.and_then(|value| value.strip_prefix("Basic ")) {
+            Some(basic) => self.check_basic(basic),
+            None => Credentials::Checked(None),
+        }
+    }
+
+    fn check_basic(&self, encoded: &str) -> Credentials {
+        let verified = self.verified_basic.lock().unwrap().get(encoded).cloned();
+        if let Some(grant_id) = verified {
+            // Revocation and expiry still apply to cached credentials
+            return Credentials::Checked(self.active_grant_access(&grant_id));
+        }
+
+        let decoded = base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok());
+        let Some((username, password)) = decoded.as_deref().and_then(|d| d.split_once(':')) else {
+            return Credentials::Checked(None);
+        };
+        if self.throttled(username) {
+            return Credentials::Checked(None);
+        }
+        let candidates = self
+            .active_grants()
+            .filter_map(|grant| match &grant.secret {
+                GrantSecret::Password {
+                    username: expected,
+                    password_hash,
+                } if expected == username => Some((grant.id.clone(), password_hash.clone())),
+                _ => None,
+            })
+            .collect();
+        Credentials::Unverified {
+            encoded: encoded.to_string(),
+            username: username.to_string(),
+            password: password.to_string(),
+            candidates,
+        }
+    }
+
+    fn throttled(&self, username: &str) -> bool {
+        self.failed_basic
+            .lock()
+            .unwrap()
+            .get(username)
+            .is_some_and(|failed| Instant::now() < failed.retry_at)
+    }
+
+    // Makes the username wait before its next attempt, twice as long with
+    // every wrong password past the free ones
+    fn record_failure(&self, username: &str) {
+        let mut failed_basic = self.failed_basic.lock().unwrap();
+        let failed = failed_basic
+            .entry(username.to_string())
+            .or_insert(FailedLogins {
+                count: 0,
+                retry_at: Instant::now(),
+            });
+        failed.count += 1;
+        if let Some(doublings) = failed.count.checked_sub(FREE_FAILURES) {
+            let backoff = Duration::from_secs(1)
+                .checked_mul(1 << doublings.min(16))
+                .map_or(MAX_BACKOFF, |backoff| backoff.min(MAX_BACKOFF));
+            failed.retry_at = Instant::now() + backoff;
+        }
+    }
+
+    // Remembers credentials that passed Argon2 for the grant
+    fn remember_basic(&self, encoded: String, username: &str, grant_id: &str) -> Option<Access> {
+        self.failed_basic.lock().unwrap().remove(username);
+        let access = self.active_grant_access(grant_id)?;
+        let mut verified = self.verified_basic.lock().unwrap();
+        // Whichever entry goes is verified again on its next use
+        if verified.len() >= MAX_VERIFIED_BASIC {
+            if let Some(evicted) = verified.keys().next().cloned() {
+                verified.remove(&evicted);
+            }
+        }
+        verified.insert(encoded, grant_id.to_string());
+        Some(access)
+    }
+
+    fn active_grant_access(&self, grant_id: &str) -> Option<Access> {
+        self.active_grants()
+            .find(|grant| grant.id == grant_id)
+            .map(grant_access)
+    }
+
+    fn active_grants(&self) -> impl Iterator<Item = &AccessGrant> {
+        let now = unix_time();
+        self.grants.iter().filter(move |grant| {
+            !grant.revoked && grant.expires_at.is_none_or(|expires_at| now < expires_at)
+        })
+    }
+}
+
+fn grant_access(grant: &AccessGrant) -> Access {
+    Access {
+        grant_id: Some(grant.id.clone()),
+        shares: grant
+            .allowed_paths
+            .as_ref()
+            .map(|allowed_paths| allowed_paths.iter().cloned().collect()),
+    }
+}
+
+// Returns the policy a server for this network should enforce, replacing
+// what an earlier server of the same network used
+pub fn policy_handle(network: &Network) -> SharedPolicy {
+    let mut policies = ACTIVE_POLICIES.lock().unwrap();
+    let handle = policies
+        .entry(network.name().to_string())
+        .or_default()
+        .clone();
+    *handle.write().unwrap() = AccessPolicy::for_network(network);
+    handle
+}
+
+// Applies changed grants or tokens to a running server, if there is one
+pub fn update_policy(network: &Network) {
+    let policies = ACTIVE_POLICIES.lock().unwrap();
+    if let Some(handle) = policies.get(network.name()) {
+        *handle.write().unwrap() = AccessPolicy::for_network(network);
+    }
+}
+
+// Authenticates a request against the policy. Accepts `Authorization: Bearer
+// <token>` or a `?token=<token>` query parameter, so links can be opened
+// directly in a browser, and HTTP Basic for password grants. Passwords
+// aren't hashed here, `authenticate` has verified them before any route ran.
+pub fn authorize(
+    policy: SharedPolicy,
+) -> impl Filter<Extract = (Access,), Error = Rejection> + Clone {
+    credentials(policy.clone()).and_then(move |credentials: Credentials| {
+        let policy = policy.clone();
+        async move {
+            let access = match credentials {
+                Credentials::Checked(access) => access,
+                Credentials::Unverified { .. } => None,
+            };
+            access.ok_or_else(|| unauthorized(&policy))
+        }
+    })
+}
+
+// Verifies Basic passwords once per request, ahead of route dispatch, so
+// the routes' own `authorize` finds them among the verified credentials.
+// Lets every request through that `authorize` would accept.
+pub fn authenticate(policy: SharedPolicy) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    credentials(policy.clone())
+        .and_then(move |credentials: Credentials| {
+            let policy = policy.clone();
+            async move {
+                let access = match credentials {
+                    Credentials::Checked(access) => access,
+                    Credentials::Unverified {
+                        encoded,
+                        username,
+                        password,
+                        candidates,
+                    } => verify_basic(&policy, encoded, &username, password, candidates).await,
+                };
+                access.map(|_| ()).ok_or_else(|| unauthorized(&policy))
+            }
+        })
+        .untuple_one()
+}
+
+fn credentials(
+    policy: SharedPolicy,
+) -> impl Filter<Extract = (Credentials,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |authorization: Option<String>, query: HashMap<String, String>| {
+                policy.read().unwrap().check(
+                    authorization.as_deref(),
+                    query.get("token").map(String::as_str),
+                )
+            },
+        )
+}
+
+// Argon2 is slow on purpose, it mustn't hold up other requests or the
+// policy, and only a few may run at once
+async fn verify_basic(
+    policy: &SharedPolicy,
+    encoded: String,
+    username: &str,
+    password: String,
+    candidates: Vec<(String, String)>,
+) -> Option<Access> {
+    if candidates.is_empty() {
+        return None;
+    }
+    let _permit = VERIFICATIONS.acquire().await.ok()?;
+    // Another request may have failed for the username while this one waited
+    if policy.read().unwrap().throttled(username) {
+        return None;
+    }
+    let verified = tauri::async_runtime::spawn_blocking(move || {
+        candidates
+            .into_iter()
+            .find(|(_, password_hash)| verify_password(&password, password_hash))
+            .map(|(grant_id, _)| grant_id)
+    })
+    .await
+    .ok()
+    .flatten();
+    let policy = policy.read().unwrap();
+    match verified {
+        Some(grant_id) => policy.remember_basic(encoded, username, &grant_id),
+        None => {
+            policy.record_failure(username);
+            None
+        }
+    }
+}
+
+fn unauthorized(policy: &SharedPolicy) -> Rejection {
+    warp::reject::custom(Unauthorized {
+        basic: policy.read().unwrap().uses_passwords(),
+    })
+}
+
+pub fn hash_password(password: &str) -> Result<String, FileError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| FileError::InvalidConfigError(format!("failed to hash password: {}", e)))
+}
+
+fn verify_password(password: &str, password_hash: &str) -> bool {
+    PasswordHash::new(password_hash)
+        .map(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+// 32 random bytes, hex encoded
+pub fn generate_token() -> String {
+    random_hex(32)
+}
+
+pub fn generate_grant_id() -> String {
+    random_hex(8)
+}
+
+fn random_hex(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+// Turns auth rejections into a proper 401/403, everything else keeps warp's defaults
+pub async fn handle_rejection(err: Rejection) -> Result<warp::reply::Response, Infallible> {
+    if let Some(unauthorized) = err.find::<Unauthorized>() {
+        let challenge = if unauthorized.basic {
+            "Basic realm=\"Quartz\", Bearer"
+        } else {
+            "Bearer"
+        };
+        let reply = warp::reply::with_status("Unauthorized", StatusCode::UNAUTHORIZED);
+        return Ok(warp::reply::with_header(reply, "www-authenticate", challenge).into_response());
+    }
+    if err.find::<Forbidden>().is_some() {
+        return Ok(warp::reply::with_status("Forbidden", StatusCode::FORBIDDEN).into_response());
+    }
+    if err.is_not_found() {
+        return Ok(warp::reply::with_status("Not Found", StatusCode::NOT_FOUND).into_response());
+    }
+    if err.find::<warp::reject::MethodNotAllowed>().is_some() {
+        return Ok(
+            warp::reply::with_status("Method Not Allowed", StatusCode::METHOD_NOT_ALLOWED)
+                .into_response(),
+        );
+    }
+
+    eprintln!("Unhandled rejection: {:?}", err);
+    Ok(warp::reply::with_status("Bad Request", StatusCode::BAD_REQUEST).into_response())
+}
+
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{authenticate, authorize, hash_password, AccessPolicy, SharedPolicy};
+    use crate::types::{AccessGrant, GrantSecret};
+    use base64::Engine;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex as StdMutex, RwLock};
+    use warp::http::StatusCode;
+    use warp::Filter;
+
+    fn password_policy() -> SharedPolicy {
+        Arc::new(RwLock::new(AccessPolicy {
+            owner_token: None,
+            grants: vec![AccessGrant {
+                id: "grant".to_string(),
+                label: "Friend".to_string(),
+                secret: GrantSecret::Password {
+                    username: "friend".to_string(),
+                    password_hash: hash_password("right").unwrap(),
+                },
+                allowed_paths: None,
+                expires_at: None,
+                revoked: false,
+            }],
+            verified_basic: StdMutex::new(HashMap::new()),
+            failed_basic: StdMutex::new(HashMap::new()),
+        }))
+    }
+
+    fn basic(username: &str, password: &str) -> String {
+        let encoded =
+            base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", username, password));
+        format!("Basic {}", encoded)
+    }
+
+    // Two routes, both authorizing, the way the server's routes are tried
+    async fn status(policy: &SharedPolicy, authorization: &str) -> StatusCode {
+        let routes = authenticate(policy.clone())
+            .and(
+                warp::path("a")
+                    .and(authorize(policy.clone()))
+                    .map(|_| "a")
+                    .or(warp::path("b").and(authorize(policy.clone())).map(|_| "b"))
+                    .unify(),
+            )
+            .recover(super::handle_rejection);
+        warp::test::request()
+            .path("/b")
+            .header("authorization", authorization)
+            .reply(&routes)
+            .await
+            .status()
+    }
+
+    #[tokio::test]
+    async fn verified_password_reaches_every_route() {
+        let policy = password_policy();
+        assert_eq!(
+            status(&policy, &basic("friend", "right")).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            policy.read().unwrap().verified_basic.lock().unwrap().len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn wrong_passwords_throttle_the_username() {
+        let policy = password_policy();
+        for _ in 0..3 {
+            assert_eq!(
+                status(&policy, &basic("friend", "wrong")).await,
+                StatusCode::UNAUTHORIZED
+            );
+        }
+        // Now the right password waits too, without being hashed
+        assert_eq!(
+            status(&policy, &basic("friend", "right")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert!(policy.read().unwrap().throttled("friend"));
+        assert!(!policy.read().unwrap().throttled("stranger"));
+    }
+}
@@ -0,0 +1,621 @@
+//Uses
+use crate::auth::unix_time;
+use crate::config::{network_data_dir, write_atomic, ConfigStore};
+use crate::listing::{is_reserved_name, modified_secs};
+use crate::local_dir::FsChangeSender;
+use crate::types::{
+    Catalog, CatalogEntry, CatalogStatus, EntryKind, FileError, FsChangeEvent, LinkedPath,
+};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex as StdMutex;
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tokio::sync::{watch, Notify};
+
+const CATALOG_FILE_NAME: &str = "catalog.json";
+// Changed catalogs are written back, and the linked paths in the config
+// checked for additions and removals, this often
+const SAVE_INTERVAL: Duration = Duration::from_secs(10);
+
+lazy_static::lazy_static! {
+    static ref CATALOGS: StdMutex<Catalogs> = StdMutex::new(Catalogs::default());
+    // Wakes the catalog task when a directory is linked or unlinked
+    static ref LINKED_PATHS_CHANGED: Notify = Notify::new();
+    // Bumped whenever any catalog changed
+    static ref UPDATES: watch::Sender<u64> = watch::channel(0).0;
+}
+
+#[derive(Default)]
+struct Catalogs {
+    // Where each linked path pointed when last read from the config
+    roots: HashMap<String, PathBuf>,
+    // Latest catalog of every linked path that has one, by name
+    ready: HashMap<String, Catalog>,
+    // Linked paths with a full scan running
+    scanning: HashMap<String, Scan>,
+    // Catalogs changed since they were last written to disk
+    unsaved: HashSet<String>,
+}
+
+struct Scan {
+    root: PathBuf,
+    // Paths the watcher reported while the scan ran, applied once it's done
+    changes: Vec<String>,
+    // Another scan was asked for while this one ran
+    again: bool,
+}
+
+// How far each linked path's catalog is
+#[tauri::command]
+pub fn get_catalog_statuses() -> Vec<CatalogStatus> {
+    let catalogs = CATALOGS.lock().unwrap();
+    let mut statuses: Vec<CatalogStatus> = catalogs
+        .roots
+        .keys()
+        .map(|name| {
+            let mut status = CatalogStatus {
+                linked_path: name.clone(),
+                scanning: catalogs.scanning.contains_key(name),
+                scanned_at: None,
+                files: 0,
+                directories: 0,
+                bytes: 0,
+            };
+            if let Some(catalog) = catalogs.ready.get(name) {
+                status.scanned_at = catalog.scanned_at;
+                for entry in catalog.entries.values() {
+                    match entry.kind {
+                        EntryKind::File => {
+                            status.files += 1;
+                            status.bytes += entry.size;
+                        }
+                        EntryKind::Directory => status.directories += 1,
+                    }
+                }
+            }
+            status
+        })
+        .collect();
+    statuses.sort_by(|a, b| a.linked_path.cmp(&b.linked_path));
+    statuses
+}
+
+// Walks a linked path again, for changes the watcher couldn't see
+#[tauri::command]
+pub fn rescan_catalog(linked_path: String) -> Result<String, FileError> {
+    let store = ConfigStore::load()?;
+    let Some(share) = store
+        .linked_paths()
+        .iter()
+        .find(|share| share.name == linked_path)
+    else {
+        return Ok("Linked path not found".to_string());
+    };
+    start_scan(&mut CATALOGS.lock().unwrap(), &share.name, &share.path);
+    Ok("Rescan started".to_string())
+}
+
+// Runs `f` on a linked path's catalog, None if it has none yet
+pub fn with_catalog<T>(linked_path: &str, f: impl FnOnce(&Catalog) -> T) -> Option<T> {
+    CATALOGS.lock().unwrap().ready.get(linked_path).map(f)
+}
+
+// Records the content hash of a file, unless the file changed since
+// `expected` was taken from the catalog
+pub fn set_hash(linked_path: &str, relative: &str, expected: &CatalogEntry, sha256: String) {
+    let mut catalogs = CATALOGS.lock().unwrap();
+    let Some(entry) = catalogs
+        .ready
+        .get_mut(linked_path)
+        .and_then(|catalog| catalog.entries.get_mut(relative))
+    else {
+        return;
+    };
+    if entry != expected {
+        return;
+    }
+    entry.sha256 = Some(sha256);
+    catalogs.unsaved.insert(linked_path.to_string());
+}
+
+// Hash a catalog holds for the file at `path`, as long as the file hasn't
+// changed since it was hashed
+pub fn known_hash(path: &Path, metadata: &fs::Metadata) -> Option<String> {
+    let covering = covering_catalogs(path);
+    let catalogs = CATALOGS.lock().unwrap();
+    covering.iter().find_map(|(linked_path, relative)| {
+        let entry = catalogs.ready.get(linked_path)?.entries.get(relative)?;
+        entry.sha256.clone().filter(|_| describes(entry, metadata))
+    })
+}
+
+// Keeps a hash worked out elsewhere in the catalogs holding the file, so
+// nothing hashes it again
+pub fn remember_hash(path: &Path, metadata: &fs::Metadata, sha256: &str) {
+    let covering = covering_catalogs(path);
+    let mut catalogs = CATALOGS.lock().unwrap();
+    for (linked_path, relative) in covering {
+        let Some(entry) = catalogs
+            .ready
+            .get_mut(&linked_path)
+            .and_then(|catalog| catalog.entries.get_mut(&relative))
+        else {
+            continue;
+        };
+        if entry.sha256.is_none() && describes(entry, metadata) {
+            entry.sha256 = Some(sha256.to_string());
+            catalogs.unsaved.insert(linked_path);
+        }
+    }
+}
+
+// Catalogs whose folder holds `path`, with the path relative to each
+fn covering_catalogs(path: &Path) -> Vec<(String, String)> {
+    let roots: Vec<(String, PathBuf)> = CATALOGS
+        .lock()
+        .unwrap()
+        .ready
+        .iter()
+        .map(|(linked_path, catalog)| (linked_path.clone(), catalog.root.clone()))
+        .collect();
+    roots
+        .into_iter()
+        .filter_map(|(linked_path, root)| {
+            let root = fs::canonicalize(&root).unwrap_or(root);
+            let segments: Option<Vec<&str>> = path
+                .strip_prefix(&root)
+                .ok()?
+                .components()
+                .map(|component| match component {
+                    Component::Normal(segment) => segment.to_str(),
+                    _ => None,
+                })
+                .collect();
+            Some((linked_path, segments?.join("/")))
+        })
+        .collect()
+}
+
+// Whether a catalog entry still describes the file as it is on disk
+fn describes(entry: &CatalogEntry, metadata: &fs::Metadata) -> bool {
+    entry.kind == EntryKind::File
+        && entry.size == metadata.len()
+        && entry.modified == modified_secs(metadata)
+        && entry.file_id == file_id(metadata)
+}
+
+// Marks the receiver changed whenever a catalog was updated, for whatever
+// keeps data derived from the catalogs
+pub fn subscribe() -> watch::Receiver<u64> {
+    UPDATES.subscribe()
+}
+
+fn catalogs_updated() {
+    UPDATES.send_modify(|generation| *generation += 1);
+}
+
+// Picks up linked paths that were just added or removed
+pub fn linked_paths_changed() {
+    LINKED_PATHS_CHANGED.notify_one();
+}
+
+// Keeps a catalog of every linked path. Each is scanned once at startup,
+// which also catches what changed while the app wasn't running, and then
+// kept up to date from the file watcher's changes.
+pub async fn run_catalogs(app: AppHandle) {
+    let mut changes = app.state::<FsChangeSender>().subscribe();
+    let mut watching = true;
+    let mut ticks = tokio::time::interval(SAVE_INTERVAL);
+    loop {
+        let mut batch = Vec::new();
+        let mut missed_changes = false;
+        tokio::select! {
+            _ = ticks.tick() => {}
+            _ = LINKED_PATHS_CHANGED.notified() => {}
+            change = changes.recv(), if watching => match change {
+                Ok(change) => batch.push(change),
+                Err(RecvError::Lagged(_)) => missed_changes = true,
+                Err(RecvError::Closed) => watching = false,
+            },
+        }
+        // Whatever else arrived with it is handled in the same pass
+        loop {
+            match changes.try_recv() {
+                Ok(change) => batch.push(change),
+                Err(TryRecvError::Lagged(_)) => missed_changes = true,
+                Err(_) => break,
+            }
+        }
+
+        let result = tauri::async_runtime::spawn_blocking(move || {
+            match ConfigStore::load() {
+                Ok(store) => reconcile(store.linked_paths()),
+                Err(e) => eprintln!("Failed to load linked paths for catalogs: {}", e),
+            }
+            let mut catalogs = CATALOGS.lock().unwrap();
+            if missed_changes {
+                // Changes were dropped, only a full scan can tell which
+                let roots: Vec<(String, PathBuf)> = catalogs
+                    .roots
+                    .iter()
+                    .map(|(name, root)| (name.clone(), root.clone()))
+                    .collect();
+                for (name, root) in roots {
+                    start_scan(&mut catalogs, &name, &root);
+                }
+            } else {
+                apply_changes(&mut catalogs, batch);
+            }
+            drop(catalogs);
+            save_catalogs();
+        })
+        .await;
+        if let Err(e) = result {
+            eprintln!("Failed to update catalogs: {}", e);
+        }
+    }
+}
+
+fn catalog_path(linked_path: &str) -> PathBuf {
+    network_data_dir("catalogs", linked_path).join(CATALOG_FILE_NAME)
+}
+
+fn load_catalog(linked_path: &str) -> Option<Catalog> {
+    let data = match fs::read(catalog_path(linked_path)) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            eprintln!("Failed to read catalog of {}: {}", linked_path, e);
+            return None;
+        }
+    };
+    match serde_json::from_slice(&data) {
+        Ok(catalog) => Some(catalog),
+        Err(e) => {
+            eprintln!("Failed to parse catalog of {}: {}", linked_path, e);
+            None
+        }
+    }
+}
+
+// Brings the catalogs in line with the linked paths in the config. New
+// linked paths start with whatever catalog is on disk and get scanned.
+fn reconcile(linked_paths: &[LinkedPath]) {
+    let mut catalogs = CATALOGS.lock().unwrap();
+    let removed: Vec<String> = catalogs
+        .roots
+        .keys()
+        .filter(|name| !linked_paths.iter().any(|share| &share.name == *name))
+        .cloned()
+        .collect();
+    for name in &removed {
+        catalogs.roots.remove(name);
+        catalogs.ready.remove(name);
+        catalogs.scanning.remove(name);
+        catalogs.unsaved.remove(name);
+        if let Err(e) = fs::remove_dir_all(network_data_dir("catalogs", name)) {
+            if e.kind() != io::ErrorKind::NotFound {
+                eprintln!("Failed to remove catalog of {}: {}", name, e);
+            }
+        }
+    }
+    if !removed.is_empty() {
+        catalogs_updated();
+    }
+
+    for share in linked_paths {
+        if catalogs.roots.get(&share.name) == Some(&share.path) {
+            continue;
+        }
+        catalogs
+            .roots
+            .insert(share.name.clone(), share.path.clone());
+        if !catalogs.ready.contains_key(&share.name) {
+            if let Some(catalog) = load_catalog(&share.name) {
+                catalogs.ready.insert(share.name.clone(), catalog);
+            }
+        }
+        start_scan(&mut catalogs, &share.name, &share.path);
+    }
+}
+
+fn start_scan(catalogs: &mut Catalogs, linked_path: &str, root: &Path) {
+    if let Some(scan) = catalogs.scanning.get_mut(linked_path) {
+        scan.root = root.to_path_buf();
+        scan.again = true;
+        return;
+    }
+    catalogs.scanning.insert(
+        linked_path.to_string(),
+        Scan {
+            root: root.to_path_buf(),
+            changes: Vec::new(),
+            again: false,
+        },
+    );
+    let linked_path = linked_path.to_string();
+    let root = root.to_path_buf();
+    tauri::async_runtime::spawn_blocking(move || scan_linked_path(linked_path, root));
+}
+
+// Runs outside the lock, readers keep seeing the previous catalog meanwhile
+fn scan_linked_path(linked_path: String, mut root: PathBuf) {
+    loop {
+        let previous = CATALOGS.lock().unwrap().ready.get(&linked_path).cloned();
+        let result = scan(&root, previous.as_ref());
+
+        let mut catalogs = CATALOGS.lock().unwrap();
+        // Unlinked while the scan ran
+        let Some(scan) = catalogs.scanning.get_mut(&linked_path) else {
+            return;
+        };
+        if scan.again {
+            scan.again = false;
+            scan.changes.clear();
+            root = scan.root.clone();
+            continue;
+        }
+        let changes = std::mem::take(&mut scan.changes);
+        catalogs.scanning.remove(&linked_path);
+        match result {
+            Ok(mut catalog) => {
+                for relative in &changes {
+                    catalog.apply(relative);
+                }
+                println!(
+                    "Catalog of {} has {} entries",
+                    linked_path,
+                    catalog.entries.len()
+                );
+                catalogs.ready.insert(linked_path.clone(), catalog);
+                catalogs.unsaved.insert(linked_path);
+                catalogs_updated();
+            }
+            Err(e) => eprintln!("Failed to scan {}: {}", root.display(), e),
+        }
+        return;
+    }
+}
+
+fn apply_changes(catalogs: &mut Catalogs, changes: Vec<FsChangeEvent>) {
+    if changes.is_empty() {
+        return;
+    }
+    let mut seen = HashSet::new();
+    for change in changes {
+        // The path is looked at again, so one update per path is enough
+        if !seen.insert((change.linked_path.clone(), change.relative_path.clone())) {
+            continue;
+        }
+        if let Some(scan) = catalogs.scanning.get_mut(&change.linked_path) {
+            scan.changes.push(change.relative_path);
+            continue;
+        }
+        let Some(catalog) = catalogs.ready.get_mut(&change.linked_path) else {
+            continue;
+        };
+        catalog.apply(&change.relative_path);
+        catalogs.unsaved.insert(change.linked_path);
+    }
+    catalogs_updated();
+}
+
+fn save_catalogs() {
+    let pending: Vec<(String, serde_json::Result<Vec<u8>>)> = {
+        let mut catalogs = CATALOGS.lock().unwrap();
+        let names: Vec<String> = catalogs.unsaved.drain().collect();
+        names
+            .into_iter()
+            .filter_map(|name| {
+                let data = serde_json::to_vec(catalogs.ready.get(&name)?);
+                Some((name, data))
+            })
+            .collect()
+    };
+    for (name, data) in pending {
+        let path = catalog_path(&name);
+        let result = data.map_err(FileError::from).and_then(|data| {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            write_atomic(&path, &data)
+        });
+        if let Err(e) = result {
+            eprintln!("Failed to save catalog of {}: {}", name, e);
+        }
+    }
+}
+
+// Walks the whole tree under `root`. Symlinks are neither followed nor
+// recorded, and nothing under a reserved name is. Hashes carry over from
+// `previous` for files that look unchanged.
+fn scan(root: &Path, previous: Option<&Catalog>) -> io::Result<Catalog> {
+    let known = previous
+        .filter(|catalog| catalog.root == root)
+        .map(|catalog| &catalog.entries);
+    let mut entries = BTreeMap::new();
+    scan_dir(root, "", known, &mut entries)?;
+    Ok(Catalog {
+        root: root.to_path_buf(),
+        scanned_at: Some(unix_time()),
+        entries,
+    })
+}
+
+// Only a root that can't be read fails the scan, unreadable folders below
+// it are recorded without their contents
+fn scan_dir(
+    dir: &Path,
+    prefix: &str,
+    known: Option<&BTreeMap<String, CatalogEntry>>,
+    entries: &mut BTreeMap<String, CatalogEntry>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if is_reserved_name(&name) {
+            continue;
+        }
+        let relative = if prefix.is_empty() {
+            name
+        } else {
+            format!("{}/{}", prefix, name)
+        };
+        // Gone since it was listed
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if !metadata.is_dir() && !metadata.is_file() {
+            continue;
+        }
+        let catalog_entry = catalog_entry(&metadata, known.and_then(|known| known.get(&relative)));
+        entries.insert(relative.clone(), catalog_entry);
+        if metadata.is_dir() {
+            if let Err(e) = scan_dir(&entry.path(), &relative, known, entries) {
+                eprintln!("Failed to read {}: {}", entry.path().display(), e);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn catalog_entry(metadata: &fs::Metadata, known: Option<&CatalogEntry>) -> CatalogEntry {
+    let kind = if metadata.is_dir() {
+        EntryKind::Directory
+    } else {
+        EntryKind::File
+    };
+    let size = if metadata.is_dir() { 0 } else { metadata.len() };
+    let modified = modified_secs(metadata);
+    let file_id = file_id(metadata);
+    let sha256 = known
+        .filter(|known| {
+            known.kind == kind
+                && known.size == size
+                && known.modified == modified
+                && known.file_id == file_id
+        })
+        .and_then(|known| known.sha256.clone());
+    CatalogEntry {
+        kind,
+        size,
+        modified,
+        file_id,
+        sha256,
+    }
+}
+
+#[cfg(unix)]
+fn file_id(metadata: &fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.ino())
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &fs::Metadata) -> Option<u64> {
+    None
+}
+
+impl Catalog {
+    // Looks at a path again after the watcher reported a change to it
+    fn apply(&mut self, relative: &str) {
+        if relative.is_empty() || relative.split('/').any(is_reserved_name) {
+            return;
+        }
+        let path = relative
+            .split('/')
+            .fold(self.root.clone(), |path, segment| path.join(segment));
+        let metadata = match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.is_dir() || metadata.is_file() => metadata,
+            Ok(_) => return self.remove(relative),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return self.remove(relative),
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", path.display(), e);
+                return;
+            }
+        };
+
+        let was_dir = self
+            .entries
+            .get(relative)
+            .is_some_and(|entry| entry.kind == EntryKind::Directory);
+        let entry = catalog_entry(&metadata, self.entries.get(relative));
+        self.entries.insert(relative.to_string(), entry);
+        if metadata.is_dir() && !was_dir {
+            // A folder moved in arrives with its contents, without events
+            // for them
+            self.remove_descendants(relative);
+            if let Err(e) = scan_dir(&path, relative, None, &mut self.entries) {
+                eprintln!("Failed to read {}: {}", path.display(), e);
+            }
+        } else if !metadata.is_dir() && was_dir {
+            self.remove_descendants(relative);
+        }
+    }
+
+    fn remove(&mut self, relative: &str) {
+        self.entries.remove(relative);
+        self.remove_descendants(relative);
+    }
+
+    // Everything under `relative/` sorts before `relative0`, '0' being the
+    // character after '/'
+    fn remove_descendants(&mut self, relative: &str) {
+        let mut below = self.entries.split_off(format!("{}/", relative).as_str());
+        let mut after = below.split_off(format!("{}0", relative).as_str());
+        self.entries.append(&mut after);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{catalog_entry, known_hash, remember_hash, CATALOGS};
+    use crate::listing::file_sha256;
+    use crate::types::Catalog;
+    use std::collections::BTreeMap;
+    use std::fs;
+
+    #[test]
+    fn files_are_hashed_once_through_the_catalog() {
+        let root = std::env::temp_dir().join(format!("quartz-catalog-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let root = fs::canonicalize(&root).unwrap();
+        let path = root.join("a.txt");
+        fs::write(&path, b"hello").unwrap();
+        let metadata = fs::symlink_metadata(&path).unwrap();
+        let linked_path = format!("catalog-test-{}", std::process::id());
+        CATALOGS.lock().unwrap().ready.insert(
+            linked_path.clone(),
+            Catalog {
+                root: root.clone(),
+                scanned_at: None,
+                entries: BTreeMap::from([("a.txt".to_string(), catalog_entry(&metadata, None))]),
+            },
+        );
+
+        assert_eq!(known_hash(&path, &metadata), None);
+        let hash = file_sha256(&path, &metadata).unwrap();
+        assert_eq!(
+            hash,
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+        assert_eq!(known_hash(&path, &metadata), Some(hash.clone()));
+
+        // A changed file isn't served the old hash
+        fs::write(&path, b"hello, world").unwrap();
+        let changed = fs::symlink_metadata(&path).unwrap();
+        assert_eq!(known_hash(&path, &changed), None);
+        remember_hash(&path, &changed, "other");
+        assert_eq!(known_hash(&path, &metadata), Some(hash));
+
+        CATALOGS.lock().unwrap().ready.remove(&linked_path);
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,818 @@
+//Uses
+use crate::types::{
+    AccessGrant, FileError, GrantSecret, LinkedPath, Mirror, Network, RemoteNetwork, ShareSummary,
+    SyncFolder, TlsSetting,
+};
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use tauri::{AppHandle, Manager};
+
+pub const PRIVATE_CONFIG_FILE_NAME: &str = "private_config.json";
+// Overrides the config directory, e.g. for portable installs and tests
+pub const CONFIG_DIR_ENV: &str = "QUARTZ_CONFIG_DIR";
+pub const CONFIG_DIR_ARG: &str = "--config-dir";
+// Where configs lived before they moved to the platform config directory
+const LEGACY_CONFIG_DIR: &str = "../configs";
+
+// Resolved once in `init_config_dir` during app setup
+lazy_static::lazy_static! {
+    static ref CONFIG_DIR: RwLock<PathBuf> = RwLock::new(PathBuf::from(LEGACY_CONFIG_DIR));
+}
+
+// Bump this whenever the on-disk layout of private_config.json changes and
+// add a matching step to `migrate`
+pub const CONFIG_VERSION: u32 = 5;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PrivateConfig {
+    pub version: u32,
+    // Incremented on every saved mutation, used to reject stale updates
+    pub revision: u64,
+    pub linked_paths: Vec<LinkedPath>,
+    pub networks: Vec<Network>,
+    // Networks on other machines this app has joined
+    pub remote_networks: Vec<RemoteNetwork>,
+    // Remote shares pulled into local linked paths
+    pub mirrors: Vec<Mirror>,
+    // Linked paths kept in step with remote shares both ways
+    pub sync_folders: Vec<SyncFolder>,
+}
+
+impl Default for PrivateConfig {
+    fn default() -> Self {
+        PrivateConfig {
+            version: CONFIG_VERSION,
+            revision: 0,
+            linked_paths: Vec::new(),
+            networks: Vec::new(),
+            remote_networks: Vec::new(),
+            mirrors: Vec::new(),
+            sync_folders: Vec::new(),
+        }
+    }
+}
+
+// Typed access to private_config.json. Every command that touches the config
+// loads a store, mutates it through the methods below and saves it back.
+pub struct ConfigStore {
+    path: PathBuf,
+    config: PrivateConfig,
+}
+
+impl ConfigStore {
+    pub fn load() -> Result<Self, FileError> {
+        Self::load_from(private_config_path())
+    }
+
+    pub fn load_from(path: impl AsRef<Path>) -> Result<Self, FileError> {
+        let path = path.as_ref().to_path_buf();
+        match read_config(&path) {
+            Ok(config) => Ok(ConfigStore { path, config }),
+            // Recovering writes the file, so it happens under the lock
+            Err(_) => {
+                let _lock = ConfigLock::acquire()?;
+                Self::load_locked(path)
+            }
+        }
+    }
+
+    // Like `load_from`, for callers that already hold the config lock
+    fn load_locked(path: PathBuf) -> Result<Self, FileError> {
+        let e = match read_config(&path) {
+            Ok(config) => return Ok(ConfigStore { path, config }),
+            // Written by a newer version of the app, which the older backup
+            // would silently undo
+            Err(e @ FileError::UnsupportedConfigVersionError(_)) => return Err(e),
+            Err(e) => e,
+        };
+
+        // Fall back to the last good copy and put it back in place
+        let backup_path = backup_path(&path);
+        eprintln!(
+            "Failed to load {}: {}, trying {}",
+            path.display(),
+            e,
+            backup_path.display()
+        );
+        let config = read_config(&backup_path).map_err(|_| e)?;
+        let data = serde_json::to_string_pretty(&config)?;
+        // The file being replaced is no good, it mustn't take the backup's place
+        replace_atomic(&path, data.as_bytes())?;
+        println!("Recovered {} from backup", path.display());
+
+        Ok(ConfigStore { path, config })
+    }
+
+    // Creates the config file with an empty, current-version config if it is missing
+    pub fn ensure_exists() -> Result<(), FileError> {
+        let path = private_config_path();
+        if path.exists() {
+            return Ok(());
+        }
+        let store = ConfigStore {
+            path,
+            config: PrivateConfig::default(),
+        };
+        store.save()
+    }
+
+    // Runs a read-modify-write of the config under the cross-process lock.
+    // If `expected_revision` is given and the file has moved on since the
+    // caller read it, nothing is written and a ConflictError is returned.
+    pub fn update<T>(
+        expected_revision: Option<u64>,
+        f: impl FnOnce(&mut ConfigStore) -> Result<T, FileError>,
+    ) -> Result<T, FileError> {
+        let _lock = ConfigLock::acquire()?;
+        let mut store = Self::load_locked(private_config_path())?;
+        if let Some(expected) = expected_revision {
+            if expected != store.config.revision {
+                return Err(FileError::ConflictError {
+                    expected,
+                    actual: store.config.revision,
+                });
+            }
+        }
+
+        let result = f(&mut store)?;
+        store.config.revision += 1;
+        store.save()?;
+
+        Ok(result)
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.config.revision
+    }
+
+    pub fn linked_paths(&self) -> &[LinkedPath] {
+        &self.config.linked_paths
+    }
+
+    pub fn networks(&self) -> &[Network] {
+        &self.config.networks
+    }
+
+    pub fn remote_networks(&self) -> &[RemoteNetwork] {
+        &self.config.remote_networks
+    }
+
+    pub fn mirrors(&self) -> &[Mirror] {
+        &self.config.mirrors
+    }
+
+    pub fn sync_folders(&self) -> &[SyncFolder] {
+        &self.config.sync_folders
+    }
+
+    pub fn add_linked_path(&mut self, linked_path: LinkedPath) -> Result<(), FileError> {
+        if self
+            .config
+            .linked_paths
+            .iter()
+            .any(|x| x.name == linked_path.name)
+        {
+            return Err(FileError::DuplicateNameError(linked_path.name));
+        }
+        self.config.linked_paths.push(linked_path);
+        Ok(())
+    }
+
+    // Returns false if no linked path with this name was stored
+    pub fn remove_linked_path(&mut self, name: &str) -> bool {
+        let len = self.config.linked_paths.len();
+        self.config.linked_paths.retain(|path| path.name != name);
+        self.config
+            .mirrors
+            .retain(|mirror| mirror.linked_path != name);
+        self.config
+            .sync_folders
+            .retain(|folder| folder.linked_path != name);
+        self.config.linked_paths.len() != len
+    }
+
+    // Whether a network, mirror or sync folder refers to the linked path
+    pub fn linked_path_in_use(&self, name: &str) -> bool {
+        self.config.networks.iter().any(|network| {
+            network
+                .linked_paths()
+                .iter()
+                .any(|linked_path| linked_path.name == name)
+        }) || self
+            .config
+            .mirrors
+            .iter()
+            .any(|mirror| mirror.linked_path == name)
+            || self
+                .config
+                .sync_folders
+                .iter()
+                .any(|folder| folder.linked_path == name)
+    }
+
+    // Networks keep their own copy of each linked path, those are updated too
+    pub fn update_linked_path(
+        &mut self,
+        name: &str,
+        f: impl FnOnce(&mut LinkedPath),
+    ) -> Result<(), FileError> {
+        let linked_path = self
+            .config
+            .linked_paths
+            .iter_mut()
+            .find(|path| path.name == name)
+            .ok_or_else(|| FileError::NotFoundError(name.to_string()))?;
+        let previous = linked_path.clone();
+        f(linked_path);
+        let updated = linked_path.clone();
+
+        for network in &mut self.config.networks {
+            for linked_path in network.linked_paths_mut() {
+                if linked_path.name == previous.name && linked_path.path == previous.path {
+                    *linked_path = updated.clone();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn add_network(&mut self, network: Network) -> Result<(), FileError> {
+        if self
+            .config
+            .networks
+            .iter()
+            .any(|x| x.name() == network.name())
+        {
+            return Err(FileError::DuplicateNameError(network.name().to_string()));
+        }
+        self.config.networks.push(network);
+        Ok(())
+    }
+
+    // Returns false if no network with this name was stored
+    pub fn remove_network(&mut self, name: &str) -> bool {
+        let len = self.config.networks.len();
+        self.config
+            .networks
+            .retain(|network| network.name() != name);
+        self.config.networks.len() != len
+    }
+
+    // Remote networks share the namespace of the networks served from here,
+    // both are listed and addressed by name
+    pub fn add_remote_network(&mut self, network: RemoteNetwork) -> Result<(), FileError> {
+        let taken = self
+            .config
+            .networks
+            .iter()
+            .any(|x| x.name() == network.name)
+            || self
+                .config
+                .remote_networks
+                .iter()
+                .any(|x| x.name == network.name);
+        if taken {
+            return Err(FileError::DuplicateNameError(network.name));
+        }
+        self.config.remote_networks.push(network);
+        Ok(())
+    }
+
+    // Returns false if no remote network with this name was stored
+    pub fn remove_remote_network(&mut self, name: &str) -> bool {
+        let len = self.config.remote_networks.len();
+        self.config
+            .remote_networks
+            .retain(|network| network.name != name);
+        self.config
+            .mirrors
+            .retain(|mirror| mirror.remote_network != name);
+        self.config
+            .sync_folders
+            .retain(|folder| folder.remote_network != name);
+        self.config.remote_networks.len() != len
+    }
+
+    pub fn set_remote_shares(
+        &mut self,
+        name: &str,
+        shares: Vec<ShareSummary>,
+    ) -> Result<(), FileError> {
+        let network = self
+            .config
+            .remote_networks
+            .iter_mut()
+            .find(|network| network.name == name)
+            .ok_or_else(|| FileError::NotFoundError(name.to_string()))?;
+        network.shares = shares;
+        Ok(())
+    }
+
+    // The remote network and linked path have to exist, mirrors are removed
+    // together with either of them
+    pub fn add_mirror(&mut self, mirror: Mirror) -> Result<(), FileError> {
+        if !self
+            .config
+            .remote_networks
+            .iter()
+            .any(|network| network.name == mirror.remote_network)
+        {
+            return Err(FileError::NotFoundError(mirror.remote_network));
+        }
+        if !self
+            .config
+            .linked_paths
+            .iter()
+            .any(|path| path.name == mirror.linked_path)
+        {
+            return Err(FileError::NotFoundError(mirror.linked_path));
+        }
+        self.config.mirrors.push(mirror);
+        Ok(())
+    }
+
+    // Returns false if no mirror with this id was stored
+    pub fn remove_mirror(&mut self, id: &str) -> bool {
+        let len = self.config.mirrors.len();
+        self.config.mirrors.retain(|mirror| mirror.id != id);
+        self.config.mirrors.len() != len
+    }
+
+    // Like mirrors, sync folders need their remote network and linked path.
+    // A linked path may sync with several shares, but only once with each.
+    pub fn add_sync_folder(&mut self, folder: SyncFolder) -> Result<(), FileError> {
+        if !self
+            .config
+            .remote_networks
+            .iter()
+            .any(|network| network.name == folder.remote_network)
+        {
+            return Err(FileError::NotFoundError(folder.remote_network));
+        }
+        if !self
+            .config
+            .linked_paths
+            .iter()
+            .any(|path| path.name == folder.linked_path)
+        {
+            return Err(FileError::NotFoundError(folder.linked_path));
+        }
+        if self.config.sync_folders.iter().any(|x| {
+            x.linked_path == folder.linked_path
+                && x.remote_network == folder.remote_network
+                && x.share == folder.share
+        }) {
+            return Err(FileError::DuplicateNameError(format!(
+                "{} <-> {}/{}",
+                folder.linked_path, folder.remote_network, folder.share
+            )));
+        }
+        self.config.sync_folders.push(folder);
+        Ok(())
+    }
+
+    // Returns false if no sync folder with this id was stored
+    pub fn remove_sync_folder(&mut self, id: &str) -> bool {
+        let len = self.config.sync_folders.len();
+        self.config.sync_folders.retain(|folder| folder.id != id);
+        self.config.sync_folders.len() != len
+    }
+
+    // Stores the public address of an internet or dark web network
+    pub fn set_network_address(
+        &mut self,
+        name: &str,
+        new_address: String,
+    ) -> Result<(), FileError> {
+        match self.network_mut(name)? {
+            Network::InternetNetwork { address, .. } | Network::DarkWebNetwork { address, .. } => {
+                *address = new_address;
+                Ok(())
+            }
+            Network::LocalNetwork { .. } => Err(FileError::InvalidConfigError(format!(
+                "network '{}' has no public address",
+                name
+            ))),
+        }
+    }
+
+    pub fn set_access_token(&mut self, name: &str, new_token: String) -> Result<(), FileError> {
+        match self.network_mut(name)? {
+            Network::InternetNetwork { access_token, .. } => {
+                *access_token = new_token;
+                Ok(())
+            }
+            _ => Err(FileError::InvalidConfigError(format!(
+                "network '{}' does not use access tokens",
+                name
+            ))),
+        }
+    }
+
+    pub fn set_network_tls(&mut self, name: &str, new_tls: TlsSetting) -> Result<(), FileError> {
+        match self.network_mut(name)? {
+            Network::LocalNetwork { tls, .. } => *tls = new_tls,
+            Network::InternetNetwork { .. } if new_tls == TlsSetting::Disabled => {
+                return Err(FileError::InvalidConfigError(format!(
+                    "internet network '{}' requires TLS",
+                    name
+                )))
+            }
+            Network::InternetNetwork { tls, .. } => *tls = new_tls,
+            // Tor already encrypts and authenticates onion connections
+            Network::DarkWebNetwork { .. } => {
+                return Err(FileError::InvalidConfigError(format!(
+                    "network '{}' does not use TLS",
+                    name
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    pub fn set_directory_index(&mut self, name: &str, enabled: bool) -> Result<(), FileError> {
+        match self.network_mut(name)? {
+            Network::LocalNetwork {
+                directory_index, ..
+            }
+            | Network::InternetNetwork {
+                directory_index, ..
+            }
+            | Network::DarkWebNetwork {
+                directory_index, ..
+            } => *directory_index = enabled,
+        }
+        Ok(())
+    }
+
+    // Grants may only name shares the network actually has
+    pub fn add_access_grant(&mut self, name: &str, grant: AccessGrant) -> Result<(), FileError> {
+        let network = self.network_mut(name)?;
+        if let Some(allowed_paths) = &grant.allowed_paths {
+            for share in allowed_paths {
+                if !network.linked_paths().iter().any(|x| &x.name == share) {
+                    return Err(FileError::NotFoundError(share.clone()));
+                }
+            }
+        }
+        if let GrantSecret::Password { username, .. } = &grant.secret {
+            let taken = network.access_grants().iter().any(|x| {
+                !x.revoked
+                    && matches!(&x.secret, GrantSecret::Password { username: other, .. } if other == username)
+            });
+            if taken {
+                return Err(FileError::DuplicateNameError(username.clone()));
+            }
+        }
+        network.access_grants_mut().push(grant);
+        Ok(())
+    }
+
+    // Returns false if the network has no grant with this id
+    pub fn revoke_access_grant(&mut self, name: &str, grant_id: &str) -> Result<bool, FileError> {
+        let grant = self
+            .network_mut(name)?
+            .access_grants_mut()
+            .iter_mut()
+            .find(|grant| grant.id == grant_id);
+        match grant {
+            Some(grant) => {
+                grant.revoked = true;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn network_mut(&mut self, name: &str) -> Result<&mut Network, FileError> {
+        self.config
+            .networks
+            .iter_mut()
+            .find(|network| network.name() == name)
+            .ok_or_else(|| FileError::NotFoundError(name.to_string()))
+    }
+
+    fn save(&self) -> Result<(), FileError> {
+        validate(&self.config)?;
+        write_json_atomic(&self.path, &self.config)
+    }
+}
+
+// Advisory lock on `private_config.json.lock`, held for the duration of a
+// mutation so other windows and processes can't interleave their own
+struct ConfigLock {
+    file: fs::File,
+}
+
+impl ConfigLock {
+    fn acquire() -> Result<Self, FileError> {
+        let mut file_name = PRIVATE_CONFIG_FILE_NAME.to_string();
+        file_name.push_str(".lock");
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(config_dir().join(file_name))?;
+        file.lock_exclusive()?;
+        Ok(ConfigLock { file })
+    }
+}
+
+impl Drop for ConfigLock {
+    fn drop(&mut self) {
+        if let Err(e) = self.file.unlock() {
+            eprintln!("Failed to release config lock: {}", e);
+        }
+    }
+}
+
+fn read_config(path: &Path) -> Result<PrivateConfig, FileError> {
+    let data = fs::read_to_string(path)?;
+    let value: Value = serde_json::from_str(&data)?;
+    let config = migrate(value)?;
+    validate(&config)?;
+    Ok(config)
+}
+
+fn backup_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".bak");
+    path.with_file_name(file_name)
+}
+
+pub fn write_json_atomic<T: Serialize>(path: &Path, value: &T) -> Result<(), FileError> {
+    let json_str = serde_json::to_string_pretty(value)?;
+    write_atomic(path, json_str.as_bytes())
+}
+
+// Writes to a temp file next to `path`, fsyncs it and renames it over the
+// target, so readers only ever see the old or the new contents. The previous
+// contents are kept as `<name>.bak` if they were valid JSON.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), FileError> {
+    // Rotate the current file into the backup slot, but never overwrite a
+    // good backup with a corrupt primary
+    if let Ok(current) = fs::read(path) {
+        if serde_json::from_slice::<Value>(&current).is_ok() {
+            let backup_path = backup_path(path);
+            let mut backup_tmp_name = backup_path.file_name().unwrap_or_default().to_os_string();
+            backup_tmp_name.push(".tmp");
+            let backup_tmp_path = path.with_file_name(backup_tmp_name);
+            {
+                let mut file = fs::File::create(&backup_tmp_path)?;
+                file.write_all(&current)?;
+                file.sync_all()?;
+            }
+            fs::rename(&backup_tmp_path, &backup_path)?;
+        }
+    }
+
+    replace_atomic(path, contents)
+}
+
+// `write_atomic` for secrets such as private keys. Only the owner may read
+// the file, from before anything is written to it, and no backup is kept.
+pub fn write_private(path: &Path, contents: &[u8]) -> Result<(), FileError> {
+    replace_atomic_with(path, contents, true)
+}
+
+// `write_atomic` without touching the backup
+fn replace_atomic(path: &Path, contents: &[u8]) -> Result<(), FileError> {
+    replace_atomic_with(path, contents, false)
+}
+
+#[cfg_attr(not(unix), allow(unused_variables))]
+fn replace_atomic_with(path: &Path, contents: &[u8], private: bool) -> Result<(), FileError> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    {
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        if private {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(&tmp_path)?;
+        // A temp file left behind earlier keeps the mode it was created with
+        #[cfg(unix)]
+        if private {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        }
+        file.write_all(contents)?;
+        file.sync_all()?;
+    }
+
+    fs::rename(&tmp_path, path)?;
+    sync_parent_dir(path);
+
+    Ok(())
+}
+
+// Makes the renames durable. Directories can't be opened for syncing on Windows.
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) {
+    if let Some(parent) = path.parent() {
+        let parent = if parent.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            parent
+        };
+        if let Ok(dir) = fs::File::open(parent) {
+            let _ = dir.sync_all();
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) {}
+
+pub fn config_dir() -> PathBuf {
+    CONFIG_DIR.read().unwrap().clone()
+}
+
+pub fn private_config_path() -> PathBuf {
+    config_dir().join(PRIVATE_CONFIG_FILE_NAME)
+}
+
+// Per-network data such as certificates lives in `<config dir>/<kind>/<name>`.
+// The name is hex encoded so any network name is a valid directory name.
+pub fn network_data_dir(kind: &str, network_name: &str) -> PathBuf {
+    let dir_name: String = network_name.bytes().map(|b| format!("{:02x}", b)).collect();
+    config_dir().join(kind).join(dir_name)
+}
+
+// Picks the config directory (CLI flag, then env var, then the platform app
+// config dir), creates it and moves configs over from the legacy location
+pub fn init_config_dir(app: &AppHandle) -> Result<PathBuf, FileError> {
+    let dir = match config_dir_override() {
+        Some(dir) => dir,
+        None => app.path().app_config_dir().map_err(|e| {
+            FileError::InvalidConfigError(format!("failed to resolve config directory: {}", e))
+        })?,
+    };
+    fs::create_dir_all(&dir)?;
+    migrate_legacy_config_dir(&dir)?;
+    println!("Using config directory: {:?}", dir);
+
+    *CONFIG_DIR.write().unwrap() = dir.clone();
+    Ok(dir)
+}
+
+fn config_dir_override() -> Option<PathBuf> {
+    let mut args = std::env::args();
+    while let Some(arg) = args.next() {
+        if arg == CONFIG_DIR_ARG {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(dir) = arg.strip_prefix("--config-dir=") {
+            return Some(PathBuf::from(dir));
+        }
+    }
+    std::env::var_os(CONFIG_DIR_ENV)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+}
+
+// Copies configs from `../configs` on the first launch that uses `dir`.
+// The legacy files are left in place.
+fn migrate_legacy_config_dir(dir: &Path) -> Result<(), FileError> {
+    let legacy_dir = Path::new(LEGACY_CONFIG_DIR);
+    if dir.join(PRIVATE_CONFIG_FILE_NAME).exists() || !legacy_dir.is_dir() {
+        return Ok(());
+    }
+    if let (Ok(legacy), Ok(target)) = (legacy_dir.canonicalize(), dir.canonicalize()) {
+        if legacy == target {
+            return Ok(());
+        }
+    }
+    let legacy_file = legacy_dir.join(PRIVATE_CONFIG_FILE_NAME);
+    if legacy_file.is_file() {
+        write_atomic(
+            &dir.join(PRIVATE_CONFIG_FILE_NAME),
+            &fs::read(&legacy_file)?,
+        )?;
+        println!("Migrated {:?} to {:?}", legacy_file, dir);
+    }
+    Ok(())
+}
+
+// Brings any older config layout up to CONFIG_VERSION
+fn migrate(mut value: Value) -> Result<PrivateConfig, FileError> {
+    let object = value
+        .as_object_mut()
+        .ok_or_else(|| FileError::InvalidConfigError("config is not a JSON object".into()))?;
+    // Configs written before versioning have no `version` field
+    let mut version = match object.get("version") {
+        Some(v) => v
+            .as_u64()
+            .ok_or_else(|| FileError::InvalidConfigError("'version' is not a number".into()))?
+            as u32,
+        None => 0,
+    };
+    if version > CONFIG_VERSION {
+        return Err(FileError::UnsupportedConfigVersionError(version));
+    }
+
+    // 0 -> 1: `linked_paths` was always present, `networks` was added later
+    if version == 0 {
+        if !object.contains_key("linked_paths") {
+            return Err(FileError::MissingLinkedPathsError);
+        }
+        object
+            .entry("networks")
+            .or_insert_with(|| Value::Array(Vec::new()));
+        version = 1;
+    }
+
+    // 1 -> 2: add the revision counter
+    if version == 1 {
+        object.entry("revision").or_insert_with(|| Value::from(0));
+        version = 2;
+    }
+
+    // 2 -> 3: add joined remote networks
+    if version == 2 {
+        object
+            .entry("remote_networks")
+            .or_insert_with(|| Value::Array(Vec::new()));
+        version = 3;
+    }
+
+    // 3 -> 4: add mirrors of remote shares
+    if version == 3 {
+        object
+            .entry("mirrors")
+            .or_insert_with(|| Value::Array(Vec::new()));
+        version = 4;
+    }
+
+    // 4 -> 5: add two-way sync folders
+    if version == 4 {
+        object
+            .entry("sync_folders")
+            .or_insert_with(|| Value::Array(Vec::new()));
+        version = 5;
+    }
+
+    object.insert("version".into(), Value::from(version));
+
+    Ok(serde_json::from_value(value)?)
+}
+
+fn validate(config: &PrivateConfig) -> Result<(), FileError> {
+    let mut names = HashSet::new();
+    for linked_path in &config.linked_paths {
+        if linked_path.name.is_empty() {
+            return Err(FileError::InvalidConfigError(
+                "linked path with an empty name".into(),
+            ));
+        }
+        if !names.insert(linked_path.name.as_str()) {
+            return Err(FileError::InvalidConfigError(format!(
+                "duplicate linked path name '{}'",
+                linked_path.name
+            )));
+        }
+    }
+
+    let mut names = HashSet::new();
+    for network in &config.networks {
+        if network.name().is_empty() {
+            return Err(FileError::InvalidConfigError(
+                "network with an empty name".into(),
+            ));
+        }
+        if !names.insert(network.name()) {
+            return Err(FileError::InvalidConfigError(format!(
+                "duplicate network name '{}'",
+                network.name()
+            )));
+        }
+    }
+    for network in &config.remote_networks {
+        if network.name.is_empty() {
+            return Err(FileError::InvalidConfigError(
+                "remote network with an empty name".into(),
+            ));
+        }
+        if !names.insert(network.name.as_str()) {
+            return Err(FileError::InvalidConfigError(format!(
+                "duplicate network name '{}'",
+                network.name
+            )));
+        }
+    }
+
+    Ok(())
+}
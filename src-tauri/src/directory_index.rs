@@ -0,0 +1,219 @@
+//Uses
+use crate::auth::SharedPolicy;
+use crate::listing::{normalize_path, read_directory, share_path, ShareMap};
+use crate::manifest::{encode_uri_component, escape_html, format_size};
+use crate::types::{DirEntry, EntryKind, LinkedPath, SortKey, SortOrder};
+use serde::Deserialize;
+use warp::filters::path::FullPath;
+use warp::filters::BoxedFilter;
+use warp::http::{header, StatusCode};
+use warp::{Filter, Rejection, Reply};
+
+#[derive(Deserialize)]
+struct IndexQuery {
+    #[serde(default)]
+    sort: SortKey,
+    #[serde(default)]
+    order: SortOrder,
+    // Carried over into every link so query token auth keeps working
+    token: Option<String>,
+}
+
+// `GET /<share>/<path>/` rendering an HTML page for directories. Sits behind
+// the file routes, so it only sees paths they couldn't serve.
+pub fn directory_index_route(
+    shares: ShareMap,
+    access: SharedPolicy,
+) -> BoxedFilter<(warp::reply::Response,)> {
+    warp::get()
+        .and(warp::path::full())
+        .and(share_path(shares, access))
+        .and(warp::query::<IndexQuery>())
+        .and_then(
+            |full: FullPath, share: LinkedPath, relative: String, query: IndexQuery| async move {
+                let (sort, order) = (query.sort, query.order);
+                let read_share = share.clone();
+                let read_relative = relative.clone();
+                let entries = tauri::async_runtime::spawn_blocking(move || {
+                    read_directory(&read_share, &read_relative, sort, order)
+                })
+                .await
+                .ok()
+                .flatten()
+                .ok_or_else(warp::reject::not_found)?;
+
+                // Relative links in the page only resolve from a URL ending in '/'
+                if !full.as_str().ends_with('/') {
+                    let location = format!("{}/{}", full.as_str(), token_query(&query));
+                    let reply = warp::reply::with_status("", StatusCode::FOUND);
+                    let reply = warp::reply::with_header(reply, header::LOCATION, location);
+                    return Ok::<_, Rejection>(reply.into_response());
+                }
+
+                let page = render_index(&share.name, &normalize_path(&relative), &entries, &query);
+                Ok(warp::reply::html(page).into_response())
+            },
+        )
+        .boxed()
+}
+
+fn render_index(share: &str, path: &str, entries: &[DirEntry], query: &IndexQuery) -> String {
+    let token = token_query(query);
+    let title = if path.is_empty() {
+        share.to_string()
+    } else {
+        format!("{}/{}", share, path)
+    };
+
+    // Every ancestor links to its own index, starting at the landing page
+    let mut href = format!("/{}/", encode_uri_component(share));
+    let mut breadcrumbs = vec![
+        format!("<a href=\"/{}\">Shares</a>", escape_html(&token)),
+        format!(
+            "<a href=\"{}{}\">{}</a>",
+            escape_html(&href),
+            escape_html(&token),
+            escape_html(share)
+        ),
+    ];
+    for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+        href.push_str(&encode_uri_component(segment));
+        href.push('/');
+        breadcrumbs.push(format!(
+            "<a href=\"{}{}\">{}</a>",
+            escape_html(&href),
+            escape_html(&token),
+            escape_html(segment)
+        ));
+    }
+
+    let header_cell = |key: SortKey, label: &str| {
+        // Clicking the active column flips the order
+        let next_order = if query.sort == key && query.order == SortOrder::Asc {
+            SortOrder::Desc
+        } else {
+            SortOrder::Asc
+        };
+        let arrow = match (query.sort == key, query.order) {
+            (true, SortOrder::Asc) => " &#9650;",
+            (true, SortOrder::Desc) => " &#9660;",
+            (false, _) => "",
+        };
+        let mut href = format!(
+            "?sort={}&order={}",
+            sort_param(key),
+            order_param(next_order)
+        );
+        if let Some(token) = &query.token {
+            href.push_str(&format!("&token={}", encode_uri_component(token)));
+        }
+        format!(
+            "<th><a href=\"{}\">{}</a>{}</th>",
+            escape_html(&href),
+            label,
+            arrow
+        )
+    };
+
+    let mut rows = String::new();
+    if !path.is_empty() {
+        rows.push_str(&format!(
+            "<tr><td><a href=\"../{}\">../</a></td><td></td><td></td><td></td></tr>\n",
+            escape_html(&token)
+        ));
+    }
+    for entry in entries {
+        let name = encode_uri_component(&entry.name);
+        let row = match entry.kind {
+            EntryKind::Directory => format!(
+                "<tr><td><a href=\"{}/{}\">{}/</a></td><td></td><td>{}</td><td></td></tr>\n",
+                name,
+                escape_html(&token),
+                escape_html(&entry.name),
+                entry.modified.map(format_timestamp).unwrap_or_default()
+            ),
+            EntryKind::File => format!(
+                "<tr><td><a href=\"{0}{1}\">{2}</a></td><td class=\"size\">{3}</td><td>{4}</td>\
+                 <td><a href=\"{0}{1}\" download>Download</a></td></tr>\n",
+                name,
+                escape_html(&token),
+                escape_html(&entry.name),
+                format_size(entry.size),
+                entry.modified.map(format_timestamp).unwrap_or_default()
+            ),
+        };
+        rows.push_str(&row);
+    }
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{} - Quartz</title>\n\
+         <style>body{{font-family:sans-serif;margin:2em}}td,th{{padding:.2em 1em;text-align:left}}\
+         td.size{{text-align:right}}</style></head>\n\
+         <body>\n<nav>{}</nav>\n<table>\n<tr>{}{}{}<th></th></tr>\n{}</table>\n</body>\n</html>\n",
+        escape_html(&title),
+        breadcrumbs.join(" / "),
+        header_cell(SortKey::Name, "Name"),
+        header_cell(SortKey::Size, "Size"),
+        header_cell(SortKey::Modified, "Modified"),
+        rows
+    )
+}
+
+fn token_query(query: &IndexQuery) -> String {
+    query
+        .token
+        .as_ref()
+        .map(|token| format!("?token={}", encode_uri_component(token)))
+        .unwrap_or_default()
+}
+
+fn sort_param(sort: SortKey) -> &'static str {
+    match sort {
+        SortKey::Name => "name",
+        SortKey::Size => "size",
+        SortKey::Modified => "modified",
+        SortKey::Type => "type",
+    }
+}
+
+fn order_param(order: SortOrder) -> &'static str {
+    match order {
+        SortOrder::Asc => "asc",
+        SortOrder::Desc => "desc",
+    }
+}
+
+// "YYYY-MM-DD HH:MM" in UTC
+fn format_timestamp(secs: u64) -> String {
+    let (year, month, day, hour, minute, _) = civil_time(secs);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        year, month, day, hour, minute
+    )
+}
+
+// (year, month, day, hour, minute, second) in UTC, converted with the
+// days-to-civil algorithm from http://howardhinnant.github.io/date_algorithms.html
+pub fn civil_time(secs: u64) -> (i64, u32, u32, u32, u32, u32) {
+    let days = (secs / 86_400) as i64;
+    let seconds = (secs % 86_400) as u32;
+
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (
+        year,
+        month,
+        day,
+        seconds / 3_600,
+        seconds % 3_600 / 60,
+        seconds % 60,
+    )
+}
@@ -0,0 +1,329 @@
+//Uses
+use crate::catalog::{set_hash, subscribe, with_catalog};
+use crate::config::ConfigStore;
+use crate::listing::{file_sha256, modified_secs};
+use crate::types::{
+    CatalogEntry, DedupeResult, DuplicateFile, DuplicateGroup, DuplicateReport, EntryKind,
+    FileError, LinkedPath,
+};
+use sha2::{Digest, Sha256};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex as StdMutex;
+
+// Linked path name and '/' separated path within it
+type FileKey = (String, String);
+
+// Files that couldn't be hashed, with the size and modification time they
+// had, so they're only tried again once they change
+lazy_static::lazy_static! {
+    static ref UNHASHABLE: StdMutex<HashMap<FileKey, (u64, Option<u64>)>> =
+        StdMutex::new(HashMap::new());
+}
+
+// A file in a catalog, with where it is on disk
+struct CatalogFile {
+    linked_path: String,
+    relative: String,
+    path: PathBuf,
+    entry: CatalogEntry,
+}
+
+// Groups the hashed files of all linked paths, or the ones listed, by
+// content. Files smaller than `min_size` bytes are left out, and so are
+// empty ones.
+#[tauri::command]
+pub async fn find_duplicates(
+    linked_paths: Option<Vec<String>>,
+    min_size: Option<u64>,
+) -> Result<DuplicateReport, FileError> {
+    let shares = selected_shares(linked_paths.as_deref())?;
+    tauri::async_runtime::spawn_blocking(move || duplicates(&shares, min_size.unwrap_or(0)).0)
+        .await
+        .map_err(|e| FileError::FileOpenError(io::Error::other(e)))
+}
+
+// Replaces every file of the duplicate groups with these hashes, all groups
+// when None, by a hard link to the first file of its group. They share one
+// copy on disk from then on, so an edit to one shows in all of them. Files
+// are hashed again first and left alone if they no longer match.
+#[tauri::command]
+pub async fn hardlink_duplicates(
+    sha256: Option<Vec<String>>,
+    linked_paths: Option<Vec<String>>,
+) -> Result<DedupeResult, FileError> {
+    let shares = selected_shares(linked_paths.as_deref())?;
+    tauri::async_runtime::spawn_blocking(move || {
+        let (report, mut paths) = duplicates(&shares, 0);
+        let mut result = DedupeResult::default();
+        for group in report.groups {
+            if sha256
+                .as_ref()
+                .is_some_and(|hashes| !hashes.contains(&group.sha256))
+            {
+                continue;
+            }
+            let files: Vec<(String, PathBuf)> = group
+                .files
+                .iter()
+                .filter_map(|file| {
+                    let label = format!("{}/{}", file.linked_path, file.path);
+                    let path = paths.remove(&(file.linked_path.clone(), file.path.clone()))?;
+                    Some((label, path))
+                })
+                .collect();
+            link_group(&group, &files, &mut result);
+        }
+        result
+    })
+    .await
+    .map_err(|e| FileError::FileOpenError(io::Error::other(e)))
+}
+
+fn selected_shares(names: Option<&[String]>) -> Result<Vec<LinkedPath>, FileError> {
+    let store = ConfigStore::load()?;
+    Ok(store
+        .linked_paths()
+        .iter()
+        .filter(|share| names.is_none_or(|names| names.contains(&share.name)))
+        .cloned()
+        .collect())
+}
+
+// The report, plus where each reported file is on disk
+fn duplicates(
+    shares: &[LinkedPath],
+    min_size: u64,
+) -> (DuplicateReport, HashMap<FileKey, PathBuf>) {
+    let mut by_content: BTreeMap<(String, u64), Vec<CatalogFile>> = BTreeMap::new();
+    let mut unhashed_files = 0;
+    // Overlapping linked paths reach the same file under different names
+    let mut seen_paths = HashSet::new();
+    for file in catalog_files(shares) {
+        if file.entry.size == 0 || file.entry.size < min_size {
+            continue;
+        }
+        if !seen_paths.insert(file.path.clone()) {
+            continue;
+        }
+        match &file.entry.sha256 {
+            Some(sha256) => by_content
+                .entry((sha256.clone(), file.entry.size))
+                .or_default()
+                .push(file),
+            None => unhashed_files += 1,
+        }
+    }
+
+    let mut groups = Vec::new();
+    let mut paths = HashMap::new();
+    for ((sha256, size), files) in by_content {
+        // Hard links to one file take its space once
+        let mut file_ids = HashSet::new();
+        let copies = files
+            .iter()
+            .filter(|file| file.entry.file_id.is_none_or(|id| file_ids.insert(id)))
+            .count() as u64;
+        if copies < 2 {
+            continue;
+        }
+        let mut group_files = Vec::new();
+        for file in files {
+            group_files.push(DuplicateFile {
+                linked_path: file.linked_path.clone(),
+                path: file.relative.clone(),
+            });
+            paths.insert((file.linked_path, file.relative), file.path);
+        }
+        groups.push(DuplicateGroup {
+            sha256,
+            size,
+            files: group_files,
+            wasted_bytes: size * (copies - 1),
+        });
+    }
+    groups.sort_by_key(|group| Reverse(group.wasted_bytes));
+
+    let report = DuplicateReport {
+        wasted_bytes: groups.iter().map(|group| group.wasted_bytes).sum(),
+        groups,
+        unhashed_files,
+    };
+    (report, paths)
+}
+
+// Every file in the catalogs of `shares`. Paths go through the canonical
+// linked path so files shared by overlapping linked paths can be told apart.
+fn catalog_files(shares: &[LinkedPath]) -> Vec<CatalogFile> {
+    let mut files = Vec::new();
+    for share in shares {
+        let Ok(root) = fs::canonicalize(&share.path) else {
+            continue;
+        };
+        with_catalog(&share.name, |catalog| {
+            for (relative, entry) in &catalog.entries {
+                if entry.kind != EntryKind::File {
+                    continue;
+                }
+                files.push(CatalogFile {
+                    linked_path: share.name.clone(),
+                    relative: relative.clone(),
+                    path: relative
+                        .split('/')
+                        .fold(root.clone(), |path, segment| path.join(segment)),
+                    entry: entry.clone(),
+                });
+            }
+        });
+    }
+    files
+}
+
+fn link_group(group: &DuplicateGroup, files: &[(String, PathBuf)], result: &mut DedupeResult) {
+    let Some(((keep_label, keep), others)) = files.split_first() else {
+        return;
+    };
+    let keep_metadata = match verified(keep, group) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            result.failed.push(format!("{}: {}", keep_label, e));
+            for (label, _) in others {
+                result
+                    .failed
+                    .push(format!("{}: {} can't be linked to", label, keep_label));
+            }
+            return;
+        }
+    };
+    // A copy with several names only frees its space once all are linked
+    let mut replaced = HashSet::new();
+    for (label, path) in others {
+        match link_file(keep, &keep_metadata, path, group) {
+            Ok(Some(metadata)) => {
+                result.linked += 1;
+                if file_key(&metadata).is_none_or(|key| replaced.insert(key)) {
+                    result.freed_bytes += group.size;
+                }
+            }
+            Ok(None) => {}
+            Err(e) => result.failed.push(format!("{}: {}", label, e)),
+        }
+    }
+}
+
+// Replaces `path` by a hard link to `keep`, returning what was there. None
+// if it already was a link to it.
+fn link_file(
+    keep: &Path,
+    keep_metadata: &fs::Metadata,
+    path: &Path,
+    group: &DuplicateGroup,
+) -> io::Result<Option<fs::Metadata>> {
+    let metadata = verified(path, group)?;
+    if file_key(&metadata).is_some() && file_key(&metadata) == file_key(keep_metadata) {
+        return Ok(None);
+    }
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::other("no file name"))?;
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(name);
+    temp_name.push(".quartz-link");
+    let temp = path.with_file_name(temp_name);
+    fs::hard_link(keep, &temp)?;
+    // Renamed over the copy, so the file never goes missing
+    if let Err(e) = fs::rename(&temp, path) {
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+    Ok(Some(metadata))
+}
+
+// Metadata of a file that still holds the group's content, read again
+// rather than taken from the catalog
+fn verified(path: &Path, group: &DuplicateGroup) -> io::Result<fs::Metadata> {
+    let metadata = fs::symlink_metadata(path)?;
+    if !metadata.is_file() || metadata.len() != group.size {
+        return Err(io::Error::other("file changed since it was hashed"));
+    }
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    if format!("{:x}", hasher.finalize()) != group.sha256 {
+        return Err(io::Error::other("file changed since it was hashed"));
+    }
+    Ok(metadata)
+}
+
+// Device and inode, the same for every hard link to a file
+#[cfg(unix)]
+fn file_key(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+// Hard links can't be told apart from copies here
+#[cfg(not(unix))]
+fn file_key(_metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+// Hashes the files of every linked path's catalog that have no hash yet,
+// picking up new and changed files as the catalogs are updated. Hashes stay
+// in the catalogs for as long as the file's size and modification time do.
+pub async fn run_hasher() {
+    let mut updates = subscribe();
+    loop {
+        if let Err(e) = tauri::async_runtime::spawn_blocking(hash_catalogs).await {
+            eprintln!("Failed to hash catalogs: {}", e);
+        }
+        if updates.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+fn hash_catalogs() {
+    let shares = match selected_shares(None) {
+        Ok(shares) => shares,
+        Err(e) => {
+            eprintln!("Failed to load linked paths for hashing: {}", e);
+            return;
+        }
+    };
+    let files: Vec<CatalogFile> = catalog_files(&shares)
+        .into_iter()
+        .filter(|file| file.entry.sha256.is_none())
+        .collect();
+    let mut hashed = 0;
+    for file in files {
+        let key = (file.linked_path.clone(), file.relative.clone());
+        let state = (file.entry.size, file.entry.modified);
+        if UNHASHABLE.lock().unwrap().get(&key) == Some(&state) {
+            continue;
+        }
+        let metadata = match fs::symlink_metadata(&file.path) {
+            Ok(metadata) => metadata,
+            // Gone or changed, the catalog catches up on its own
+            Err(_) => continue,
+        };
+        if metadata.len() != file.entry.size || modified_secs(&metadata) != file.entry.modified {
+            continue;
+        }
+        match file_sha256(&file.path, &metadata) {
+            Ok(sha256) => {
+                set_hash(&file.linked_path, &file.relative, &file.entry, sha256);
+                hashed += 1;
+            }
+            Err(e) => {
+                eprintln!("Failed to hash {}: {}", file.path.display(), e);
+                UNHASHABLE.lock().unwrap().insert(key, state);
+            }
+        }
+    }
+    if hashed > 0 {
+        println!("Hashed {} files", hashed);
+    }
+}
@@ -0,0 +1,566 @@
+//Uses
+use crate::auth::SharedPolicy;
+use crate::listing::{resolve_share_path, share_path, ShareMap};
+use crate::types::LinkedPath;
+use headers::{
+    ETag, HeaderMapExt, IfMatch, IfModifiedSince, IfNoneMatch, IfRange, IfUnmodifiedSince,
+    LastModified,
+};
+use std::fs;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use warp::filters::BoxedFilter;
+use warp::http::{header, HeaderMap, HeaderValue, Method, Response, StatusCode};
+use warp::hyper::body::{Body, Bytes};
+use warp::Filter;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+// More ranges than this are answered with the whole file
+const MAX_RANGES: usize = 64;
+
+// `GET`/`HEAD /<share>/<path>` with byte ranges (including multipart
+// responses), ETag/Last-Modified validators and conditional requests.
+// Directories are served through their index.html if they have one.
+pub fn file_route(shares: ShareMap, access: SharedPolicy) -> BoxedFilter<(warp::reply::Response,)> {
+    warp::get()
+        .or(warp::head())
+        .unify()
+        .and(warp::method())
+        .and(share_path(shares, access))
+        .and(warp::header::headers_cloned())
+        .and_then(
+            |method: Method, share: LinkedPath, relative: String, headers: HeaderMap| async move {
+                let path =
+                    tauri::async_runtime::spawn_blocking(move || resolve_file(&share, &relative))
+                        .await
+                        .ok()
+                        .flatten()
+                        .ok_or_else(warp::reject::not_found)?;
+                serve_file(&path, &headers, method == Method::HEAD)
+                    .await
+                    .ok_or_else(warp::reject::not_found)
+            },
+        )
+        .boxed()
+}
+
+fn resolve_file(share: &LinkedPath, relative: &str) -> Option<PathBuf> {
+    let path = resolve_share_path(share, relative)?;
+    if path.is_file() {
+        return Some(path);
+    }
+    // index.html may itself be a symlink, so it goes through the same check
+    let index = format!("{}/index.html", relative.trim_end_matches('/'));
+    resolve_share_path(share, &index).filter(|index| index.is_file())
+}
+
+// What a response says about the file it serves
+struct FileInfo {
+    len: u64,
+    etag: ETag,
+    last_modified: LastModified,
+    modified: SystemTime,
+    mime: String,
+}
+
+async fn serve_file(path: &Path, headers: &HeaderMap, head: bool) -> Option<Response<Body>> {
+    let mut file = tokio::fs::File::open(path).await.ok()?;
+    let metadata = file.metadata().await.ok()?;
+    let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+    let info = FileInfo {
+        len: metadata.len(),
+        etag: file_etag(&metadata, modified),
+        last_modified: LastModified::from(modified),
+        modified,
+        mime: mime_guess::from_path(path)
+            .first_or_octet_stream()
+            .to_string(),
+    };
+
+    // RFC 9110 13.2.2: If-Match, If-Unmodified-Since, If-None-Match, If-Modified-Since
+    let precondition_failed = match headers.typed_get::<IfMatch>() {
+        Some(if_match) => !if_match.precondition_passes(&info.etag),
+        None => headers
+            .typed_get::<IfUnmodifiedSince>()
+            .is_some_and(|since| !since.precondition_passes(info.modified)),
+    };
+    if precondition_failed {
+        return Some(empty_response(StatusCode::PRECONDITION_FAILED, &info));
+    }
+    let not_modified = match headers.typed_get::<IfNoneMatch>() {
+        Some(if_none_match) => !if_none_match.precondition_passes(&info.etag),
+        None => headers
+            .typed_get::<IfModifiedSince>()
+            .is_some_and(|since| !since.is_modified(info.modified)),
+    };
+    if not_modified {
+        return Some(empty_response(StatusCode::NOT_MODIFIED, &info));
+    }
+
+    // A stale If-Range means the client's partial copy is outdated, so it gets
+    // the whole file instead of the requested ranges
+    let ranges_apply = headers
+        .typed_get::<IfRange>()
+        .is_none_or(|if_range| !if_range.is_modified(Some(&info.etag), Some(&info.last_modified)));
+    let ranges = match headers.get(header::RANGE) {
+        Some(range) if ranges_apply => parse_ranges(range, info.len),
+        _ => None,
+    };
+
+    let mut response = Response::builder().header(header::ACCEPT_RANGES, "bytes");
+    let (parts, content_length) = match ranges {
+        None => {
+            response = response
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, &info.mime);
+            (vec![(Bytes::new(), 0, info.len)], info.len)
+        }
+        Some(ranges) if ranges.is_empty() => {
+            return Response::builder()
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", info.len))
+                .body(Body::empty())
+                .ok();
+        }
+        Some(ranges) if ranges.len() == 1 => {
+            let (start, end) = ranges[0];
+            response = response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_TYPE, &info.mime)
+                .header(
+                    header::CONTENT_RANGE,
+                    format!("bytes {}-{}/{}", start, end, info.len),
+                );
+            (vec![(Bytes::new(), start, end + 1)], end + 1 - start)
+        }
+        Some(ranges) => {
+            let boundary = format!("{:016x}", rand::random::<u64>());
+            let parts: Vec<(Bytes, u64, u64)> = ranges
+                .iter()
+                .map(|&(start, end)| {
+                    let part_header = format!(
+                        "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                        boundary, info.mime, start, end, info.len
+                    );
+                    (Bytes::from(part_header), start, end + 1)
+                })
+                .chain(std::iter::once((
+                    Bytes::from(format!("\r\n--{}--\r\n", boundary)),
+                    0,
+                    0,
+                )))
+                .collect();
+            let content_length = parts
+                .iter()
+                .map(|(prefix, start, end)| prefix.len() as u64 + end - start)
+                .sum();
+            response = response.status(StatusCode::PARTIAL_CONTENT).header(
+                header::CONTENT_TYPE,
+                format!("multipart/byteranges; boundary={}", boundary),
+            );
+            (parts, content_length)
+        }
+    };
+    response = response.header(header::CONTENT_LENGTH, content_length);
+    if head {
+        return Some(with_validators(response.body(Body::empty()).ok()?, &info));
+    }
+
+    let (mut sender, body) = Body::channel();
+    tauri::async_runtime::spawn(async move {
+        let mut buf = vec![0u8; CHUNK_SIZE];
+        for (prefix, start, end) in parts {
+            if !prefix.is_empty() && sender.send_data(prefix).await.is_err() {
+                return;
+            }
+            if file.seek(SeekFrom::Start(start)).await.is_err() {
+                sender.abort();
+                return;
+            }
+            let mut remaining = end - start;
+            while remaining > 0 {
+                let want = remaining.min(CHUNK_SIZE as u64) as usize;
+                let read = match file.read(&mut buf[..want]).await {
+                    Ok(0) | Err(_) => {
+                        // Truncated while serving, the client sees a short body
+                        sender.abort();
+                        return;
+                    }
+                    Ok(read) => read,
+                };
+                if sender
+                    .send_data(Bytes::copy_from_slice(&buf[..read]))
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+                remaining -= read as u64;
+            }
+        }
+    });
+    Some(with_validators(response.body(body).ok()?, &info))
+}
+
+// Parses `bytes=a-b,c-,-n` into sorted, merged, inclusive ranges within the
+// file. Returns None when the header should be ignored (malformed, not
+// bytes, too many ranges) and an empty list when nothing is satisfiable.
+fn parse_ranges(range: &HeaderValue, len: u64) -> Option<Vec<(u64, u64)>> {
+    let specs = range.to_str().ok()?.trim().strip_prefix("bytes=")?;
+    let mut ranges = Vec::new();
+    for spec in specs
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+    {
+        let (first, last) = spec.split_once('-')?;
+        let range = match (first.trim(), last.trim()) {
+            ("", suffix) => {
+                let suffix: u64 = suffix.parse().ok()?;
+                (suffix > 0 && len > 0).then(|| (len.saturating_sub(suffix), len - 1))
+            }
+            (first, "") => {
+                let first: u64 = first.parse().ok()?;
+                (first < len).then(|| (first, len - 1))
+            }
+            (first, last) => {
+                let (first, last): (u64, u64) = (first.parse().ok()?, last.parse().ok()?);
+                if first > last {
+                    return None;
+                }
+                (first < len).then(|| (first, last.min(len - 1)))
+            }
+        };
+        ranges.extend(range);
+    }
+    if ranges.len() > MAX_RANGES {
+        return None;
+    }
+
+    // Overlapping or adjacent ranges are sent once
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    Some(merged)
+}
+
+// Changes whenever the size or modification time does
+fn file_etag(metadata: &fs::Metadata, modified: SystemTime) -> ETag {
+    let since_epoch = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!(
+        "\"{:x}-{:x}.{:x}\"",
+        metadata.len(),
+        since_epoch.as_secs(),
+        since_epoch.subsec_nanos()
+    )
+    .parse()
+    .expect("hex digits form a valid entity tag")
+}
+
+fn empty_response(status: StatusCode, info: &FileInfo) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    with_validators(response, info)
+}
+
+fn with_validators(mut response: Response<Body>, info: &FileInfo) -> Response<Body> {
+    response.headers_mut().typed_insert(info.etag.clone());
+    response.headers_mut().typed_insert(info.last_modified);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::SharedPolicy;
+    use crate::server::server_routes;
+    use crate::types::{LinkedPath, SymlinkPolicy};
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::{Duration, UNIX_EPOCH};
+    use warp::filters::BoxedFilter;
+    use warp::http::{header, Response, StatusCode};
+    use warp::hyper::body::Bytes;
+
+    const FILE_LEN: usize = 1000;
+    const FILE_PATH: &str = "/media/video.bin";
+    const LAST_MODIFIED: &str = "Tue, 14 Nov 2023 22:13:20 GMT";
+
+    // A share holding one 1000 byte file, removed again at the end of the test
+    struct TestShare {
+        dir: PathBuf,
+        routes: BoxedFilter<(warp::reply::Response,)>,
+        contents: Vec<u8>,
+    }
+
+    impl TestShare {
+        fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("quartz-files-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            let contents: Vec<u8> = (0..FILE_LEN).map(|i| (i % 251) as u8).collect();
+            let path = dir.join("video.bin");
+            fs::write(&path, &contents).unwrap();
+            // Whole seconds, so Last-Modified is exact
+            fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+                .unwrap();
+
+            let share = LinkedPath {
+                name: "media".to_string(),
+                path: dir.clone(),
+                show_hidden: false,
+                symlinks: SymlinkPolicy::default(),
+                writable: false,
+                quota_bytes: None,
+                full_text: false,
+            };
+            let routes = server_routes(
+                "test".to_string(),
+                vec![share],
+                SharedPolicy::default(),
+                true,
+            );
+            TestShare {
+                dir,
+                routes,
+                contents,
+            }
+        }
+
+        async fn get(&self, headers: &[(&str, &str)]) -> Response<Bytes> {
+            let mut request = warp::test::request().path(FILE_PATH);
+            for (name, value) in headers {
+                request = request.header(*name, *value);
+            }
+            request.reply(&self.routes).await
+        }
+
+        async fn etag(&self) -> String {
+            let response = self.get(&[]).await;
+            header_str(&response, header::ETAG).to_string()
+        }
+    }
+
+    impl Drop for TestShare {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn header_str(response: &Response<Bytes>, name: header::HeaderName) -> &str {
+        response
+            .headers()
+            .get(&name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn serves_whole_file_with_validators() {
+        let share = TestShare::new("whole");
+        let response = share.get(&[]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header_str(&response, header::ACCEPT_RANGES), "bytes");
+        assert_eq!(header_str(&response, header::CONTENT_LENGTH), "1000");
+        assert_eq!(header_str(&response, header::LAST_MODIFIED), LAST_MODIFIED);
+        assert!(header_str(&response, header::ETAG).starts_with('"'));
+        assert_eq!(response.body().as_ref(), share.contents.as_slice());
+
+        let head = warp::test::request()
+            .method("HEAD")
+            .path(FILE_PATH)
+            .header("range", "bytes=0-99")
+            .reply(&share.routes)
+            .await;
+        assert_eq!(head.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(header_str(&head, header::CONTENT_LENGTH), "100");
+        assert!(head.body().is_empty());
+    }
+
+    #[tokio::test]
+    async fn serves_single_ranges() {
+        let share = TestShare::new("single");
+        for (range, start, end) in [
+            ("bytes=100-199", 100, 199),
+            ("bytes=990-", 990, 999),
+            ("bytes=-10", 990, 999),
+            // Clamped to the end of the file
+            ("bytes=900-5000", 900, 999),
+            // Overlapping ranges are merged into one
+            ("bytes=0-9,5-14", 0, 14),
+        ] {
+            let response = share.get(&[("range", range)]).await;
+            assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT, "{}", range);
+            assert_eq!(
+                header_str(&response, header::CONTENT_RANGE),
+                format!("bytes {}-{}/1000", start, end)
+            );
+            assert_eq!(
+                header_str(&response, header::CONTENT_LENGTH),
+                (end + 1 - start).to_string()
+            );
+            assert_eq!(response.body().as_ref(), &share.contents[start..=end]);
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_multipart_ranges() {
+        let share = TestShare::new("multipart");
+        let response = share.get(&[("range", "bytes=0-9, 500-519,-5")]).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        let content_type = header_str(&response, header::CONTENT_TYPE);
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .expect("multipart content type");
+        assert_eq!(
+            header_str(&response, header::CONTENT_LENGTH),
+            response.body().len().to_string()
+        );
+
+        let body = response.body().as_ref();
+        let delimiter = format!("\r\n--{}", boundary);
+        let mut parts = Vec::new();
+        let mut rest = body;
+        while let Some(at) = find(rest, delimiter.as_bytes()) {
+            rest = &rest[at + delimiter.len()..];
+            if rest.starts_with(b"--") {
+                break;
+            }
+            let headers_end = find(rest, b"\r\n\r\n").unwrap();
+            let headers = std::str::from_utf8(&rest[..headers_end])
+                .unwrap()
+                .to_string();
+            let data_start = headers_end + 4;
+            let data_end = data_start + find(&rest[data_start..], delimiter.as_bytes()).unwrap();
+            parts.push((headers, rest[data_start..data_end].to_vec()));
+            rest = &rest[data_end..];
+        }
+
+        let expected = [(0, 9), (500, 519), (995, 999)];
+        assert_eq!(parts.len(), expected.len());
+        for ((headers, data), (start, end)) in parts.iter().zip(expected) {
+            assert!(headers.contains("Content-Type: application/octet-stream"));
+            assert!(headers.contains(&format!("Content-Range: bytes {}-{}/1000", start, end)));
+            assert_eq!(data.as_slice(), &share.contents[start..=end]);
+        }
+    }
+
+    fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+        haystack
+            .windows(needle.len())
+            .position(|window| window == needle)
+    }
+
+    #[tokio::test]
+    async fn if_range_needs_a_current_validator() {
+        let share = TestShare::new("if-range");
+        let etag = share.etag().await;
+        let stale_date = "Mon, 13 Nov 2023 22:13:20 GMT";
+        for (if_range, expected) in [
+            (etag.as_str(), StatusCode::PARTIAL_CONTENT),
+            ("\"stale\"", StatusCode::OK),
+            (LAST_MODIFIED, StatusCode::PARTIAL_CONTENT),
+            (stale_date, StatusCode::OK),
+        ] {
+            let response = share
+                .get(&[("range", "bytes=10-19"), ("if-range", if_range)])
+                .await;
+            assert_eq!(response.status(), expected, "If-Range: {}", if_range);
+            let body = if expected == StatusCode::OK {
+                &share.contents[..]
+            } else {
+                &share.contents[10..20]
+            };
+            assert_eq!(response.body().as_ref(), body);
+        }
+    }
+
+    #[tokio::test]
+    async fn answers_conditional_requests_with_not_modified() {
+        let share = TestShare::new("conditional");
+        let etag = share.etag().await;
+        let earlier = "Mon, 13 Nov 2023 22:13:20 GMT";
+        let later = "Wed, 15 Nov 2023 22:13:20 GMT";
+        for (headers, expected) in [
+            (
+                vec![("if-none-match", etag.as_str())],
+                StatusCode::NOT_MODIFIED,
+            ),
+            (vec![("if-none-match", "\"other\"")], StatusCode::OK),
+            (vec![("if-none-match", "*")], StatusCode::NOT_MODIFIED),
+            (
+                vec![("if-modified-since", LAST_MODIFIED)],
+                StatusCode::NOT_MODIFIED,
+            ),
+            (vec![("if-modified-since", later)], StatusCode::NOT_MODIFIED),
+            (vec![("if-modified-since", earlier)], StatusCode::OK),
+            // If-None-Match wins over If-Modified-Since
+            (
+                vec![
+                    ("if-none-match", "\"other\""),
+                    ("if-modified-since", LAST_MODIFIED),
+                ],
+                StatusCode::OK,
+            ),
+            // A conditional request that fails is not narrowed by its range
+            (
+                vec![("if-none-match", etag.as_str()), ("range", "bytes=0-9")],
+                StatusCode::NOT_MODIFIED,
+            ),
+            (
+                vec![("if-match", "\"other\"")],
+                StatusCode::PRECONDITION_FAILED,
+            ),
+            (
+                vec![("if-unmodified-since", earlier)],
+                StatusCode::PRECONDITION_FAILED,
+            ),
+        ] {
+            let response = share.get(&headers).await;
+            assert_eq!(response.status(), expected, "{:?}", headers);
+            if expected == StatusCode::NOT_MODIFIED {
+                assert!(response.body().is_empty());
+                assert_eq!(header_str(&response, header::ETAG), etag);
+                assert_eq!(header_str(&response, header::LAST_MODIFIED), LAST_MODIFIED);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_unsatisfiable_ranges() {
+        let share = TestShare::new("unsatisfiable");
+        for range in [
+            "bytes=1000-",
+            "bytes=5000-6000",
+            "bytes=-0",
+            "bytes=1000-1001,2000-",
+        ] {
+            let response = share.get(&[("range", range)]).await;
+            assert_eq!(
+                response.status(),
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                "{}",
+                range
+            );
+            assert_eq!(header_str(&response, header::CONTENT_RANGE), "bytes */1000");
+        }
+
+        // Malformed and non-byte ranges are ignored
+        for range in ["bytes=abc", "items=0-9", "bytes=9-0"] {
+            let response = share.get(&[("range", range)]).await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", range);
+            assert_eq!(response.body().len(), FILE_LEN);
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//Uses
+use crate::config::ConfigStore;
+use crate::types::{ConfigIssue, FileError, LinkedPath};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// How a directory being linked sits against a linked path
+pub enum Overlap {
+    Same,
+    // '/' separated path of the new directory inside the linked path
+    Inside(String),
+    // '/' separated path of the linked path inside the new directory
+    Around(String),
+}
+
+// Problems with the stored config, such as linked paths that overlap
+#[tauri::command]
+pub fn check_config_health() -> Result<Vec<ConfigIssue>, FileError> {
+    let store = ConfigStore::load()?;
+    Ok(config_issues(store.linked_paths()))
+}
+
+// Logs what the health check would report, once at startup
+pub fn report_config_issues() {
+    let store = match ConfigStore::load() {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("Failed to load config for the health check: {}", e);
+            return;
+        }
+    };
+    for issue in config_issues(store.linked_paths()) {
+        match issue {
+            ConfigIssue::Overlap {
+                outer,
+                inner,
+                relative_path,
+            } if relative_path.is_empty() => {
+                eprintln!(
+                    "Linked paths {} and {} are the same directory",
+                    outer, inner
+                )
+            }
+            ConfigIssue::Overlap {
+                outer,
+                inner,
+                relative_path,
+            } => eprintln!(
+                "Linked path {} is inside {} at {}",
+                inner, outer, relative_path
+            ),
+            ConfigIssue::MissingPath { linked_path, path } => eprintln!(
+                "Linked path {} points at {}, which can't be read",
+                linked_path,
+                path.display()
+            ),
+        }
+    }
+}
+
+pub fn config_issues(linked_paths: &[LinkedPath]) -> Vec<ConfigIssue> {
+    let mut issues = Vec::new();
+    let mut roots: Vec<(&str, PathBuf)> = Vec::new();
+    for linked_path in linked_paths {
+        match fs::canonicalize(&linked_path.path) {
+            Ok(root) => roots.push((&linked_path.name, root)),
+            Err(_) => issues.push(ConfigIssue::MissingPath {
+                linked_path: linked_path.name.clone(),
+                path: linked_path.path.clone(),
+            }),
+        }
+    }
+
+    for (i, (name, root)) in roots.iter().enumerate() {
+        for (other_name, other_root) in &roots[i + 1..] {
+            let (outer, inner, relative_path) =
+                if let Some(relative) = nested_path(other_root, root) {
+                    (name, other_name, relative)
+                } else if let Some(relative) = nested_path(root, other_root) {
+                    (other_name, name, relative)
+                } else {
+                    continue;
+                };
+            issues.push(ConfigIssue::Overlap {
+                outer: outer.to_string(),
+                inner: inner.to_string(),
+                relative_path,
+            });
+        }
+    }
+    issues
+}
+
+// Every linked path that `dir`, canonical, overlaps
+pub fn overlaps(linked_paths: &[LinkedPath], dir: &Path) -> Vec<(String, Overlap)> {
+    linked_paths
+        .iter()
+        .filter_map(|linked_path| {
+            let root = fs::canonicalize(&linked_path.path).ok()?;
+            let overlap = match (nested_path(dir, &root), nested_path(&root, dir)) {
+                (Some(relative), _) if relative.is_empty() => Overlap::Same,
+                (Some(relative), _) => Overlap::Inside(relative),
+                (None, Some(relative)) => Overlap::Around(relative),
+                (None, None) => return None,
+            };
+            Some((linked_path.name.clone(), overlap))
+        })
+        .collect()
+}
+
+// Where `inner` is below `outer`, both canonical. Empty when they're the
+// same directory, None when `inner` isn't inside `outer`.
+pub fn nested_path(inner: &Path, outer: &Path) -> Option<String> {
+    let relative = inner.strip_prefix(outer).ok()?;
+    Some(
+        relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"),
+    )
+}
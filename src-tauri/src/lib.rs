@@ -1,19 +1,61 @@
 // Modules
+mod archive;
+mod audit;
+mod auth;
+mod catalog;
+mod config;
+mod directory_index;
+mod duplicates;
+mod files;
+mod health;
+mod listing;
 mod local_dir;
+mod manifest;
+mod mirror;
+mod port_mapping;
+mod remote;
+mod resumable;
+mod search;
 mod server;
+mod sync;
+mod tls;
+mod tor;
 mod types;
+mod uploads;
 
 // Uses
+use audit::get_audit_log;
+use catalog::{get_catalog_statuses, rescan_catalog, run_catalogs};
+use config::{init_config_dir, ConfigStore};
+use duplicates::{find_duplicates, hardlink_duplicates, run_hasher};
+use health::{check_config_health, report_config_issues};
 use local_dir::{
-    create_local_network, get_linked_paths, link_directory, read_private_networks, remove_network,
-    select_directory, setup_file_watcher, unlink_directory, PRIVATE_CONFIG_FILE_PATH,
+    add_access_password, create_dark_web_network, create_internet_network, create_invite,
+    create_local_network, get_config_revision, get_linked_paths, link_directory,
+    list_access_grants, list_network_interfaces, read_private_networks, regenerate_access_token,
+    remove_network, revoke_access_grant, select_directory, set_directory_index, set_network_tls,
+    set_share_policy, set_share_write_access, setup_file_watcher, unlink_directory,
+    FS_CHANGE_CAPACITY,
+};
+use mirror::{
+    create_mirror, get_mirror_statuses, list_mirrors, remove_mirror, run_mirror_scheduler,
+    sync_mirror,
+};
+use remote::{
+    browse_remote, download_remote_file, join_network, leave_network, list_remote_networks,
+    list_remote_shares,
+};
+use search::{run_text_indexer, search_files, set_full_text_search};
+use server::{
+    get_certificate_fingerprint, get_server_statuses, restart_network_server,
+    start_file_server_command, start_network_server, stop_file_server_command, stop_network_server,
 };
-use serde_json::json;
-use server::{start_file_server_command, stop_file_server_command};
-use std::fs::*;
-use std::io::prelude::*;
-use std::path::Path;
 use std::sync::Arc;
+use sync::{
+    create_sync_folder, get_sync_statuses, list_sync_folders, remove_sync_folder,
+    run_sync_scheduler, sync_now,
+};
+use tauri::Manager;
 use tokio::sync::broadcast;
 use tokio::sync::Mutex;
 use types::ServerState;
@@ -34,50 +76,70 @@ pub fn run() {
             stop_file_server_command,
             read_private_networks,
             create_local_network,
+            get_config_revision,
+            start_network_server,
+            stop_network_server,
+            restart_network_server,
+            get_server_statuses,
+            list_network_interfaces,
+            create_internet_network,
+            regenerate_access_token,
+            create_dark_web_network,
+            set_network_tls,
+            get_certificate_fingerprint,
+            create_invite,
+            add_access_password,
+            revoke_access_grant,
+            list_access_grants,
+            set_directory_index,
+            set_share_policy,
+            set_share_write_access,
+            get_audit_log,
+            join_network,
+            list_remote_networks,
+            leave_network,
+            list_remote_shares,
+            browse_remote,
+            download_remote_file,
+            create_mirror,
+            list_mirrors,
+            remove_mirror,
+            sync_mirror,
+            get_mirror_statuses,
+            create_sync_folder,
+            list_sync_folders,
+            remove_sync_folder,
+            sync_now,
+            get_sync_statuses,
+            get_catalog_statuses,
+            rescan_catalog,
+            search_files,
+            set_full_text_search,
+            find_duplicates,
+            hardlink_duplicates,
+            check_config_health,
         ])
         .setup(|app| {
             let app_handle = app.handle().clone();
             // Ensure folders and configs are created
-            if !Path::new("../configs").is_dir() {
-                std::fs::create_dir("../configs").expect("Failed to create configs directory");
-            }
-            if !Path::new(PRIVATE_CONFIG_FILE_PATH).exists() {
-                let mut file = File::create(PRIVATE_CONFIG_FILE_PATH)
-                    .expect("Failed to create private_config file");
-                let data = json!({
-                    "linked_paths": [],
-                    "networks" : [],
-                });
-                let json_content = serde_json::to_string_pretty(&data)
-                    .expect("Failed to serialize local networks");
-                file.write_all(json_content.as_bytes())
-                    .expect("Failed to write to private_config file");
-            }
+            init_config_dir(&app_handle).expect("Failed to create configs directory");
+            ConfigStore::ensure_exists().expect("Failed to create private_config file");
+            report_config_issues();
+            // Changes inside linked paths, subsystems subscribe through the
+            // managed sender
+            let (file_watcher_tx, _) = broadcast::channel(FS_CHANGE_CAPACITY);
+            app.manage(file_watcher_tx.clone());
+            tauri::async_runtime::spawn(run_catalogs(app_handle.clone()));
+            tauri::async_runtime::spawn(run_text_indexer());
+            tauri::async_runtime::spawn(run_hasher());
+            tauri::async_runtime::spawn(run_mirror_scheduler(app_handle.clone()));
+            tauri::async_runtime::spawn(run_sync_scheduler(app_handle.clone()));
             let app_handle_clone = app_handle.clone();
             tauri::async_runtime::spawn(async move {
-                let (file_watcher_tx, _file_watcher_rx) = broadcast::channel(32);
-                let (priv_paths_file_change_tx, priv_paths_file_change_rx) =
-                    std::sync::mpsc::channel::<()>();
                 // Initialize the file watcher
-                if let Err(e) =
-                    setup_file_watcher(app_handle_clone, file_watcher_tx, priv_paths_file_change_tx)
-                        .await
-                {
+                if let Err(e) = setup_file_watcher(app_handle_clone, file_watcher_tx).await {
                     eprintln!("Error setting up file watcher: {}", e);
                 }
-                loop {
-                    match priv_paths_file_change_rx.try_recv() {
-                        Ok(msg) => println!("Received: {:?}", msg),
-                        Err(std::sync::mpsc::TryRecvError::Empty) => {
-                            println!("No more messages yet");
-                            break;
-                        }
-                        Err(std::sync::mpsc::TryRecvError::Disconnected) => {
-                            println!("Channel closed");
-                            break;
-                        }
-                    }
-                }
             });
 
             Ok(())
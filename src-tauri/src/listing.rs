@@ -0,0 +1,486 @@
+//Uses
+use crate::auth::{authorize, Access, Forbidden, SharedPolicy};
+use crate::catalog::{known_hash, remember_hash};
+use crate::types::{
+    DirEntry, DirectoryListing, EntryKind, LinkedPath, ShareTree, SortKey, SortOrder,
+    SymlinkPolicy, TreeEntry,
+};
+use percent_encoding::percent_decode_str;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use warp::filters::path::Tail;
+use warp::filters::BoxedFilter;
+use warp::http::StatusCode;
+use warp::{Filter, Rejection, Reply};
+
+const DEFAULT_PAGE_SIZE: usize = 100;
+const MAX_PAGE_SIZE: usize = 1000;
+
+#[derive(Deserialize)]
+struct TreeQuery {
+    // `sha256` adds content hashes, slow the first time on large shares
+    hash: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ListQuery {
+    #[serde(default)]
+    offset: usize,
+    limit: Option<usize>,
+    #[serde(default)]
+    sort: SortKey,
+    #[serde(default)]
+    order: SortOrder,
+}
+
+pub type ShareMap = Arc<HashMap<String, LinkedPath>>;
+
+// Where resumable uploads keep their partial files, inside each share. Never
+// served or writable, whatever the share's hidden file setting.
+pub const STAGING_DIR: &str = ".quartz-uploads";
+// Two-way sync's file index and trash, kept out of reach the same way
+pub const SYNC_DIR: &str = ".quartz-sync";
+
+// Folders Quartz keeps inside shares for itself
+pub fn is_reserved_name(name: &str) -> bool {
+    name == STAGING_DIR || name == SYNC_DIR
+}
+
+pub fn share_map(linked_paths: Vec<LinkedPath>) -> ShareMap {
+    Arc::new(
+        linked_paths
+            .into_iter()
+            .map(|linked_path| (linked_path.name.clone(), linked_path))
+            .collect(),
+    )
+}
+
+// Matches `/<share>/<path>`, authenticates the request and yields the share
+// with the decoded path below it
+pub fn share_path(
+    shares: ShareMap,
+    access: SharedPolicy,
+) -> impl Filter<Extract = (LinkedPath, String), Error = Rejection> + Clone {
+    authorized_share_path(shares, access)
+        .map(|_: Access, share, relative| (share, relative))
+        .untuple_one()
+}
+
+// `share_path` that also yields who the request authenticated as
+pub fn authorized_share_path(
+    shares: ShareMap,
+    access: SharedPolicy,
+) -> impl Filter<Extract = (Access, LinkedPath, String), Error = Rejection> + Clone {
+    warp::path::param::<String>()
+        .and(warp::path::tail())
+        .and(authorize(access))
+        .and_then(move |share: String, tail: Tail, access: Access| {
+            let shares = shares.clone();
+            async move {
+                let share = decode_path(&share).ok_or_else(warp::reject::not_found)?;
+                if !access.allows(&share) {
+                    return Err(warp::reject::custom(Forbidden));
+                }
+                let linked_path = shares
+                    .get(&share)
+                    .cloned()
+                    .ok_or_else(warp::reject::not_found)?;
+                let relative = decode_path(tail.as_str()).ok_or_else(warp::reject::not_found)?;
+                Ok::<_, Rejection>((access, linked_path, relative))
+            }
+        })
+        .untuple_one()
+}
+
+// Maps a '/' separated path below a share to the host path. Returns None
+// for anything but plain names (`..`, `.`, drive prefixes), for paths that
+// resolve outside the share root through symlinks and for whatever the
+// share's hidden file and symlink settings keep out.
+pub fn resolve_share_path(share: &LinkedPath, relative: &str) -> Option<PathBuf> {
+    let root = fs::canonicalize(&share.path).ok()?;
+    let mut path = root.clone();
+    for segment in relative.split('/').filter(|segment| !segment.is_empty()) {
+        if !is_plain_name(segment) || is_reserved_name(segment) {
+            return None;
+        }
+        path.push(segment);
+        let metadata = fs::symlink_metadata(&path).ok()?;
+        if !share.show_hidden && is_hidden(segment, &metadata) {
+            return None;
+        }
+        if metadata.file_type().is_symlink() && share.symlinks == SymlinkPolicy::Never {
+            return None;
+        }
+    }
+    let resolved = fs::canonicalize(&path).ok()?;
+    within_share(share, &root, &resolved).then_some(resolved)
+}
+
+// A single file or folder name, no `..`, `.`, separators or drive prefixes
+pub fn is_plain_name(segment: &str) -> bool {
+    let mut components = Path::new(segment).components();
+    matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(_)), None)
+    )
+}
+
+// Resolves an entry found while reading a directory of the share, following
+// symlinks the share allows. None when the entry shouldn't be shown.
+pub fn entry_metadata(
+    share: &LinkedPath,
+    root: &Path,
+    path: &Path,
+    name: &str,
+) -> Option<(PathBuf, fs::Metadata)> {
+    if is_reserved_name(name) {
+        return None;
+    }
+    let metadata = fs::symlink_metadata(path).ok()?;
+    if !share.show_hidden && is_hidden(name, &metadata) {
+        return None;
+    }
+    if !metadata.file_type().is_symlink() {
+        return Some((path.to_path_buf(), metadata));
+    }
+    if share.symlinks == SymlinkPolicy::Never {
+        return None;
+    }
+    let target = fs::canonicalize(path).ok()?;
+    if !within_share(share, root, &target) {
+        return None;
+    }
+    let metadata = fs::metadata(&target).ok()?;
+    Some((target, metadata))
+}
+
+// A link inside the share may still lead into a hidden directory
+fn within_share(share: &LinkedPath, root: &Path, resolved: &Path) -> bool {
+    match resolved.strip_prefix(root) {
+        Ok(relative) => !relative.components().any(|component| {
+            let name = component.as_os_str().to_string_lossy();
+            is_reserved_name(&name) || (!share.show_hidden && name.starts_with('.'))
+        }),
+        Err(_) => false,
+    }
+}
+
+fn is_hidden(name: &str, metadata: &fs::Metadata) -> bool {
+    name.starts_with('.') || has_hidden_attribute(metadata)
+}
+
+#[cfg(windows)]
+fn has_hidden_attribute(metadata: &fs::Metadata) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+    metadata.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0
+}
+
+#[cfg(not(windows))]
+fn has_hidden_attribute(_metadata: &fs::Metadata) -> bool {
+    false
+}
+
+// A file or directory met while walking a share
+pub struct WalkEntry {
+    // '/' separated, relative to the directory the walk started at
+    pub relative: String,
+    pub path: PathBuf,
+    pub metadata: fs::Metadata,
+}
+
+// Visits everything below `dir` (a resolved path inside the share) depth
+// first, directories before their contents, names in order. Links back to a
+// directory that is still being walked are skipped so cycles end. Unreadable
+// entries are skipped, errors returned by `visit` stop the walk.
+pub fn walk_share<F>(share: &LinkedPath, dir: &Path, visit: &mut F) -> io::Result<()>
+where
+    F: FnMut(WalkEntry) -> io::Result<()>,
+{
+    let root = fs::canonicalize(&share.path)?;
+    let mut ancestors = HashSet::new();
+    walk_dir(share, &root, dir, "", &mut ancestors, visit)
+}
+
+fn walk_dir<F>(
+    share: &LinkedPath,
+    root: &Path,
+    dir: &Path,
+    prefix: &str,
+    ancestors: &mut HashSet<PathBuf>,
+    visit: &mut F,
+) -> io::Result<()>
+where
+    F: FnMut(WalkEntry) -> io::Result<()>,
+{
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Ok(());
+    };
+    let mut entries: Vec<fs::DirEntry> = entries.flatten().collect();
+    entries.sort_by_key(|entry| entry.file_name());
+
+    ancestors.insert(dir.to_path_buf());
+    for entry in entries {
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        let Some((path, metadata)) = entry_metadata(share, root, &entry.path(), &name) else {
+            continue;
+        };
+        let relative = if prefix.is_empty() {
+            name
+        } else {
+            format!("{}/{}", prefix, name)
+        };
+        if metadata.is_dir() {
+            if ancestors.contains(&path) {
+                continue;
+            }
+            visit(WalkEntry {
+                relative: relative.clone(),
+                path: path.clone(),
+                metadata,
+            })?;
+            walk_dir(share, root, &path, &relative, ancestors, visit)?;
+        } else if metadata.is_file() {
+            visit(WalkEntry {
+                relative,
+                path,
+                metadata,
+            })?;
+        }
+    }
+    ancestors.remove(dir);
+    Ok(())
+}
+
+// Percent-decodes a path taken from the request URI
+pub fn decode_path(raw: &str) -> Option<String> {
+    percent_decode_str(raw)
+        .decode_utf8()
+        .ok()
+        .map(|path| path.into_owned())
+}
+
+// `GET /api/v1/list/<share>/<path>?offset=&limit=&sort=&order=`
+pub fn listing_route(
+    shares: ShareMap,
+    access: SharedPolicy,
+) -> BoxedFilter<(warp::reply::Response,)> {
+    warp::get()
+        .and(warp::path!("api" / "v1" / "list" / ..))
+        .and(share_path(shares, access))
+        .and(warp::query::<ListQuery>())
+        .and_then(
+            |share: LinkedPath, relative: String, query: ListQuery| async move {
+                let listing = tauri::async_runtime::spawn_blocking(move || {
+                    list_directory(&share, &relative, &query)
+                })
+                .await
+                .ok()
+                .flatten();
+                match listing {
+                    Some(listing) => Ok(warp::reply::json(&listing).into_response()),
+                    None => Err::<_, Rejection>(warp::reject::not_found()),
+                }
+            },
+        )
+        .boxed()
+}
+
+// `GET /api/v1/tree/<share>/<path>` with every file and folder below a
+// directory in one response, for clients that mirror a share.
+// `?hash=sha256` adds the content hash of each file.
+pub fn tree_route(shares: ShareMap, access: SharedPolicy) -> BoxedFilter<(warp::reply::Response,)> {
+    warp::get()
+        .and(warp::path!("api" / "v1" / "tree" / ..))
+        .and(share_path(shares, access))
+        .and(warp::query::<TreeQuery>())
+        .and_then(
+            |share: LinkedPath, relative: String, query: TreeQuery| async move {
+                let with_hashes = match query.hash.as_deref() {
+                    None => false,
+                    Some("sha256") => true,
+                    // Answered here, a rejection would lose to the routes behind
+                    Some(_) => {
+                        let reply = warp::reply::with_status(
+                            "Unsupported hash, use sha256",
+                            StatusCode::BAD_REQUEST,
+                        );
+                        return Ok(reply.into_response());
+                    }
+                };
+                let tree = tauri::async_runtime::spawn_blocking(move || {
+                    share_tree(&share, &relative, with_hashes)
+                })
+                .await
+                .ok()
+                .flatten();
+                match tree {
+                    Some(tree) => Ok(warp::reply::json(&tree).into_response()),
+                    None => Err::<_, Rejection>(warp::reject::not_found()),
+                }
+            },
+        )
+        .boxed()
+}
+
+fn share_tree(share: &LinkedPath, relative: &str, with_hashes: bool) -> Option<ShareTree> {
+    let dir = resolve_share_path(share, relative).filter(|dir| dir.is_dir())?;
+    let mut entries = Vec::new();
+    walk_share(share, &dir, &mut |entry: WalkEntry| {
+        let is_dir = entry.metadata.is_dir();
+        // Files that can't be read are listed without a hash
+        let sha256 = (with_hashes && !is_dir)
+            .then(|| file_sha256(&entry.path, &entry.metadata).ok())
+            .flatten();
+        entries.push(TreeEntry {
+            path: entry.relative,
+            kind: if is_dir {
+                EntryKind::Directory
+            } else {
+                EntryKind::File
+            },
+            size: if is_dir { 0 } else { entry.metadata.len() },
+            modified: modified_secs(&entry.metadata),
+            sha256,
+        });
+        Ok(())
+    })
+    .ok()?;
+
+    Some(ShareTree {
+        share: share.name.clone(),
+        path: normalize_path(relative),
+        entries,
+    })
+}
+
+// Hex SHA-256 of a file, from the catalogs if they hashed it as it is now.
+// Files hashed here are kept in the catalogs too.
+pub fn file_sha256(path: &Path, metadata: &fs::Metadata) -> io::Result<String> {
+    if let Some(hash) = known_hash(path, metadata) {
+        return Ok(hash);
+    }
+
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    let hash = format!("{:x}", hasher.finalize());
+    remember_hash(path, metadata, &hash);
+    Ok(hash)
+}
+
+fn list_directory(
+    share: &LinkedPath,
+    relative: &str,
+    query: &ListQuery,
+) -> Option<DirectoryListing> {
+    let entries = read_directory(share, relative, query.sort, query.order)?;
+    let total = entries.len();
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE);
+    let entries = entries.into_iter().skip(query.offset).take(limit).collect();
+
+    Some(DirectoryListing {
+        share: share.name.clone(),
+        path: normalize_path(relative),
+        total,
+        offset: query.offset,
+        entries,
+    })
+}
+
+// All entries of a directory below a share, None if it doesn't resolve to a
+// directory inside the share
+pub fn read_directory(
+    share: &LinkedPath,
+    relative: &str,
+    sort: SortKey,
+    order: SortOrder,
+) -> Option<Vec<DirEntry>> {
+    let dir = resolve_share_path(share, relative)?;
+    let root = fs::canonicalize(&share.path).ok()?;
+    let mut entries: Vec<DirEntry> = fs::read_dir(&dir)
+        .ok()?
+        .flatten()
+        .filter_map(|entry| read_entry(&entry, share, &root))
+        .collect();
+    sort_entries(&mut entries, sort, order);
+    Some(entries)
+}
+
+// Drops empty segments, "a//b/" becomes "a/b"
+pub fn normalize_path(relative: &str) -> String {
+    relative
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// Skips entries that can't be described (non UTF-8 names, special files)
+// and those the share keeps out
+fn read_entry(entry: &fs::DirEntry, share: &LinkedPath, root: &Path) -> Option<DirEntry> {
+    let name = entry.file_name().into_string().ok()?;
+    let (_, metadata) = entry_metadata(share, root, &entry.path(), &name)?;
+
+    let kind = if metadata.is_dir() {
+        EntryKind::Directory
+    } else if metadata.is_file() {
+        EntryKind::File
+    } else {
+        return None;
+    };
+    let modified = modified_secs(&metadata);
+    let mime = (kind == EntryKind::File).then(|| {
+        mime_guess::from_path(&name)
+            .first_or_octet_stream()
+            .to_string()
+    });
+
+    Some(DirEntry {
+        size: if kind == EntryKind::File {
+            metadata.len()
+        } else {
+            0
+        },
+        name,
+        kind,
+        modified,
+        mime,
+    })
+}
+
+pub fn modified_secs(metadata: &fs::Metadata) -> Option<u64> {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs())
+}
+
+// Directories always come first, the order only applies within each group
+fn sort_entries(entries: &mut [DirEntry], sort: SortKey, order: SortOrder) {
+    entries.sort_by(|a, b| {
+        let by_key = match sort {
+            SortKey::Name => Ordering::Equal,
+            SortKey::Size => a.size.cmp(&b.size),
+            SortKey::Modified => a.modified.cmp(&b.modified),
+            SortKey::Type => a.mime.cmp(&b.mime),
+        }
+        .then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+        let by_key = match order {
+            SortOrder::Asc => by_key,
+            SortOrder::Desc => by_key.reverse(),
+        };
+        (b.kind == EntryKind::Directory)
+            .cmp(&(a.kind == EntryKind::Directory))
+            .then(by_key)
+    });
+}
@@ -1,13 +1,20 @@
 //Uses
-use crate::types::{Error, FileError, LinkedPath, Network};
+use crate::auth::{generate_grant_id, generate_token, hash_password, unix_time, update_policy};
+use crate::catalog::linked_paths_changed;
+use crate::config::{config_dir, private_config_path, ConfigStore};
+use crate::health::{overlaps, Overlap};
+use crate::listing::is_reserved_name;
+use crate::tls::load_provided;
+use crate::tor::DEFAULT_CONTROL_PORT;
+use crate::types::{
+    AccessGrant, BindAddress, Error, FileError, FsChangeEvent, FsChangeKind, GrantSecret,
+    LinkedPath, Network, NetworkInterface, OverlapPolicy, SymlinkPolicy, TlsSetting,
+};
+use notify::event::{EventKind, ModifyKind, RenameMode};
 use notify::RecommendedWatcher;
 use notify::Watcher;
 use notify_debouncer_full::{new_debouncer, Debouncer, FileIdMap};
-use serde_json::Value;
 use std::collections::HashSet;
-use std::fs;
-use std::fs::OpenOptions;
-use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex as StdMutex};
 use tauri::{AppHandle, Emitter};
@@ -17,52 +24,37 @@ use tokio::sync::oneshot;
 use tokio::sync::{broadcast, Mutex};
 use tokio::time::Duration;
 
-pub const PRIVATE_CONFIG_FILE_PATH: &str = "../configs/private_config.json";
-pub const PUBLIC_LINKEDPATH_FILE_PATH: &str = "../configs/public_paths.json";
+pub const DEFAULT_PORT: u16 = 3030;
+// Changes a slow subscriber may fall behind by before it misses some
+pub const FS_CHANGE_CAPACITY: usize = 1024;
+
+// Carries every change inside linked paths. Managed as app state, other
+// subsystems subscribe to it.
+pub type FsChangeSender = broadcast::Sender<FsChangeEvent>;
 
 // Global variable to keep track of watched paths
 lazy_static::lazy_static! {
     static ref WATCHED_LINKEDPATHS: Mutex<HashSet<LinkedPath>> = Mutex::new(HashSet::new());
+    // Directories the watcher watches recursively for them
+    static ref WATCHED_DIRS: Mutex<HashSet<PathBuf>> = Mutex::new(HashSet::new());
 }
 
-pub fn read_private_config() -> Result<Value, FileError> {
-    let data = fs::read_to_string(PRIVATE_CONFIG_FILE_PATH).unwrap();
-    let config_contents: Value = serde_json::from_str(&data)?;
-
-    Ok(config_contents)
-}
 pub fn read_private_linked_paths() -> Result<Vec<LinkedPath>, FileError> {
-    let config_contents = read_private_config().unwrap();
-    if let Some(linked_paths_value) = config_contents.get("linked_paths") {
-        let linked_paths: Vec<LinkedPath> = serde_json::from_value(linked_paths_value.clone())
-            .expect("Failed to deserialize linked_paths");
-        Ok(linked_paths)
-    } else {
-        Err(FileError::MissingLinkedPathsError)
-    }
+    let store = ConfigStore::load()?;
+    Ok(store.linked_paths().to_vec())
 }
 #[tauri::command]
 pub fn read_private_networks() -> Result<Vec<Network>, FileError> {
-    let config_contents = read_private_config().unwrap();
-    if let Some(networks_value) = config_contents.get("networks") {
-        let networks: Vec<Network> =
-            serde_json::from_value(networks_value.clone()).expect("Failed to deserialize networks");
-        Ok(networks)
-    } else {
-        Err(FileError::MissingLinkedPathsError)
-    }
+    let store = ConfigStore::load()?;
+    Ok(store.networks().to_vec())
 }
 
-fn write_json_to_file(json_value: &Value) -> Result<(), FileError> {
-    let mut file = OpenOptions::new()
-        .write(true)
-        .truncate(true) // Truncate the file to overwrite it
-        .open(PRIVATE_CONFIG_FILE_PATH)?;
-
-    let updated_json_str = serde_json::to_string_pretty(json_value)?;
-    file.write_all(updated_json_str.as_bytes())?;
-
-    Ok(())
+// Revision the frontend passes back to mutating commands so they fail with a
+// ConflictError instead of overwriting changes made elsewhere
+#[tauri::command]
+pub fn get_config_revision() -> Result<u64, FileError> {
+    let store = ConfigStore::load()?;
+    Ok(store.revision())
 }
 
 #[tauri::command]
@@ -86,67 +78,173 @@ pub async fn select_directory(app: AppHandle) -> Result<Option<PathBuf>, Error>
     Ok(selected_dir)
 }
 
+// Links a directory under `name`. Directories that are, contain or sit
+// inside an already linked one are handled according to `overlap`.
 #[tauri::command]
-pub fn link_directory(app: AppHandle, path: String, name: String) -> Result<String, FileError> {
-    if name == "" {
+pub fn link_directory(
+    app: AppHandle,
+    path: String,
+    name: String,
+    overlap: Option<OverlapPolicy>,
+    revision: u64,
+) -> Result<String, FileError> {
+    if name.is_empty() {
         return Ok("Name your linked path".to_string());
     };
-    if path == "" {
+    if path.is_empty() {
         return Ok("Directory not selected".to_string());
     };
+    // Only compared against other linked paths, the path is stored as given
+    // since canonical Windows paths carry a `\\?\` prefix
+    let Ok(dir) = std::fs::canonicalize(&path) else {
+        return Ok("Directory not found".to_string());
+    };
 
-    let mut json_value = read_private_config()?;
-
-    // Modify the `linked_paths` field without altering other fields
-    if let Some(linked_paths_value) = json_value.get_mut("linked_paths") {
-        // Deserialize `linked_paths` into a Vec<LinkedPath>
-        let mut linked_paths: Vec<LinkedPath> = serde_json::from_value(linked_paths_value.clone())?;
-        if linked_paths.iter().any(|x| x.name == name) {
-            return Ok("Linked path with this name already exists".to_string());
-        };
-        let new_linked_path = LinkedPath {
-            name: name,
-            path: PathBuf::from(path),
-        };
-        // Add the new path
-        linked_paths.push(new_linked_path);
+    let new_linked_path = LinkedPath {
+        name,
+        path: PathBuf::from(path),
+        show_hidden: false,
+        symlinks: SymlinkPolicy::default(),
+        writable: false,
+        quota_bytes: None,
+        full_text: false,
+    };
+    let policy = overlap.unwrap_or_default();
+
+    // Checked under the config lock, so two directories linked at the same
+    // time can't both pass as not overlapping
+    let result = ConfigStore::update(Some(revision), |store| {
+        let overlaps = overlaps(store.linked_paths(), &dir);
+        let described: Vec<String> = overlaps
+            .iter()
+            .map(|(name, overlap)| match overlap {
+                Overlap::Same => format!("'{}' is the same directory", name),
+                Overlap::Inside(relative) => format!("it is inside '{}' at {}", name, relative),
+                Overlap::Around(relative) => format!("'{}' is inside it at {}", name, relative),
+            })
+            .collect();
+        if !overlaps.is_empty() && policy == OverlapPolicy::Reject {
+            return Err(FileError::OverlapError(format!(
+                "Directory overlaps linked paths: {}",
+                described.join(", ")
+            )));
+        }
+        // Already shared through the linked path around it
+        if let Some((outer, _)) = overlaps
+            .iter()
+            .find(|(_, overlap)| !matches!(overlap, Overlap::Around(_)))
+            .filter(|_| policy == OverlapPolicy::Merge)
+        {
+            return Err(FileError::OverlapError(format!(
+                "Directory is already linked through '{}'",
+                outer
+            )));
+        }
 
-        // Serialize the updated `linked_paths` back into the JSON value
-        *linked_paths_value = serde_json::to_value(&linked_paths)?;
-    } else {
-        return Err(FileError::MissingLinkedPathsError);
-    }
+        if overlaps.is_empty() {
+            store.add_linked_path(new_linked_path)?;
+            return Ok("Directory linked successfully".to_string());
+        }
+        if policy == OverlapPolicy::Warn {
+            store.add_linked_path(new_linked_path)?;
+            return Ok(format!("Directory linked, but {}", described.join(", ")));
+        }
 
-    // Write the updated JSON back to the file
-    write_json_to_file(&json_value)?;
+        // Merging, the linked paths inside it go unless something uses them
+        let (in_use, unused): (Vec<String>, Vec<String>) = overlaps
+            .into_iter()
+            .map(|(name, _)| name)
+            .partition(|name| store.linked_path_in_use(name));
+        for name in &unused {
+            store.remove_linked_path(name);
+        }
+        store.add_linked_path(new_linked_path)?;
+        let mut message = "Directory linked".to_string();
+        if !unused.is_empty() {
+            message += &format!(", replacing '{}'", unused.join("', '"));
+        }
+        if !in_use.is_empty() {
+            message += &format!(
+                ", '{}' still inside it since networks, mirrors or sync folders use them",
+                in_use.join("', '")
+            );
+        }
+        Ok(message)
+    });
+    let message = match result {
+        Ok(message) => message,
+        Err(FileError::DuplicateNameError(_)) => {
+            return Ok("Linked path with this name already exists".to_string())
+        }
+        Err(FileError::OverlapError(message)) => return Ok(message),
+        Err(e) => return Err(e),
+    };
+    linked_paths_changed();
 
     if let Err(e) = app.emit("linked_paths_changed", ()) {
         eprintln!("Failed to emit event to frontend: {}", e);
     }
 
-    Ok("Directory linked successfully".to_string())
+    Ok(message)
 }
 
+// Which hidden files and symlinks a linked path serves, in every network
+// sharing it. Takes effect the next time those networks' servers start.
 #[tauri::command]
-pub fn unlink_directory(app: AppHandle, path_name: String) -> Result<String, FileError> {
-    let mut json_value = read_private_config()?;
+pub fn set_share_policy(
+    app: AppHandle,
+    path_name: String,
+    show_hidden: bool,
+    symlinks: SymlinkPolicy,
+    revision: u64,
+) -> Result<String, FileError> {
+    ConfigStore::update(Some(revision), |store| {
+        store.update_linked_path(&path_name, |linked_path| {
+            linked_path.show_hidden = show_hidden;
+            linked_path.symlinks = symlinks;
+        })
+    })?;
 
-    // Modify the `linked_paths` field without altering other fields
-    if let Some(linked_paths_value) = json_value.get_mut("linked_paths") {
-        // Deserialize `linked_paths` into a Vec<LinkedPath>
-        let mut linked_paths: Vec<LinkedPath> = serde_json::from_value(linked_paths_value.clone())?;
+    if let Err(e) = app.emit("linked_paths_changed", ()) {
+        eprintln!("Failed to emit event to frontend: {}", e);
+    }
+    Ok("Share policy updated".to_string())
+}
 
-        // Filter out the target item based on `name`
-        linked_paths.retain(|path| path.name != path_name);
+// Lets clients upload, create folders, rename and delete in a linked path,
+// up to `quota_bytes` in total. Takes effect the next time the servers of
+// networks sharing it start.
+#[tauri::command]
+pub fn set_share_write_access(
+    app: AppHandle,
+    path_name: String,
+    writable: bool,
+    quota_bytes: Option<u64>,
+    revision: u64,
+) -> Result<String, FileError> {
+    ConfigStore::update(Some(revision), |store| {
+        store.update_linked_path(&path_name, |linked_path| {
+            linked_path.writable = writable;
+            linked_path.quota_bytes = quota_bytes;
+        })
+    })?;
 
-        // Serialize the updated `linked_paths` back into the JSON value
-        *linked_paths_value = serde_json::to_value(&linked_paths)?;
-    } else {
-        return Err(FileError::MissingLinkedPathsError);
+    if let Err(e) = app.emit("linked_paths_changed", ()) {
+        eprintln!("Failed to emit event to frontend: {}", e);
     }
+    Ok("Write access updated".to_string())
+}
 
-    // Write the updated JSON back to the file
-    write_json_to_file(&json_value)?;
+#[tauri::command]
+pub fn unlink_directory(
+    app: AppHandle,
+    path_name: String,
+    revision: u64,
+) -> Result<String, FileError> {
+    ConfigStore::update(Some(revision), |store| {
+        Ok(store.remove_linked_path(&path_name))
+    })?;
+    linked_paths_changed();
 
     if let Err(e) = app.emit("linked_paths_changed", ()) {
         eprintln!("Failed to emit event to frontend: {}", e);
@@ -159,32 +257,85 @@ pub fn create_local_network(
     app: AppHandle,
     name: String,
     linked_paths: Vec<LinkedPath>,
+    port: Option<u16>,
+    bind_address: Option<BindAddress>,
+    tls: Option<TlsSetting>,
+    revision: u64,
 ) -> Result<String, FileError> {
-    if name == "" {
+    if name.is_empty() {
         return Ok("Name your network".to_string());
     };
     if linked_paths.is_empty() {
         return Ok("Paths not selected".to_string());
     };
+    let bind_address = bind_address.unwrap_or_default();
+    if let BindAddress::Interface { ip } = &bind_address {
+        if !list_network_interfaces()?.iter().any(|x| &x.ip == ip) {
+            return Ok("Selected interface is not available".to_string());
+        }
+    }
+    let tls = tls.unwrap_or_default();
+    if let Some(message) = check_tls_files(&tls) {
+        return Ok(message);
+    }
 
-    let mut json_value = read_private_config()?;
+    let new_network = Network::LocalNetwork {
+        name,
+        linked_paths,
+        port: port.unwrap_or(DEFAULT_PORT),
+        bind_address,
+        tls,
+        access_grants: Vec::new(),
+        directory_index: true,
+    };
+    match ConfigStore::update(Some(revision), |store| store.add_network(new_network)) {
+        Ok(()) => {}
+        Err(FileError::DuplicateNameError(_)) => {
+            return Ok("Network with this name already exists".to_string())
+        }
+        Err(e) => return Err(e),
+    }
 
-    // Modify the `networks` field without altering other fields
-    if let Some(networks_value) = json_value.get_mut("networks") {
-        let mut networks: Vec<Network> = serde_json::from_value(networks_value.clone())?;
-        let new_network = Network::LocalNetwork {
-            name,
-            linked_paths,
-            port: 3030,
-        };
-        networks.push(new_network);
-        *networks_value = serde_json::to_value(&networks)?;
-    } else {
-        return Err(FileError::MissingLinkedPathsError);
+    if let Err(e) = app.emit("linked_paths_changed", ()) {
+        eprintln!("Failed to emit event to frontend: {}", e);
     }
 
-    // Write the updated JSON back to the file
-    write_json_to_file(&json_value)?;
+    Ok("Network created successfully".to_string())
+}
+
+#[tauri::command]
+pub fn create_internet_network(
+    app: AppHandle,
+    name: String,
+    linked_paths: Vec<LinkedPath>,
+    port: Option<u16>,
+    revision: u64,
+) -> Result<String, FileError> {
+    if name.is_empty() {
+        return Ok("Name your network".to_string());
+    };
+    if linked_paths.is_empty() {
+        return Ok("Paths not selected".to_string());
+    };
+
+    // The address is filled in once the server has mapped its port
+    let new_network = Network::InternetNetwork {
+        name,
+        linked_paths,
+        address: String::new(),
+        port: port.unwrap_or(DEFAULT_PORT),
+        access_token: generate_token(),
+        tls: TlsSetting::SelfSigned,
+        access_grants: Vec::new(),
+        directory_index: true,
+    };
+    match ConfigStore::update(Some(revision), |store| store.add_network(new_network)) {
+        Ok(()) => {}
+        Err(FileError::DuplicateNameError(_)) => {
+            return Ok("Network with this name already exists".to_string())
+        }
+        Err(e) => return Err(e),
+    }
 
     if let Err(e) = app.emit("linked_paths_changed", ()) {
         eprintln!("Failed to emit event to frontend: {}", e);
@@ -194,36 +345,278 @@ pub fn create_local_network(
 }
 
 #[tauri::command]
-pub fn remove_network(app: AppHandle, network_name: String) -> Result<String, String> {
-    let mut json_value = read_private_config().unwrap();
+pub fn create_dark_web_network(
+    app: AppHandle,
+    name: String,
+    linked_paths: Vec<LinkedPath>,
+    tor_control_port: Option<u16>,
+    tor_control_password: Option<String>,
+    revision: u64,
+) -> Result<String, FileError> {
+    if name.is_empty() {
+        return Ok("Name your network".to_string());
+    };
+    if linked_paths.is_empty() {
+        return Ok("Paths not selected".to_string());
+    };
+
+    // The .onion address is filled in once the service is published
+    let new_network = Network::DarkWebNetwork {
+        name,
+        linked_paths,
+        address: String::new(),
+        tor_control_port: tor_control_port.unwrap_or(DEFAULT_CONTROL_PORT),
+        tor_control_password: tor_control_password.filter(|password| !password.is_empty()),
+        access_grants: Vec::new(),
+        directory_index: true,
+    };
+    match ConfigStore::update(Some(revision), |store| store.add_network(new_network)) {
+        Ok(()) => {}
+        Err(FileError::DuplicateNameError(_)) => {
+            return Ok("Network with this name already exists".to_string())
+        }
+        Err(e) => return Err(e),
+    }
+
+    if let Err(e) = app.emit("linked_paths_changed", ()) {
+        eprintln!("Failed to emit event to frontend: {}", e);
+    }
 
-    // Modify the `networks` field without altering other fields
-    if let Some(networks_value) = json_value.get_mut("networks") {
-        let mut networks: Vec<Network> = serde_json::from_value(networks_value.clone()).unwrap();
+    Ok("Network created successfully".to_string())
+}
 
-        networks.retain(|network| match network {
-            Network::LocalNetwork { name, .. } => name != network_name.as_str(),
-            Network::InternetNetwork { name, .. } => name != network_name.as_str(),
-            Network::DarkWebNetwork { name, .. } => name != network_name.as_str(),
-        });
+// Invalidates the old token, also for a running server
+#[tauri::command]
+pub fn regenerate_access_token(
+    app: AppHandle,
+    network_name: String,
+    revision: u64,
+) -> Result<String, FileError> {
+    let token = generate_token();
+    ConfigStore::update(Some(revision), |store| {
+        store.set_access_token(&network_name, token.clone())
+    })?;
+    refresh_access_policy(&network_name)?;
 
-        *networks_value = serde_json::to_value(&networks).unwrap();
-    } else {
-        return Err("Error".to_string());
+    if let Err(e) = app.emit("linked_paths_changed", ()) {
+        eprintln!("Failed to emit event to frontend: {}", e);
+    }
+
+    Ok(token)
+}
+
+// Takes effect the next time the network's server starts
+#[tauri::command]
+pub fn set_directory_index(
+    app: AppHandle,
+    network_name: String,
+    enabled: bool,
+    revision: u64,
+) -> Result<String, FileError> {
+    ConfigStore::update(Some(revision), |store| {
+        store.set_directory_index(&network_name, enabled)
+    })?;
+
+    if let Err(e) = app.emit("linked_paths_changed", ()) {
+        eprintln!("Failed to emit event to frontend: {}", e);
+    }
+    Ok("Directory index updated".to_string())
+}
+
+// Creates an invite token limited to `allowed_paths` (all shares if None)
+// that stops working after `expires_in_hours`
+#[tauri::command]
+pub fn create_invite(
+    app: AppHandle,
+    network_name: String,
+    label: String,
+    allowed_paths: Option<Vec<String>>,
+    expires_in_hours: Option<u64>,
+    revision: u64,
+) -> Result<AccessGrant, FileError> {
+    let grant = AccessGrant {
+        id: generate_grant_id(),
+        label,
+        secret: GrantSecret::Token {
+            token: generate_token(),
+        },
+        allowed_paths,
+        expires_at: expires_in_hours.map(|hours| unix_time() + hours * 60 * 60),
+        revoked: false,
+    };
+    ConfigStore::update(Some(revision), |store| {
+        store.add_access_grant(&network_name, grant.clone())
+    })?;
+    refresh_access_policy(&network_name)?;
+
+    if let Err(e) = app.emit("linked_paths_changed", ()) {
+        eprintln!("Failed to emit event to frontend: {}", e);
+    }
+
+    Ok(grant)
+}
+
+// Lets browsers in with HTTP Basic credentials
+#[tauri::command]
+pub fn add_access_password(
+    app: AppHandle,
+    network_name: String,
+    username: String,
+    password: String,
+    allowed_paths: Option<Vec<String>>,
+    revision: u64,
+) -> Result<String, FileError> {
+    if username.is_empty() || username.contains(':') {
+        return Ok("Choose a username without ':'".to_string());
+    }
+    if password.is_empty() {
+        return Ok("Choose a password".to_string());
+    }
+
+    let grant = AccessGrant {
+        id: generate_grant_id(),
+        label: username.clone(),
+        secret: GrantSecret::Password {
+            username,
+            password_hash: hash_password(&password)?,
+        },
+        allowed_paths,
+        expires_at: None,
+        revoked: false,
+    };
+    match ConfigStore::update(Some(revision), |store| {
+        store.add_access_grant(&network_name, grant)
+    }) {
+        Ok(()) => {}
+        Err(FileError::DuplicateNameError(_)) => {
+            return Ok("User with this name already exists".to_string())
+        }
+        Err(e) => return Err(e),
+    }
+    refresh_access_policy(&network_name)?;
+
+    if let Err(e) = app.emit("linked_paths_changed", ()) {
+        eprintln!("Failed to emit event to frontend: {}", e);
+    }
+
+    Ok("Password added successfully".to_string())
+}
+
+// Takes effect immediately, also for a running server
+#[tauri::command]
+pub fn revoke_access_grant(
+    app: AppHandle,
+    network_name: String,
+    grant_id: String,
+    revision: u64,
+) -> Result<String, FileError> {
+    let revoked = ConfigStore::update(Some(revision), |store| {
+        store.revoke_access_grant(&network_name, &grant_id)
+    })?;
+    if !revoked {
+        return Ok("Access not found".to_string());
+    }
+    refresh_access_policy(&network_name)?;
+
+    if let Err(e) = app.emit("linked_paths_changed", ()) {
+        eprintln!("Failed to emit event to frontend: {}", e);
+    }
+
+    Ok("Access revoked successfully".to_string())
+}
+
+#[tauri::command]
+pub fn list_access_grants(network_name: String) -> Result<Vec<AccessGrant>, FileError> {
+    let store = ConfigStore::load()?;
+    let network = store
+        .networks()
+        .iter()
+        .find(|network| network.name() == network_name)
+        .ok_or(FileError::NotFoundError(network_name))?;
+    Ok(network.access_grants().to_vec())
+}
+
+// Pushes the network's stored grants and token to its running server
+fn refresh_access_policy(network_name: &str) -> Result<(), FileError> {
+    let store = ConfigStore::load()?;
+    if let Some(network) = store
+        .networks()
+        .iter()
+        .find(|network| network.name() == network_name)
+    {
+        update_policy(network);
+    }
+    Ok(())
+}
+
+// Takes effect the next time the network's server starts
+#[tauri::command]
+pub fn set_network_tls(
+    app: AppHandle,
+    network_name: String,
+    tls: TlsSetting,
+    revision: u64,
+) -> Result<String, FileError> {
+    if let Some(message) = check_tls_files(&tls) {
+        return Ok(message);
     }
-    // Write the updated JSON back to the file
-    let _ = write_json_to_file(&json_value);
+    ConfigStore::update(Some(revision), |store| {
+        store.set_network_tls(&network_name, tls)
+    })?;
+
+    if let Err(e) = app.emit("linked_paths_changed", ()) {
+        eprintln!("Failed to emit event to frontend: {}", e);
+    }
+    Ok("TLS settings updated".to_string())
+}
+
+// Reports unreadable or non-PEM certificate files before they are saved
+fn check_tls_files(tls: &TlsSetting) -> Option<String> {
+    match tls {
+        TlsSetting::Provided {
+            cert_path,
+            key_path,
+        } => load_provided(cert_path, key_path)
+            .err()
+            .map(|e| format!("Invalid certificate: {}", e)),
+        _ => None,
+    }
+}
+
+#[tauri::command]
+pub fn remove_network(
+    app: AppHandle,
+    network_name: String,
+    revision: u64,
+) -> Result<String, FileError> {
+    ConfigStore::update(Some(revision), |store| {
+        Ok(store.remove_network(&network_name))
+    })?;
+
     if let Err(e) = app.emit("linked_paths_changed", ()) {
         eprintln!("Failed to emit event to frontend: {}", e);
     }
     Ok("Directory removed successfully".to_string())
 }
 
+// Addresses a local network can be bound to with `BindAddress::Interface`
+#[tauri::command]
+pub fn list_network_interfaces() -> Result<Vec<NetworkInterface>, FileError> {
+    let interfaces = if_addrs::get_if_addrs()?
+        .into_iter()
+        .map(|interface| NetworkInterface {
+            ip: interface.ip(),
+            is_loopback: interface.is_loopback(),
+            name: interface.name,
+        })
+        .collect();
+
+    Ok(interfaces)
+}
+
 #[tauri::command]
 pub fn get_linked_paths() -> Result<Vec<LinkedPath>, FileError> {
-    // Open a file and read it contents
-    let linked_paths = read_private_linked_paths().unwrap();
-    // println!("{}", linked_paths[0].name);
+    let linked_paths = read_private_linked_paths()?;
 
     Ok(linked_paths)
 }
@@ -231,8 +624,7 @@ pub fn get_linked_paths() -> Result<Vec<LinkedPath>, FileError> {
 // Function to set up the file watcher
 pub async fn setup_file_watcher(
     app_handle: AppHandle,
-    tx: broadcast::Sender<LinkedPath>,
-    priv_paths_file_change_tx: std::sync::mpsc::Sender<()>,
+    tx: FsChangeSender,
 ) -> Result<(), Box<dyn std::error::Error>> {
     // Create a channel to receive file events
     let (notify_tx, notify_rx) = std::sync::mpsc::channel();
@@ -242,16 +634,15 @@ pub async fn setup_file_watcher(
     println!("Debouncer initialized");
 
     // Wrap channels in Arc and Mutex for concurrent access
-    let tx = Arc::new(Mutex::new(tx));
     let notify_rx = Arc::new(StdMutex::new(notify_rx));
     let debouncer = Arc::new(StdMutex::new(debouncer));
 
     // Spawn blocking task to handle file events
-    let tx_clone = Arc::clone(&tx);
     let notify_rx_clone = Arc::clone(&notify_rx);
     let debouncer_clone = Arc::clone(&debouncer);
     let app_handle_clone_1 = app_handle.clone();
     let app_handle_clone_2 = app_handle.clone();
+    let private_config_file = private_config_path();
 
     tokio::task::spawn_blocking(move || {
         println!("Starting blocking task to handle file events");
@@ -263,25 +654,31 @@ pub async fn setup_file_watcher(
                 Ok(debounced_events) => {
                     println!("Received debounced events");
                     for debounced_event in debounced_events {
+                        let changes = {
+                            let watched_linked_paths = WATCHED_LINKEDPATHS.blocking_lock();
+                            fs_changes(&debounced_event, &watched_linked_paths)
+                        };
+                        for change in changes {
+                            // Nobody may be subscribed, that's fine
+                            let _ = tx.send(change.clone());
+                            if let Err(e) = app_handle_clone_1.emit("fs_change", &change) {
+                                eprintln!("Failed to emit event to frontend: {}", e);
+                            }
+                        }
+
                         for path in &debounced_event.paths {
                             if let Ok(canonical_path) = path.canonicalize() {
-                                if canonical_path
-                                    == PathBuf::from(PRIVATE_CONFIG_FILE_PATH)
-                                        .canonicalize()
-                                        .unwrap()
+                                if private_config_file
+                                    .canonicalize()
+                                    .is_ok_and(|config_path| config_path == canonical_path)
                                 {
                                     // File was changed, reload linked paths
-                                    let tx_clone = Arc::clone(&tx_clone);
                                     let debouncer_clone = Arc::clone(&debouncer_clone);
                                     println!("private_paths.json changed");
-                                    if let Err(e) = priv_paths_file_change_tx.send(()) {
-                                        eprintln!("Error sending file change signal: {}", e);
-                                    }
                                     tokio::runtime::Runtime::new().unwrap().block_on(async {
                                         if let Err(e) = handle_file_change(
                                             app_handle_clone_1.clone(),
                                             &debouncer_clone,
-                                            &tx_clone,
                                         )
                                         .await
                                         {
@@ -300,18 +697,22 @@ pub async fn setup_file_watcher(
         }
     });
 
-    // Watch the VAULT_PATH initially
-    if let Err(e) = debouncer.lock().unwrap().watcher().watch(
-        Path::new(PRIVATE_CONFIG_FILE_PATH),
-        notify::RecursiveMode::NonRecursive,
-    ) {
-        eprintln!("Failed to watch path {}: {}", PRIVATE_CONFIG_FILE_PATH, e);
+    // Watch the config directory rather than the file itself, since saves
+    // replace the file by renaming a new one over it
+    let config_dir = config_dir();
+    if let Err(e) = debouncer
+        .lock()
+        .unwrap()
+        .watcher()
+        .watch(&config_dir, notify::RecursiveMode::NonRecursive)
+    {
+        eprintln!("Failed to watch path {}: {}", config_dir.display(), e);
     } else {
-        println!("Started watching path: {:?}", PRIVATE_CONFIG_FILE_PATH);
+        println!("Started watching path: {:?}", config_dir);
     }
 
     // Initial load of paths and start watching them
-    if let Err(e) = handle_file_change(app_handle_clone_2.clone(), &debouncer, &tx).await {
+    if let Err(e) = handle_file_change(app_handle_clone_2.clone(), &debouncer).await {
         eprintln!("Error setting up file watcher: {}", e);
     }
 
@@ -322,7 +723,6 @@ pub async fn setup_file_watcher(
 async fn handle_file_change(
     app_handle: AppHandle,
     debouncer: &Arc<StdMutex<Debouncer<RecommendedWatcher, FileIdMap>>>,
-    tx: &Arc<Mutex<broadcast::Sender<LinkedPath>>>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let linked_paths = read_private_linked_paths()?;
     let new_paths: HashSet<LinkedPath> = linked_paths.into_iter().collect();
@@ -330,57 +730,38 @@ async fn handle_file_change(
     // Retrieve currently watched paths
     let mut watched_linked_paths = WATCHED_LINKEDPATHS.lock().await;
 
-    // Identify paths to add and remove
-    let paths_to_add: HashSet<_> = new_paths
-        .difference(&watched_linked_paths)
-        .cloned()
-        .collect();
-    let paths_to_remove: HashSet<_> = watched_linked_paths
-        .difference(&new_paths)
-        .cloned()
-        .collect();
-
-    // Add new paths to the watcher
-    for linked_path in &paths_to_add {
-        if let Err(e) = debouncer
-            .lock()
-            .unwrap()
-            .watcher()
-            .watch(&linked_path.path, notify::RecursiveMode::Recursive)
-        {
-            eprintln!("Failed to watch path {}: {}", linked_path.path.display(), e);
+    // Identify directories to add and remove
+    let new_dirs = watch_dirs(&new_paths);
+    let mut watched_dirs = WATCHED_DIRS.lock().await;
+    let dirs_to_add: HashSet<_> = new_dirs.difference(&watched_dirs).cloned().collect();
+    let dirs_to_remove: HashSet<_> = watched_dirs.difference(&new_dirs).cloned().collect();
+
+    // Remove directories from the watcher first, a linked path around them
+    // may be about to take over
+    for dir in &dirs_to_remove {
+        if let Err(e) = debouncer.lock().unwrap().watcher().unwatch(dir) {
+            eprintln!("Failed to unwatch path {}: {}", dir.display(), e);
         } else {
-            println!("Started watching path: {:?}", linked_path.path);
+            println!("Stopped watching path: {:?}", dir);
         }
-        watched_linked_paths.insert(linked_path.clone());
+        watched_dirs.remove(dir);
     }
 
-    // Remove paths from the watcher
-    for linked_path in &paths_to_remove {
+    // Add new directories to the watcher
+    for dir in &dirs_to_add {
         if let Err(e) = debouncer
             .lock()
             .unwrap()
             .watcher()
-            .unwatch(&linked_path.path)
+            .watch(dir, notify::RecursiveMode::Recursive)
         {
-            eprintln!(
-                "Failed to unwatch path {}: {}",
-                linked_path.path.display(),
-                e
-            );
+            eprintln!("Failed to watch path {}: {}", dir.display(), e);
         } else {
-            println!("Stopped watching path: {:?}", linked_path.path);
-        }
-        watched_linked_paths.remove(linked_path);
-    }
-
-    // Notify about the changes
-    for linked_path in &paths_to_add {
-        let tx = tx.lock().await;
-        if let Err(e) = tx.send(linked_path.clone()) {
-            eprintln!("Failed to send file change event: {}", e);
+            println!("Started watching path: {:?}", dir);
         }
+        watched_dirs.insert(dir.clone());
     }
+    *watched_linked_paths = new_paths;
 
     // Emit event with updated paths
     if let Err(e) = app_handle.emit("linked_paths_changed", ()) {
@@ -391,3 +772,71 @@ async fn handle_file_change(
 
     Ok(())
 }
+
+// Directories to watch recursively for `linked_paths`. Linked paths inside
+// another one are covered by its watch, watching them as well would report
+// each change in them twice.
+fn watch_dirs(linked_paths: &HashSet<LinkedPath>) -> HashSet<PathBuf> {
+    let paths: HashSet<&Path> = linked_paths
+        .iter()
+        .map(|linked_path| linked_path.path.as_path())
+        .collect();
+    paths
+        .iter()
+        .filter(|path| {
+            !paths
+                .iter()
+                .any(|other| other != *path && path.starts_with(other))
+        })
+        .map(|path| path.to_path_buf())
+        .collect()
+}
+
+// The changes a debounced event makes inside the watched linked paths. A
+// rename is reported as the old path's removal and the new path's creation.
+// Quartz's own staging and sync folders are left out.
+fn fs_changes(event: &notify::Event, linked_paths: &HashSet<LinkedPath>) -> Vec<FsChangeEvent> {
+    let mut changes = Vec::new();
+    for (index, path) in event.paths.iter().enumerate() {
+        let Some(kind) = change_kind(&event.kind, index, path) else {
+            continue;
+        };
+        for linked_path in linked_paths {
+            let Ok(relative) = path.strip_prefix(&linked_path.path) else {
+                continue;
+            };
+            let segments: Vec<String> = relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            if segments.iter().any(|segment| is_reserved_name(segment)) {
+                continue;
+            }
+            changes.push(FsChangeEvent {
+                linked_path: linked_path.name.clone(),
+                relative_path: segments.join("/"),
+                kind,
+            });
+        }
+    }
+    changes
+}
+
+// `index` is the path's position in the event, renames list the old path first
+fn change_kind(kind: &EventKind, index: usize, path: &Path) -> Option<FsChangeKind> {
+    match kind {
+        EventKind::Access(_) => None,
+        EventKind::Create(_) => Some(FsChangeKind::Created),
+        EventKind::Remove(_) => Some(FsChangeKind::Removed),
+        EventKind::Modify(ModifyKind::Name(RenameMode::From)) => Some(FsChangeKind::Removed),
+        EventKind::Modify(ModifyKind::Name(RenameMode::To)) => Some(FsChangeKind::Created),
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if index == 0 => {
+            Some(FsChangeKind::Removed)
+        }
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => Some(FsChangeKind::Created),
+        // Platforms that don't tell which side of a rename this is
+        EventKind::Modify(ModifyKind::Name(_)) if path.exists() => Some(FsChangeKind::Created),
+        EventKind::Modify(ModifyKind::Name(_)) => Some(FsChangeKind::Removed),
+        EventKind::Modify(_) | EventKind::Any | EventKind::Other => Some(FsChangeKind::Modified),
+    }
+}
@@ -0,0 +1,187 @@
+//Uses
+use crate::auth::{authorize, Access, SharedPolicy};
+use crate::listing::{resolve_share_path, walk_share};
+use crate::types::{LinkedPath, NetworkManifest, ShareCapability, ShareSummary};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::time::{Duration, Instant};
+use warp::filters::BoxedFilter;
+use warp::{Filter, Reply};
+
+pub const MANIFEST_VERSION: u32 = 1;
+// Walking large shares is slow, so summaries are reused for a while
+const SUMMARY_TTL: Duration = Duration::from_secs(60);
+
+// Hex of the first 8 bytes of SHA-256 over the share name
+pub fn share_id(name: &str) -> String {
+    Sha256::digest(name.as_bytes())[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+struct ShareSummaries {
+    linked_paths: Vec<LinkedPath>,
+    cache: Mutex<Option<(Instant, Vec<ShareSummary>)>>,
+}
+
+impl ShareSummaries {
+    async fn get(&self) -> Vec<ShareSummary> {
+        // Held while walking so concurrent requests wait for one walk
+        let mut cache = self.cache.lock().await;
+        if let Some((created, summaries)) = cache.as_ref() {
+            if created.elapsed() < SUMMARY_TTL {
+                return summaries.clone();
+            }
+        }
+
+        let linked_paths = self.linked_paths.clone();
+        let summaries = tauri::async_runtime::spawn_blocking(move || {
+            linked_paths.iter().map(summarize).collect::<Vec<_>>()
+        })
+        .await
+        .unwrap_or_default();
+        *cache = Some((Instant::now(), summaries.clone()));
+        summaries
+    }
+}
+
+fn summarize(linked_path: &LinkedPath) -> ShareSummary {
+    let (mut size, mut file_count) = (0, 0);
+    // Counts what clients can actually reach, so hidden files and links the
+    // share doesn't follow are left out
+    if let Some(root) = resolve_share_path(linked_path, "") {
+        let _ = walk_share(linked_path, &root, &mut |entry| {
+            if entry.metadata.is_file() {
+                size += entry.metadata.len();
+                file_count += 1;
+            }
+            Ok(())
+        });
+    }
+    let mut capabilities = vec![
+        ShareCapability::Read,
+        ShareCapability::List,
+        ShareCapability::Archive,
+    ];
+    if linked_path.writable {
+        capabilities.push(ShareCapability::Write);
+    }
+    ShareSummary {
+        id: share_id(&linked_path.name),
+        name: linked_path.name.clone(),
+        size,
+        file_count,
+        capabilities,
+    }
+}
+
+// `GET /` describing the shares the client may read. Browsers get an HTML
+// landing page, anything else JSON; `?format=json|html` overrides.
+pub fn manifest_route(
+    linked_paths: Vec<LinkedPath>,
+    access: SharedPolicy,
+) -> BoxedFilter<(warp::reply::Response,)> {
+    let summaries = Arc::new(ShareSummaries {
+        linked_paths,
+        cache: Mutex::new(None),
+    });
+    warp::get()
+        .and(warp::path::end())
+        .and(authorize(access))
+        .and(warp::header::optional::<String>("accept"))
+        .and(warp::query::<HashMap<String, String>>())
+        .then(
+            move |access: Access, accept: Option<String>, query: HashMap<String, String>| {
+                let summaries = summaries.clone();
+                async move {
+                    let shares = summaries
+                        .get()
+                        .await
+                        .into_iter()
+                        .filter(|share| access.allows(&share.name))
+                        .collect();
+                    let manifest = NetworkManifest {
+                        version: MANIFEST_VERSION,
+                        shares,
+                    };
+                    let wants_html = match query.get("format").map(String::as_str) {
+                        Some(format) => format == "html",
+                        None => accept.is_some_and(|accept| accept.contains("text/html")),
+                    };
+                    if wants_html {
+                        let token = query.get("token").map(String::as_str);
+                        warp::reply::html(landing_page(&manifest, token)).into_response()
+                    } else {
+                        warp::reply::json(&manifest).into_response()
+                    }
+                }
+            },
+        )
+        .boxed()
+}
+
+fn landing_page(manifest: &NetworkManifest, token: Option<&str>) -> String {
+    // Keep query token auth working when following links
+    let query = token
+        .map(|token| format!("?token={}", encode_uri_component(token)))
+        .unwrap_or_default();
+    let rows: String = manifest
+        .shares
+        .iter()
+        .map(|share| {
+            format!(
+                "<tr><td><a href=\"/{}/{}\">{}</a></td><td>{}</td><td>{}</td></tr>\n",
+                encode_uri_component(&share.name),
+                escape_html(&query),
+                escape_html(&share.name),
+                share.file_count,
+                format_size(share.size)
+            )
+        })
+        .collect();
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Quartz</title></head>\n\
+         <body>\n<h1>Shared folders</h1>\n<table>\n\
+         <tr><th>Name</th><th>Files</th><th>Size</th></tr>\n{}</table>\n</body>\n</html>\n",
+        rows
+    )
+}
+
+pub fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+pub fn encode_uri_component(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
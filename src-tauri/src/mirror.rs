@@ -0,0 +1,678 @@
+//Uses
+use crate::auth::{generate_grant_id, unix_time};
+use crate::config::ConfigStore;
+use crate::listing::{
+    file_sha256, is_plain_name, is_reserved_name, modified_secs, normalize_path, STAGING_DIR,
+};
+use crate::remote::{client, error_chain, remote_network, resource_url, send, share_path};
+use crate::types::{
+    EntryKind, FileError, Mirror, MirrorCompare, MirrorPhase, MirrorProgress, MirrorStatus,
+    RemoteError, RemoteNetwork, ShareTree, TreeEntry,
+};
+use futures_util::StreamExt;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex as StdMutex;
+use std::time::{Instant, UNIX_EPOCH};
+use tauri::{AppHandle, Emitter};
+use tokio::io::AsyncWriteExt;
+use tokio::time::Duration;
+
+// How often scheduled mirrors are checked for being due
+const SCHEDULER_TICK: Duration = Duration::from_secs(60);
+// Progress events during downloads are sent at most this often
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+// Last sync of every mirror that ran since the app started, by mirror id
+lazy_static::lazy_static! {
+    static ref MIRROR_STATUSES: StdMutex<HashMap<String, MirrorStatus>> =
+        StdMutex::new(HashMap::new());
+}
+
+// Mirrors `remote_path` of a share on a joined network into a linked path
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub fn create_mirror(
+    app: AppHandle,
+    remote_network: String,
+    share: String,
+    remote_path: Option<String>,
+    linked_path: String,
+    interval_minutes: Option<u64>,
+    compare: Option<MirrorCompare>,
+    delete_extraneous: Option<bool>,
+    revision: u64,
+) -> Result<Mirror, FileError> {
+    if interval_minutes == Some(0) {
+        return Err(FileError::InvalidConfigError(
+            "mirror interval must be at least a minute".into(),
+        ));
+    }
+    let mirror = Mirror {
+        id: generate_grant_id(),
+        remote_network,
+        share,
+        remote_path: normalize_path(&remote_path.unwrap_or_default()),
+        linked_path,
+        interval_minutes,
+        compare: compare.unwrap_or_default(),
+        delete_extraneous: delete_extraneous.unwrap_or(false),
+    };
+    ConfigStore::update(Some(revision), |store| store.add_mirror(mirror.clone()))?;
+
+    if let Err(e) = app.emit("linked_paths_changed", ()) {
+        eprintln!("Failed to emit event to frontend: {}", e);
+    }
+    Ok(mirror)
+}
+
+#[tauri::command]
+pub fn list_mirrors() -> Result<Vec<Mirror>, FileError> {
+    let store = ConfigStore::load()?;
+    Ok(store.mirrors().to_vec())
+}
+
+// Files already mirrored stay in the linked path
+#[tauri::command]
+pub fn remove_mirror(
+    app: AppHandle,
+    mirror_id: String,
+    revision: u64,
+) -> Result<String, FileError> {
+    let removed = ConfigStore::update(Some(revision), |store| Ok(store.remove_mirror(&mirror_id)))?;
+    if !removed {
+        return Ok("Mirror not found".to_string());
+    }
+    MIRROR_STATUSES.lock().unwrap().remove(&mirror_id);
+
+    if let Err(e) = app.emit("linked_paths_changed", ()) {
+        eprintln!("Failed to emit event to frontend: {}", e);
+    }
+    Ok("Mirror removed successfully".to_string())
+}
+
+// Syncs right away and resolves once done, `mirror_progress` events follow
+// the sync meanwhile
+#[tauri::command]
+pub async fn sync_mirror(app: AppHandle, mirror_id: String) -> Result<MirrorStatus, RemoteError> {
+    let store = ConfigStore::load()?;
+    let mirror = store
+        .mirrors()
+        .iter()
+        .find(|mirror| mirror.id == mirror_id)
+        .cloned()
+        .ok_or(RemoteError::MirrorNotFound(mirror_id))?;
+    sync(&app, &mirror).await
+}
+
+#[tauri::command]
+pub fn get_mirror_statuses() -> HashMap<String, MirrorStatus> {
+    MIRROR_STATUSES.lock().unwrap().clone()
+}
+
+// Runs for the lifetime of the app, starting mirrors with an interval once
+// their last sync started long enough ago. Each mirror syncs once at startup.
+pub async fn run_mirror_scheduler(app: AppHandle) {
+    let mut ticks = tokio::time::interval(SCHEDULER_TICK);
+    loop {
+        ticks.tick().await;
+        let mirrors = match ConfigStore::load() {
+            Ok(store) => store.mirrors().to_vec(),
+            Err(e) => {
+                eprintln!("Failed to load mirrors: {}", e);
+                continue;
+            }
+        };
+        let now = unix_time();
+        for mirror in mirrors {
+            let Some(minutes) = mirror.interval_minutes else {
+                continue;
+            };
+            let status = MIRROR_STATUSES
+                .lock()
+                .unwrap()
+                .get(&mirror.id)
+                .cloned()
+                .unwrap_or_default();
+            let due = status
+                .last_started
+                .is_none_or(|started| now >= started + minutes * 60);
+            if status.running || !due {
+                continue;
+            }
+            let app = app.clone();
+            tauri::async_runtime::spawn(async move {
+                if let Err(e) = sync(&app, &mirror).await {
+                    eprintln!("Mirror {} failed: {}", mirror.id, e);
+                }
+            });
+        }
+    }
+}
+
+async fn sync(app: &AppHandle, mirror: &Mirror) -> Result<MirrorStatus, RemoteError> {
+    {
+        let mut statuses = MIRROR_STATUSES.lock().unwrap();
+        let status = statuses.entry(mirror.id.clone()).or_default();
+        if status.running {
+            return Err(RemoteError::MirrorBusy(mirror.id.clone()));
+        }
+        *status = MirrorStatus {
+            running: true,
+            last_started: Some(unix_time()),
+            ..status.clone()
+        };
+    }
+
+    let mut progress = Progress::new(app, &mirror.id);
+    let result = pull(mirror, &mut progress).await;
+    let error = result.as_ref().err().map(|e| e.to_string());
+    let status = {
+        let mut statuses = MIRROR_STATUSES.lock().unwrap();
+        let status = statuses.entry(mirror.id.clone()).or_default();
+        *status = MirrorStatus {
+            running: false,
+            last_started: status.last_started,
+            last_finished: Some(unix_time()),
+            files_downloaded: progress.event.files_done,
+            bytes_downloaded: progress.event.bytes_done,
+            files_deleted: progress.files_deleted,
+            error: error.clone(),
+        };
+        status.clone()
+    };
+
+    progress.event.current = None;
+    progress.event.error = error;
+    match result {
+        Ok(()) => {
+            progress.emit(MirrorPhase::Finished);
+            Ok(status)
+        }
+        Err(e) => {
+            progress.emit(MirrorPhase::Failed);
+            Err(e)
+        }
+    }
+}
+
+struct Progress<'a> {
+    app: &'a AppHandle,
+    event: MirrorProgress,
+    files_deleted: u64,
+    last_emit: Instant,
+}
+
+impl<'a> Progress<'a> {
+    fn new(app: &'a AppHandle, mirror: &str) -> Self {
+        Progress {
+            app,
+            event: MirrorProgress {
+                mirror: mirror.to_string(),
+                phase: MirrorPhase::Scanning,
+                files_done: 0,
+                files_total: 0,
+                bytes_done: 0,
+                bytes_total: 0,
+                current: None,
+                error: None,
+            },
+            files_deleted: 0,
+            last_emit: Instant::now(),
+        }
+    }
+
+    fn emit(&mut self, phase: MirrorPhase) {
+        self.event.phase = phase;
+        self.last_emit = Instant::now();
+        if let Err(e) = self.app.emit("mirror_progress", &self.event) {
+            eprintln!("Failed to emit event to frontend: {}", e);
+        }
+    }
+
+    // For byte counts, which change too often to send every update
+    fn update(&mut self) {
+        if self.last_emit.elapsed() >= PROGRESS_INTERVAL {
+            self.emit(self.event.phase);
+        }
+    }
+}
+
+// What a sync has to do, worked out from the remote tree and the linked path
+struct Plan {
+    downloads: Vec<TreeEntry>,
+    // Every path the remote side has, relative to the linked path
+    remote_paths: HashSet<String>,
+    // Local folders that downloads may replace, only with `replace_files`
+    replaced_dirs: HashSet<String>,
+    // Why remote entries were left out, a local file or folder is in the way
+    conflicts: Vec<String>,
+}
+
+async fn pull(mirror: &Mirror, progress: &mut Progress<'_>) -> Result<(), RemoteError> {
+    let network = remote_network(&mirror.remote_network)?;
+    let linked_path = ConfigStore::load()?
+        .linked_paths()
+        .iter()
+        .find(|path| path.name == mirror.linked_path)
+        .cloned()
+        .ok_or_else(|| FileError::NotFoundError(mirror.linked_path.clone()))?;
+    let root = fs::canonicalize(&linked_path.path).map_err(sync_error)?;
+
+    progress.emit(MirrorPhase::Scanning);
+    let tree = fetch_tree(&network, mirror).await?;
+    let compare = mirror.compare;
+    let replace_files = mirror.delete_extraneous;
+    let plan_root = root.clone();
+    let plan = tauri::async_runtime::spawn_blocking(move || {
+        plan(&plan_root, tree, compare, replace_files)
+    })
+    .await
+    .map_err(|e| RemoteError::SyncError(e.to_string()))??;
+
+    progress.event.files_total = plan.downloads.len() as u64;
+    progress.event.bytes_total = plan.downloads.iter().map(|entry| entry.size).sum();
+    progress.emit(MirrorPhase::Downloading);
+    let client = client(&network)?;
+    let mut failures = plan.conflicts.clone();
+    for entry in &plan.downloads {
+        progress.event.current = Some(entry.path.clone());
+        let bytes_before = progress.event.bytes_done;
+        let replace_dir = plan.replaced_dirs.contains(&entry.path);
+        match download(
+            &client,
+            &network,
+            mirror,
+            &root,
+            entry,
+            replace_dir,
+            progress,
+        )
+        .await
+        {
+            Ok(()) => progress.event.files_done += 1,
+            Err(e) => {
+                eprintln!("Failed to mirror {}: {}", entry.path, e);
+                // Failed files don't count towards the bytes mirrored
+                progress.event.bytes_done = bytes_before;
+                failures.push(format!("{}: {}", entry.path, e));
+            }
+        }
+        progress.emit(MirrorPhase::Downloading);
+    }
+    progress.event.current = None;
+
+    if mirror.delete_extraneous {
+        progress.emit(MirrorPhase::Deleting);
+        let delete_root = root.clone();
+        let remote_paths = plan.remote_paths;
+        progress.files_deleted = tauri::async_runtime::spawn_blocking(move || {
+            delete_extraneous(&delete_root, "", &remote_paths)
+        })
+        .await
+        .map_err(|e| RemoteError::SyncError(e.to_string()))?
+        .map_err(sync_error)?;
+    }
+
+    match failures.first() {
+        None => Ok(()),
+        Some(first) => Err(RemoteError::SyncError(format!(
+            "{} of {} paths failed, first {}",
+            failures.len(),
+            plan.downloads.len() + plan.conflicts.len(),
+            first
+        ))),
+    }
+}
+
+async fn fetch_tree(network: &RemoteNetwork, mirror: &Mirror) -> Result<ShareTree, RemoteError> {
+    let url = resource_url(
+        network,
+        &format!(
+            "api/v1/tree/{}",
+            share_path(&mirror.share, Some(&mirror.remote_path))
+        ),
+    )?;
+    let mut request = client(network)?.get(url);
+    if mirror.compare == MirrorCompare::Hash {
+        request = request.query(&[("hash", "sha256")]);
+    }
+    send(network, request)
+        .await?
+        .json()
+        .await
+        .map_err(|e| RemoteError::InvalidResponse(error_chain(&e)))
+}
+
+// Creates the remote folders locally and picks the files that differ. The
+// remote side isn't trusted: paths must stay below the linked path, and
+// files are only placed in folders created or checked here, so a local
+// symlink can't redirect them. A local file where the remote side has a
+// folder, or a local folder where it has a file, is only replaced when
+// `replace_files` is set. Otherwise the remote entry, with everything in
+// it, is reported as a conflict and skipped.
+fn plan(
+    root: &Path,
+    tree: ShareTree,
+    compare: MirrorCompare,
+    replace_files: bool,
+) -> Result<Plan, RemoteError> {
+    let mut downloads = Vec::new();
+    let mut remote_paths = HashSet::new();
+    let mut replaced_dirs = HashSet::new();
+    let mut conflicts = Vec::new();
+    // Conflicting folders and their contents
+    let mut skipped = HashSet::new();
+    for entry in tree.entries {
+        let segments: Vec<&str> = entry.path.split('/').collect();
+        let valid = segments
+            .iter()
+            .all(|segment| is_plain_name(segment) && !is_reserved_name(segment));
+        let parent = segments[..segments.len() - 1].join("/");
+        if valid && skipped.contains(&parent) {
+            skipped.insert(entry.path);
+            continue;
+        }
+        if !valid || !(parent.is_empty() || remote_paths.contains(&parent)) {
+            return Err(RemoteError::InvalidResponse(format!(
+                "unexpected path '{}'",
+                entry.path
+            )));
+        }
+
+        let local = root.join(&entry.path);
+        let existing = fs::symlink_metadata(&local).ok();
+        match entry.kind {
+            EntryKind::Directory => {
+                if !existing.as_ref().is_some_and(|metadata| metadata.is_dir()) {
+                    if existing.is_some() {
+                        if !replace_files {
+                            conflicts.push(format!(
+                                "{}: a local file is where the remote folder goes",
+                                entry.path
+                            ));
+                            skipped.insert(entry.path);
+                            continue;
+                        }
+                        fs::remove_file(&local).map_err(sync_error)?;
+                    }
+                    fs::create_dir(&local).map_err(sync_error)?;
+                }
+            }
+            EntryKind::File => {
+                if existing.as_ref().is_some_and(|metadata| metadata.is_dir()) {
+                    if !replace_files {
+                        conflicts.push(format!(
+                            "{}: a local folder is where the remote file goes",
+                            entry.path
+                        ));
+                        continue;
+                    }
+                    replaced_dirs.insert(entry.path.clone());
+                }
+                let up_to_date = existing.is_some_and(|metadata| {
+                    metadata.is_file()
+                        && metadata.len() == entry.size
+                        && match compare {
+                            MirrorCompare::SizeAndModified => {
+                                modified_secs(&metadata) == entry.modified
+                            }
+                            MirrorCompare::Hash => entry.sha256.as_ref().is_some_and(|hash| {
+                                file_sha256(&local, &metadata).is_ok_and(|local| local == *hash)
+                            }),
+                        }
+                });
+                if !up_to_date {
+                    downloads.push(entry.clone());
+                }
+            }
+        }
+        remote_paths.insert(entry.path);
+    }
+    Ok(Plan {
+        downloads,
+        remote_paths,
+        replaced_dirs,
+        conflicts,
+    })
+}
+
+// Downloads into the linked path's staging folder and renames the file into
+// place once it is complete, with the remote modification time
+async fn download(
+    client: &reqwest::Client,
+    network: &RemoteNetwork,
+    mirror: &Mirror,
+    root: &Path,
+    entry: &TreeEntry,
+    replace_dir: bool,
+    progress: &mut Progress<'_>,
+) -> Result<(), RemoteError> {
+    let remote_path = if mirror.remote_path.is_empty() {
+        entry.path.clone()
+    } else {
+        format!("{}/{}", mirror.remote_path, entry.path)
+    };
+    let url = resource_url(network, &share_path(&mirror.share, Some(&remote_path)))?;
+    let response = send(network, client.get(url)).await?;
+
+    let staging = root.join(STAGING_DIR);
+    tokio::fs::create_dir_all(&staging)
+        .await
+        .map_err(sync_error)?;
+    let part = staging.join(format!("mirror-{}.part", generate_grant_id()));
+    let result = async {
+        let mut file = tokio::fs::File::create(&part).await.map_err(sync_error)?;
+        let mut hasher = Sha256::new();
+        let mut received = 0u64;
+        let mut body = response.bytes_stream();
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk.map_err(|e| RemoteError::DownloadError(error_chain(&e)))?;
+            file.write_all(&chunk).await.map_err(sync_error)?;
+            hasher.update(&chunk);
+            received += chunk.len() as u64;
+            progress.event.bytes_done += chunk.len() as u64;
+            progress.update();
+        }
+        // The file changed since the tree was fetched, the next sync gets it
+        let changed = match &entry.sha256 {
+            Some(hash) => format!("{:x}", hasher.finalize()) != *hash,
+            None => received != entry.size,
+        };
+        if changed {
+            return Err(RemoteError::DownloadError(
+                "file changed on the remote side during the sync".into(),
+            ));
+        }
+        file.sync_all().await.map_err(sync_error)?;
+
+        let file = file.into_std().await;
+        let target = root.join(&entry.path);
+        let modified = entry.modified;
+        let staged = part.clone();
+        tauri::async_runtime::spawn_blocking(move || {
+            place(file, &staged, &target, modified, replace_dir)
+        })
+        .await
+        .map_err(|e| RemoteError::SyncError(e.to_string()))?
+        .map_err(sync_error)
+    }
+    .await;
+
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&part).await;
+    }
+    result
+}
+
+// `replace_dir` is set when the plan allowed a local folder to give way to
+// the file, one that appeared after planning is left alone
+fn place(
+    file: fs::File,
+    part: &Path,
+    target: &Path,
+    modified: Option<u64>,
+    replace_dir: bool,
+) -> io::Result<()> {
+    if let Some(secs) = modified {
+        file.set_modified(UNIX_EPOCH + Duration::from_secs(secs))?;
+    }
+    drop(file);
+    if fs::symlink_metadata(target).is_ok_and(|metadata| metadata.is_dir()) {
+        if !replace_dir {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "a local folder is where the remote file goes",
+            ));
+        }
+        fs::remove_dir_all(target)?;
+    }
+    fs::rename(part, target)
+}
+
+// Removes what the remote side doesn't have, returns how many entries went.
+// Symlinks are removed, never followed. Hidden entries are left alone since
+// the remote share may just not show them.
+fn delete_extraneous(root: &Path, prefix: &str, remote_paths: &HashSet<String>) -> io::Result<u64> {
+    let dir: PathBuf = if prefix.is_empty() {
+        root.to_path_buf()
+    } else {
+        root.join(prefix)
+    };
+    let mut deleted = 0;
+    for entry in fs::read_dir(&dir)?.flatten() {
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if name.starts_with('.') {
+            continue;
+        }
+        let relative = if prefix.is_empty() {
+            name
+        } else {
+            format!("{}/{}", prefix, name)
+        };
+        let metadata = entry.metadata()?;
+        if remote_paths.contains(&relative) {
+            if metadata.is_dir() {
+                deleted += delete_extraneous(root, &relative, remote_paths)?;
+            }
+            continue;
+        }
+        if metadata.is_dir() {
+            fs::remove_dir_all(entry.path())?;
+        } else {
+            fs::remove_file(entry.path())?;
+        }
+        deleted += 1;
+    }
+    Ok(deleted)
+}
+
+fn sync_error(e: io::Error) -> RemoteError {
+    RemoteError::SyncError(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::plan;
+    use crate::types::{EntryKind, MirrorCompare, ShareTree, TreeEntry};
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn tree(entries: &[(&str, EntryKind)]) -> ShareTree {
+        ShareTree {
+            share: "remote".to_string(),
+            path: String::new(),
+            entries: entries
+                .iter()
+                .map(|(path, kind)| TreeEntry {
+                    path: path.to_string(),
+                    kind: *kind,
+                    size: 1,
+                    modified: None,
+                    sha256: None,
+                })
+                .collect(),
+        }
+    }
+
+    fn local_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("quartz-mirror-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // The remote side has a folder `docs` with a file in it, and a file `notes`
+    fn remote() -> ShareTree {
+        tree(&[
+            ("docs", EntryKind::Directory),
+            ("docs/sub", EntryKind::Directory),
+            ("docs/sub/a.txt", EntryKind::File),
+            ("notes", EntryKind::File),
+        ])
+    }
+
+    #[test]
+    fn local_file_in_place_of_remote_folder_is_kept() {
+        let root = local_dir("file-kept");
+        fs::write(root.join("docs"), b"local").unwrap();
+
+        let plan = plan(&root, remote(), MirrorCompare::SizeAndModified, false).unwrap();
+        assert_eq!(plan.conflicts.len(), 1);
+        assert!(plan.conflicts[0].starts_with("docs: "));
+        let downloads: Vec<&str> = plan.downloads.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(downloads, ["notes"]);
+        assert_eq!(fs::read(root.join("docs")).unwrap(), b"local");
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn local_file_in_place_of_remote_folder_is_replaced_when_allowed() {
+        let root = local_dir("file-replaced");
+        fs::write(root.join("docs"), b"local").unwrap();
+
+        let plan = plan(&root, remote(), MirrorCompare::SizeAndModified, true).unwrap();
+        assert!(plan.conflicts.is_empty());
+        assert!(root.join("docs/sub").is_dir());
+        let downloads: Vec<&str> = plan.downloads.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(downloads, ["docs/sub/a.txt", "notes"]);
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn local_folder_in_place_of_remote_file_is_kept() {
+        let root = local_dir("folder-kept");
+        fs::create_dir(root.join("notes")).unwrap();
+        fs::write(root.join("notes/mine.txt"), b"local").unwrap();
+
+        let plan = plan(&root, remote(), MirrorCompare::SizeAndModified, false).unwrap();
+        assert_eq!(plan.conflicts.len(), 1);
+        assert!(plan.conflicts[0].starts_with("notes: "));
+        assert!(plan.replaced_dirs.is_empty());
+        assert!(plan.downloads.iter().all(|entry| entry.path != "notes"));
+        assert!(root.join("notes/mine.txt").is_file());
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn local_folder_in_place_of_remote_file_is_replaced_when_allowed() {
+        let root = local_dir("folder-replaced");
+        fs::create_dir(root.join("notes")).unwrap();
+
+        let plan = plan(&root, remote(), MirrorCompare::SizeAndModified, true).unwrap();
+        assert!(plan.conflicts.is_empty());
+        assert!(plan.replaced_dirs.contains("notes"));
+        assert!(plan.downloads.iter().any(|entry| entry.path == "notes"));
+
+        let _ = fs::remove_dir_all(&root);
+    }
+}
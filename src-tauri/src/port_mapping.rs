@@ -0,0 +1,506 @@
+//Uses
+use crate::types::PortMappingError;
+use igd_next::aio::tokio::{search_gateway, Tokio};
+use igd_next::aio::Gateway;
+use igd_next::{PortMappingProtocol, SearchOptions};
+use natpmp::{
+    get_default_gateway, new_natpmp_async_with, NatpmpAsync, Protocol, Response, NATPMP_PORT,
+};
+use std::net::{IpAddr, SocketAddr, SocketAddrV4};
+use tokio::net::UdpSocket;
+use tokio::time::{timeout, Duration};
+
+// Mappings are leased and renewed while the server runs, so a crashed app
+// doesn't leave ports open forever
+pub const LEASE_DURATION: Duration = Duration::from_secs(60 * 60);
+pub const RENEW_INTERVAL: Duration = Duration::from_secs(20 * 60);
+const SEARCH_TIMEOUT: Duration = Duration::from_secs(5);
+const NATPMP_TIMEOUT: Duration = Duration::from_secs(3);
+const DESCRIPTION: &str = "Quartz file server";
+
+// Where the router is looked for
+pub struct GatewaySearch {
+    // Where UPnP-IGD discovery requests go
+    pub ssdp_addr: SocketAddr,
+    // The NAT-PMP gateway, None for the default route's
+    pub natpmp_addr: Option<SocketAddrV4>,
+}
+
+impl Default for GatewaySearch {
+    fn default() -> Self {
+        GatewaySearch {
+            ssdp_addr: SearchOptions::default().broadcast_address,
+            natpmp_addr: None,
+        }
+    }
+}
+
+pub enum PortMapping {
+    Upnp {
+        gateway: Gateway<Tokio>,
+        local_addr: SocketAddr,
+        external_port: u16,
+    },
+    NatPmp {
+        client: NatpmpAsync<UdpSocket>,
+        local_port: u16,
+        external_port: u16,
+    },
+}
+
+// Forwards `local_port` on the router, trying UPnP-IGD first and NAT-PMP
+// second. Returns the mapping and the address peers can reach us on.
+pub async fn map_port(local_port: u16) -> Result<(PortMapping, SocketAddr), PortMappingError> {
+    map_port_with(local_port, &GatewaySearch::default()).await
+}
+
+pub async fn map_port_with(
+    local_port: u16,
+    search: &GatewaySearch,
+) -> Result<(PortMapping, SocketAddr), PortMappingError> {
+    let upnp_error = match map_upnp(local_port, search.ssdp_addr).await {
+        Ok(mapped) => return Ok(mapped),
+        Err(e) => e,
+    };
+    let natpmp_error = match map_natpmp(local_port, search.natpmp_addr).await {
+        Ok(mapped) => return Ok(mapped),
+        Err(e) => e,
+    };
+
+    Err(PortMappingError::Unavailable {
+        upnp: upnp_error,
+        natpmp: natpmp_error,
+    })
+}
+
+impl PortMapping {
+    pub async fn renew(&mut self) -> Result<(), PortMappingError> {
+        let result = match self {
+            PortMapping::Upnp {
+                gateway,
+                local_addr,
+                external_port,
+            } => add_upnp_port(gateway, *local_addr, *external_port).await,
+            PortMapping::NatPmp {
+                client,
+                local_port,
+                external_port,
+            } => {
+                let lease = LEASE_DURATION.as_secs() as u32;
+                natpmp_map(client, *local_port, *external_port, lease)
+                    .await
+                    .map(|_| ())
+            }
+        };
+        result.map_err(PortMappingError::RenewError)
+    }
+
+    pub async fn remove(mut self) {
+        let result = match &mut self {
+            PortMapping::Upnp {
+                gateway,
+                external_port,
+                ..
+            } => gateway
+                .remove_port(PortMappingProtocol::TCP, *external_port)
+                .await
+                .map_err(|e| e.to_string()),
+            PortMapping::NatPmp {
+                client, local_port, ..
+            } => natpmp_map(client, *local_port, 0, 0).await.map(|_| ()),
+        };
+        if let Err(e) = result {
+            eprintln!("Failed to remove port mapping: {}", e);
+        }
+    }
+}
+
+async fn map_upnp(
+    local_port: u16,
+    ssdp_addr: SocketAddr,
+) -> Result<(PortMapping, SocketAddr), String> {
+    let search_options = SearchOptions {
+        broadcast_address: ssdp_addr,
+        timeout: Some(SEARCH_TIMEOUT),
+        ..Default::default()
+    };
+    let gateway = search_gateway(search_options)
+        .await
+        .map_err(|e| e.to_string())?;
+    let local_ip = local_ip_towards(gateway.addr)
+        .await
+        .map_err(|e| e.to_string())?;
+    let local_addr = SocketAddr::new(local_ip, local_port);
+
+    add_upnp_port(&gateway, local_addr, local_port).await?;
+    let external_ip = gateway.get_external_ip().await.map_err(|e| e.to_string())?;
+    println!("Mapped port {} via UPnP on {}", local_port, gateway.addr);
+
+    let mapping = PortMapping::Upnp {
+        gateway,
+        local_addr,
+        external_port: local_port,
+    };
+    Ok((mapping, SocketAddr::new(external_ip, local_port)))
+}
+
+async fn add_upnp_port(
+    gateway: &Gateway<Tokio>,
+    local_addr: SocketAddr,
+    external_port: u16,
+) -> Result<(), String> {
+    gateway
+        .add_port(
+            PortMappingProtocol::TCP,
+            external_port,
+            local_addr,
+            LEASE_DURATION.as_secs() as u32,
+            DESCRIPTION,
+        )
+        .await
+        .map_err(|e| e.to_string())
+}
+
+async fn map_natpmp(
+    local_port: u16,
+    gateway: Option<SocketAddrV4>,
+) -> Result<(PortMapping, SocketAddr), String> {
+    let gateway = match gateway {
+        Some(gateway) => gateway,
+        None => SocketAddrV4::new(
+            get_default_gateway().map_err(|e| e.to_string())?,
+            NATPMP_PORT,
+        ),
+    };
+    let socket = UdpSocket::bind(("0.0.0.0", 0))
+        .await
+        .map_err(|e| e.to_string())?;
+    socket.connect(gateway).await.map_err(|e| e.to_string())?;
+    let mut client = new_natpmp_async_with(socket, *gateway.ip());
+
+    client
+        .send_public_address_request()
+        .await
+        .map_err(|e| e.to_string())?;
+    let external_ip = match natpmp_response(&client).await? {
+        Response::Gateway(response) => *response.public_address(),
+        _ => return Err("unexpected NAT-PMP response".into()),
+    };
+
+    let lease = LEASE_DURATION.as_secs() as u32;
+    let external_port = natpmp_map(&client, local_port, local_port, lease).await?;
+    println!(
+        "Mapped port {} via NAT-PMP on {}",
+        local_port,
+        client.gateway()
+    );
+
+    let mapping = PortMapping::NatPmp {
+        client,
+        local_port,
+        external_port,
+    };
+    Ok((
+        mapping,
+        SocketAddr::new(IpAddr::V4(external_ip), external_port),
+    ))
+}
+
+// A lifetime of 0 deletes the mapping. Returns the external port the
+// gateway actually assigned.
+async fn natpmp_map(
+    client: &NatpmpAsync<UdpSocket>,
+    local_port: u16,
+    external_port: u16,
+    lifetime: u32,
+) -> Result<u16, String> {
+    client
+        .send_port_mapping_request(Protocol::TCP, local_port, external_port, lifetime)
+        .await
+        .map_err(|e| e.to_string())?;
+    match natpmp_response(client).await? {
+        Response::TCP(response) => Ok(response.public_port()),
+        _ => Err("unexpected NAT-PMP response".into()),
+    }
+}
+
+async fn natpmp_response(client: &NatpmpAsync<UdpSocket>) -> Result<Response, String> {
+    timeout(NATPMP_TIMEOUT, client.read_response_or_retry())
+        .await
+        .map_err(|_| "NAT-PMP gateway did not respond".to_string())?
+        .map_err(|e| e.to_string())
+}
+
+// The LAN address of the interface that routes to `gateway`
+async fn local_ip_towards(gateway: SocketAddr) -> std::io::Result<IpAddr> {
+    let socket = UdpSocket::bind(("0.0.0.0", 0)).await?;
+    socket.connect(gateway).await?;
+    Ok(socket.local_addr()?.ip())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, SocketAddrV4};
+    use std::sync::{Arc, Mutex as StdMutex};
+    use warp::http::StatusCode;
+    use warp::Filter;
+
+    const LOCAL_PORT: u16 = 8443;
+
+    const ROOT_DESCRIPTION: &str = r#"<?xml version="1.0"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+  <device>
+    <deviceType>urn:schemas-upnp-org:device:InternetGatewayDevice:1</deviceType>
+    <serviceList>
+      <service>
+        <serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>
+        <controlURL>/control</controlURL>
+        <SCPDURL>/scpd.xml</SCPDURL>
+      </service>
+    </serviceList>
+  </device>
+</root>"#;
+
+    const SERVICE_DESCRIPTION: &str = r#"<?xml version="1.0"?>
+<scpd xmlns="urn:schemas-upnp-org:service-1-0">
+  <actionList>
+    <action>
+      <name>AddPortMapping</name>
+      <argumentList>
+        <argument><name>NewRemoteHost</name><direction>in</direction></argument>
+        <argument><name>NewExternalPort</name><direction>in</direction></argument>
+        <argument><name>NewProtocol</name><direction>in</direction></argument>
+        <argument><name>NewInternalPort</name><direction>in</direction></argument>
+        <argument><name>NewInternalClient</name><direction>in</direction></argument>
+        <argument><name>NewEnabled</name><direction>in</direction></argument>
+        <argument><name>NewPortMappingDescription</name><direction>in</direction></argument>
+        <argument><name>NewLeaseDuration</name><direction>in</direction></argument>
+      </argumentList>
+    </action>
+    <action>
+      <name>DeletePortMapping</name>
+      <argumentList>
+        <argument><name>NewRemoteHost</name><direction>in</direction></argument>
+        <argument><name>NewExternalPort</name><direction>in</direction></argument>
+        <argument><name>NewProtocol</name><direction>in</direction></argument>
+      </argumentList>
+    </action>
+  </actionList>
+</scpd>"#;
+
+    // SOAP actions the stub was asked for, with their request bodies
+    type Requests<T> = Arc<StdMutex<Vec<T>>>;
+
+    fn soap_envelope(body: &str) -> String {
+        format!(
+            r#"<?xml version="1.0"?><s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/"><s:Body>{}</s:Body></s:Envelope>"#,
+            body
+        )
+    }
+
+    // Answers SSDP searches and serves the IGD descriptions and SOAP control
+    // URL. With `refuse`, every AddPortMapping fails as if the port was taken.
+    async fn igd_stub(refuse: bool) -> (SocketAddr, Requests<(String, String)>) {
+        let requests: Requests<(String, String)> = Arc::default();
+        let recorded = requests.clone();
+        let control = warp::post()
+            .and(warp::path("control"))
+            .and(warp::header::<String>("soapaction"))
+            .and(warp::body::bytes())
+            .map(move |soap_action: String, body: warp::hyper::body::Bytes| {
+                let action = soap_action
+                    .trim_matches('"')
+                    .rsplit('#')
+                    .next()
+                    .unwrap_or_default()
+                    .to_string();
+                let body = String::from_utf8_lossy(&body).into_owned();
+                recorded.lock().unwrap().push((action.clone(), body));
+                let (status, reply) = match action.as_str() {
+                    "AddPortMapping" if refuse => (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        soap_envelope(
+                            "<s:Fault><faultcode>s:Client</faultcode><faultstring>UPnPError</faultstring><detail><UPnPError xmlns=\"urn:schemas-upnp-org:control-1-0\"><errorCode>718</errorCode><errorDescription>ConflictInMappingEntry</errorDescription></UPnPError></detail></s:Fault>",
+                        ),
+                    ),
+                    "GetExternalIPAddress" => (
+                        StatusCode::OK,
+                        soap_envelope(
+                            "<u:GetExternalIPAddressResponse xmlns:u=\"urn:schemas-upnp-org:service:WANIPConnection:1\"><NewExternalIPAddress>203.0.113.7</NewExternalIPAddress></u:GetExternalIPAddressResponse>",
+                        ),
+                    ),
+                    action => (
+                        StatusCode::OK,
+                        soap_envelope(&format!(
+                            "<u:{0}Response xmlns:u=\"urn:schemas-upnp-org:service:WANIPConnection:1\"></u:{0}Response>",
+                            action
+                        )),
+                    ),
+                };
+                warp::reply::with_status(reply, status)
+            });
+        let descriptions = warp::get()
+            .and(warp::path("rootDesc.xml"))
+            .map(|| ROOT_DESCRIPTION)
+            .or(warp::get()
+                .and(warp::path("scpd.xml"))
+                .map(|| SERVICE_DESCRIPTION));
+        let (http_addr, server) =
+            warp::serve(control.or(descriptions)).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let ssdp = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let ssdp_addr = ssdp.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1024];
+            while let Ok((len, from)) = ssdp.recv_from(&mut buf).await {
+                if !String::from_utf8_lossy(&buf[..len]).starts_with("M-SEARCH") {
+                    continue;
+                }
+                let reply = format!(
+                    "HTTP/1.1 200 OK\r\nST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\nLOCATION: http://{}/rootDesc.xml\r\n\r\n",
+                    http_addr
+                );
+                let _ = ssdp.send_to(reply.as_bytes(), from).await;
+            }
+        });
+        (ssdp_addr, requests)
+    }
+
+    // A NAT-PMP gateway that hands out external port 40000 and answers every
+    // request with `result_code`. Records (opcode, private port, public port,
+    // lifetime) of mapping requests.
+    async fn natpmp_stub(result_code: u16) -> (SocketAddrV4, Requests<(u8, u16, u16, u32)>) {
+        let requests: Requests<(u8, u16, u16, u32)> = Arc::default();
+        let recorded = requests.clone();
+        let socket = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let SocketAddr::V4(addr) = socket.local_addr().unwrap() else {
+            unreachable!()
+        };
+        tokio::spawn(async move {
+            let mut buf = [0u8; 16];
+            while let Ok((len, from)) = socket.recv_from(&mut buf).await {
+                let opcode = buf[1];
+                let mut reply = vec![0, 128 + opcode];
+                reply.extend_from_slice(&result_code.to_be_bytes());
+                reply.extend_from_slice(&7u32.to_be_bytes());
+                if opcode == 0 {
+                    reply.extend_from_slice(&[203, 0, 113, 9]);
+                } else if len >= 12 {
+                    let private_port = u16::from_be_bytes([buf[4], buf[5]]);
+                    let public_port = u16::from_be_bytes([buf[6], buf[7]]);
+                    let lifetime = u32::from_be_bytes([buf[8], buf[9], buf[10], buf[11]]);
+                    recorded
+                        .lock()
+                        .unwrap()
+                        .push((opcode, private_port, public_port, lifetime));
+                    let assigned: u16 = if lifetime == 0 { 0 } else { 40000 };
+                    reply.extend_from_slice(&private_port.to_be_bytes());
+                    reply.extend_from_slice(&assigned.to_be_bytes());
+                    reply.extend_from_slice(&lifetime.to_be_bytes());
+                }
+                let _ = socket.send_to(&reply, from).await;
+            }
+        });
+        (addr, requests)
+    }
+
+    // Nothing listens here, so a NAT-PMP attempt would time out
+    fn unused_natpmp_addr() -> SocketAddrV4 {
+        SocketAddrV4::new(Ipv4Addr::LOCALHOST, 9)
+    }
+
+    #[tokio::test]
+    async fn maps_renews_and_removes_over_upnp() {
+        let (ssdp_addr, requests) = igd_stub(false).await;
+        let search = GatewaySearch {
+            ssdp_addr,
+            natpmp_addr: Some(unused_natpmp_addr()),
+        };
+
+        let (mut mapping, external_addr) = map_port_with(LOCAL_PORT, &search).await.unwrap();
+        assert_eq!(external_addr, "203.0.113.7:8443".parse().unwrap());
+        assert!(matches!(mapping, PortMapping::Upnp { .. }));
+        mapping.renew().await.unwrap();
+        mapping.remove().await;
+
+        let requests = requests.lock().unwrap();
+        let actions: Vec<&str> = requests.iter().map(|(action, _)| action.as_str()).collect();
+        assert_eq!(
+            actions,
+            [
+                "AddPortMapping",
+                "GetExternalIPAddress",
+                "AddPortMapping",
+                "DeletePortMapping"
+            ]
+        );
+        for (_, body) in requests
+            .iter()
+            .filter(|(action, _)| action == "AddPortMapping")
+        {
+            assert!(body.contains("<NewExternalPort>8443</NewExternalPort>"));
+            assert!(body.contains("<NewInternalPort>8443</NewInternalPort>"));
+            assert!(body.contains("<NewInternalClient>127.0.0.1</NewInternalClient>"));
+            assert!(body.contains("<NewProtocol>TCP</NewProtocol>"));
+            assert!(body.contains("<NewLeaseDuration>3600</NewLeaseDuration>"));
+        }
+        assert!(requests[3]
+            .1
+            .contains("<NewExternalPort>8443</NewExternalPort>"));
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_natpmp_when_upnp_refuses() {
+        let (ssdp_addr, upnp_requests) = igd_stub(true).await;
+        let (natpmp_addr, requests) = natpmp_stub(0).await;
+        let search = GatewaySearch {
+            ssdp_addr,
+            natpmp_addr: Some(natpmp_addr),
+        };
+
+        let (mut mapping, external_addr) = map_port_with(LOCAL_PORT, &search).await.unwrap();
+        // The port the gateway assigned, not the one asked for
+        assert_eq!(external_addr, "203.0.113.9:40000".parse().unwrap());
+        assert!(matches!(
+            mapping,
+            PortMapping::NatPmp {
+                external_port: 40000,
+                ..
+            }
+        ));
+        assert_eq!(upnp_requests.lock().unwrap()[0].0, "AddPortMapping");
+        mapping.renew().await.unwrap();
+        mapping.remove().await;
+
+        // TCP mappings are opcode 2, removal is a lifetime of 0
+        assert_eq!(
+            *requests.lock().unwrap(),
+            [
+                (2, LOCAL_PORT, LOCAL_PORT, 3600),
+                (2, LOCAL_PORT, 40000, 3600),
+                (2, LOCAL_PORT, 0, 0)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_both_errors_when_no_gateway_maps() {
+        let (ssdp_addr, _) = igd_stub(true).await;
+        // 2 is "not authorized"
+        let (natpmp_addr, _) = natpmp_stub(2).await;
+        let search = GatewaySearch {
+            ssdp_addr,
+            natpmp_addr: Some(natpmp_addr),
+        };
+
+        match map_port_with(LOCAL_PORT, &search).await {
+            Err(PortMappingError::Unavailable { upnp, natpmp }) => {
+                assert!(!upnp.is_empty());
+                assert!(!natpmp.is_empty());
+            }
+            _ => panic!("expected both mapping methods to fail"),
+        }
+    }
+}
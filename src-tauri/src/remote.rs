@@ -0,0 +1,502 @@
+//Uses
+use crate::auth::unix_time;
+use crate::config::ConfigStore;
+use crate::local_dir::DEFAULT_PORT;
+use crate::manifest::{encode_uri_component, MANIFEST_VERSION};
+use crate::tor::DEFAULT_SOCKS_PORT;
+use crate::types::{
+    DirectoryListing, FileError, NetworkManifest, RemoteError, RemoteNetwork, ShareSummary,
+    SortKey, SortOrder,
+};
+use futures_util::StreamExt;
+use reqwest::{Client, Proxy, Url};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tauri::{AppHandle, Emitter};
+use tokio::io::AsyncWriteExt;
+use tokio::time::Duration;
+
+// Onion services can take a while to build a circuit to
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+const FINGERPRINT_MISMATCH: &str = "certificate doesn't match the pinned fingerprint";
+// Longer error bodies from the remote server are cut off
+const MAX_ERROR_MESSAGE: usize = 200;
+
+#[derive(Serialize)]
+struct ListQuery {
+    offset: Option<usize>,
+    limit: Option<usize>,
+    sort: Option<SortKey>,
+    order: Option<SortOrder>,
+}
+
+// Joins a network served by another Quartz instance. `address` is either
+// `host[:port]` or an invite link like
+// `https://host:port/?token=<token>&fingerprint=<AB:CD:...>`; `token` and
+// `fingerprint` given separately take precedence over the link's. The
+// manifest is fetched once to check the address and credentials before the
+// network is saved.
+#[tauri::command]
+pub async fn join_network(
+    app: AppHandle,
+    address: String,
+    name: Option<String>,
+    token: Option<String>,
+    fingerprint: Option<String>,
+    revision: u64,
+) -> Result<RemoteNetwork, RemoteError> {
+    let (url, link_token, link_fingerprint) = parse_address(&address, fingerprint.is_some())?;
+    let fingerprint = fingerprint
+        .or(link_fingerprint)
+        .map(|fingerprint| normalize_fingerprint(&fingerprint))
+        .transpose()?;
+    let name = match name.map(|name| name.trim().to_string()) {
+        Some(name) if !name.is_empty() => name,
+        _ => url.host_str().unwrap_or_default().to_string(),
+    };
+    let mut network = RemoteNetwork {
+        name,
+        url: url.to_string(),
+        token: token.or(link_token).filter(|token| !token.is_empty()),
+        fingerprint,
+        shares: Vec::new(),
+        joined_at: unix_time(),
+    };
+
+    network.shares = fetch_manifest(&network).await?.shares;
+    ConfigStore::update(Some(revision), |store| {
+        store.add_remote_network(network.clone())
+    })?;
+    println!("Joined remote network {} at {}", network.name, network.url);
+
+    if let Err(e) = app.emit("linked_paths_changed", ()) {
+        eprintln!("Failed to emit event to frontend: {}", e);
+    }
+    Ok(network)
+}
+
+#[tauri::command]
+pub fn list_remote_networks() -> Result<Vec<RemoteNetwork>, FileError> {
+    let store = ConfigStore::load()?;
+    Ok(store.remote_networks().to_vec())
+}
+
+#[tauri::command]
+pub fn leave_network(
+    app: AppHandle,
+    network_name: String,
+    revision: u64,
+) -> Result<String, FileError> {
+    let removed = ConfigStore::update(Some(revision), |store| {
+        Ok(store.remove_remote_network(&network_name))
+    })?;
+    if !removed {
+        return Ok("Network not found".to_string());
+    }
+
+    if let Err(e) = app.emit("linked_paths_changed", ()) {
+        eprintln!("Failed to emit event to frontend: {}", e);
+    }
+    Ok("Left network successfully".to_string())
+}
+
+// Fetches the current manifest, the stored share list is updated if it changed
+#[tauri::command]
+pub async fn list_remote_shares(
+    app: AppHandle,
+    network_name: String,
+) -> Result<Vec<ShareSummary>, RemoteError> {
+    let network = remote_network(&network_name)?;
+    let shares = fetch_manifest(&network).await?.shares;
+    if shares != network.shares {
+        ConfigStore::update(None, |store| {
+            store.set_remote_shares(&network_name, shares.clone())
+        })?;
+        if let Err(e) = app.emit("linked_paths_changed", ()) {
+            eprintln!("Failed to emit event to frontend: {}", e);
+        }
+    }
+    Ok(shares)
+}
+
+// One page of a directory on a remote share, through `/api/v1/list`
+#[tauri::command]
+pub async fn browse_remote(
+    network_name: String,
+    share: String,
+    path: Option<String>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+    sort: Option<SortKey>,
+    order: Option<SortOrder>,
+) -> Result<DirectoryListing, RemoteError> {
+    let network = remote_network(&network_name)?;
+    let url = resource_url(
+        &network,
+        &format!("api/v1/list/{}", share_path(&share, path.as_deref())),
+    )?;
+    let query = ListQuery {
+        offset,
+        limit,
+        sort,
+        order,
+    };
+    let response = send(&network, client(&network)?.get(url).query(&query)).await?;
+    response
+        .json()
+        .await
+        .map_err(|e| RemoteError::InvalidResponse(error_chain(&e)))
+}
+
+// Streams a remote file to `destination`. It is written next to it as
+// `<name>.part` first, so an interrupted download never looks complete.
+#[tauri::command]
+pub async fn download_remote_file(
+    network_name: String,
+    share: String,
+    path: String,
+    destination: PathBuf,
+) -> Result<String, RemoteError> {
+    let network = remote_network(&network_name)?;
+    let url = resource_url(&network, &share_path(&share, Some(&path)))?;
+    let response = send(&network, client(&network)?.get(url)).await?;
+    let expected = response.content_length();
+
+    let mut part_name = destination.file_name().unwrap_or_default().to_os_string();
+    part_name.push(".part");
+    let part_path = destination.with_file_name(part_name);
+    let result = async {
+        let mut file = tokio::fs::File::create(&part_path)
+            .await
+            .map_err(download_error)?;
+        let mut received = 0u64;
+        let mut body = response.bytes_stream();
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk.map_err(|e| RemoteError::DownloadError(error_chain(&e)))?;
+            file.write_all(&chunk).await.map_err(download_error)?;
+            received += chunk.len() as u64;
+        }
+        if expected.is_some_and(|expected| expected != received) {
+            return Err(RemoteError::DownloadError(
+                "connection closed before the file was complete".into(),
+            ));
+        }
+        file.sync_all().await.map_err(download_error)?;
+        tokio::fs::rename(&part_path, &destination)
+            .await
+            .map_err(download_error)?;
+        Ok(received)
+    }
+    .await;
+
+    match result {
+        Ok(received) => Ok(format!("Downloaded {} bytes", received)),
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&part_path).await;
+            Err(e)
+        }
+    }
+}
+
+pub fn remote_network(network_name: &str) -> Result<RemoteNetwork, RemoteError> {
+    let store = ConfigStore::load()?;
+    store
+        .remote_networks()
+        .iter()
+        .find(|network| network.name == network_name)
+        .cloned()
+        .ok_or_else(|| RemoteError::NetworkNotFound(network_name.to_string()))
+}
+
+async fn fetch_manifest(network: &RemoteNetwork) -> Result<NetworkManifest, RemoteError> {
+    let url = resource_url(network, "")?;
+    let request = client(network)?.get(url).query(&[("format", "json")]);
+    let manifest: NetworkManifest = send(network, request)
+        .await?
+        .json()
+        .await
+        .map_err(|e| RemoteError::InvalidManifest(error_chain(&e)))?;
+
+    if manifest.version > MANIFEST_VERSION {
+        return Err(RemoteError::InvalidManifest(format!(
+            "version {} is newer than this version of Quartz supports",
+            manifest.version
+        )));
+    }
+    let mut names = HashSet::new();
+    for share in &manifest.shares {
+        if share.name.is_empty() || share.name.contains('/') {
+            return Err(RemoteError::InvalidManifest(format!(
+                "invalid share name '{}'",
+                share.name
+            )));
+        }
+        if !names.insert(share.name.as_str()) {
+            return Err(RemoteError::InvalidManifest(format!(
+                "duplicate share name '{}'",
+                share.name
+            )));
+        }
+    }
+    Ok(manifest)
+}
+
+// Sends with the network's token and turns error statuses into errors
+pub async fn send(
+    network: &RemoteNetwork,
+    request: reqwest::RequestBuilder,
+) -> Result<reqwest::Response, RemoteError> {
+    let request = match &network.token {
+        Some(token) => request.bearer_auth(token),
+        None => request,
+    };
+    let response = request.send().await.map_err(|e| {
+        let message = error_chain(&e);
+        if message.contains(FINGERPRINT_MISMATCH) {
+            RemoteError::FingerprintMismatch
+        } else {
+            RemoteError::ConnectError(message)
+        }
+    })?;
+
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let mut message = response.text().await.unwrap_or_default();
+    if message.len() > MAX_ERROR_MESSAGE {
+        let mut end = MAX_ERROR_MESSAGE;
+        while !message.is_char_boundary(end) {
+            end -= 1;
+        }
+        message.truncate(end);
+    }
+    Err(RemoteError::StatusError {
+        status: status.as_u16(),
+        message,
+    })
+}
+
+// Requests to pinned networks only trust the pinned certificate, onion
+// addresses go through the local Tor daemon
+pub fn client(network: &RemoteNetwork) -> Result<Client, RemoteError> {
+    let url = base_url(network)?;
+    let mut builder = Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .user_agent(concat!("Quartz/", env!("CARGO_PKG_VERSION")));
+    if let Some(fingerprint) = &network.fingerprint {
+        builder = builder.use_preconfigured_tls(pinned_tls_config(fingerprint)?);
+    }
+    if url.host_str().is_some_and(|host| host.ends_with(".onion")) {
+        let proxy = Proxy::all(format!("socks5h://127.0.0.1:{}", DEFAULT_SOCKS_PORT))
+            .map_err(|e| RemoteError::ConnectError(error_chain(&e)))?;
+        builder = builder.proxy(proxy);
+    }
+    builder
+        .build()
+        .map_err(|e| RemoteError::ConnectError(error_chain(&e)))
+}
+
+fn base_url(network: &RemoteNetwork) -> Result<Url, RemoteError> {
+    Url::parse(&network.url).map_err(|e| RemoteError::InvalidAddress(e.to_string()))
+}
+
+pub fn resource_url(network: &RemoteNetwork, path: &str) -> Result<Url, RemoteError> {
+    base_url(network)?
+        .join(path)
+        .map_err(|e| RemoteError::InvalidAddress(e.to_string()))
+}
+
+// `<share>/<path>` with every segment percent-encoded
+pub fn share_path(share: &str, path: Option<&str>) -> String {
+    let mut encoded = encode_uri_component(share);
+    for segment in path
+        .unwrap_or_default()
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+    {
+        encoded.push('/');
+        encoded.push_str(&encode_uri_component(segment));
+    }
+    encoded
+}
+
+// The server's base URL plus the token and fingerprint an invite link
+// carries. Bare addresses default to Quartz's port, and to https if a
+// fingerprint is going to be pinned, explicit http is refused then.
+fn parse_address(
+    address: &str,
+    pinned: bool,
+) -> Result<(Url, Option<String>, Option<String>), RemoteError> {
+    let address = address.trim();
+    let has_scheme = address.contains("://");
+    let link = if has_scheme {
+        address.to_string()
+    } else {
+        format!("http://{}", address)
+    };
+    let mut url = Url::parse(&link).map_err(|e| RemoteError::InvalidAddress(e.to_string()))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(RemoteError::InvalidAddress(format!(
+            "unsupported scheme '{}'",
+            url.scheme()
+        )));
+    }
+    let host = url
+        .host_str()
+        .ok_or_else(|| RemoteError::InvalidAddress("missing host".into()))?
+        .to_ascii_lowercase();
+
+    let mut token = None;
+    let mut fingerprint = None;
+    for (key, value) in url.query_pairs() {
+        match key.as_ref() {
+            "token" => token = Some(value.into_owned()),
+            "fingerprint" => fingerprint = Some(value.into_owned()),
+            _ => {}
+        }
+    }
+
+    // A pinned certificate means nothing over plain http, the connection
+    // would go out unencrypted and unverified
+    let onion = host.ends_with(".onion");
+    if has_scheme && url.scheme() == "http" && !onion && (pinned || fingerprint.is_some()) {
+        return Err(RemoteError::InvalidAddress(
+            "a fingerprint needs an https address".into(),
+        ));
+    }
+
+    // Onion services are reached on port 80 and Tor does the encryption
+    if !has_scheme && !onion {
+        if pinned || fingerprint.is_some() {
+            let _ = url.set_scheme("https");
+        }
+        if url.port().is_none() {
+            let _ = url.set_port(Some(DEFAULT_PORT));
+        }
+    }
+    url.set_path("/");
+    url.set_query(None);
+    url.set_fragment(None);
+    Ok((url, token, fingerprint))
+}
+
+// Accepts `AB:CD:...` as shown by `get_certificate_fingerprint` as well as
+// plain hex, stored in the former format
+fn normalize_fingerprint(fingerprint: &str) -> Result<String, RemoteError> {
+    let hex: String = fingerprint
+        .chars()
+        .filter(|c| *c != ':' && !c.is_whitespace())
+        .collect::<String>()
+        .to_ascii_uppercase();
+    if hex.len() != 64 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(RemoteError::InvalidAddress(
+            "fingerprint must be a SHA-256 digest".into(),
+        ));
+    }
+    let pairs: Vec<&str> = (0..hex.len()).step_by(2).map(|i| &hex[i..i + 2]).collect();
+    Ok(pairs.join(":"))
+}
+
+fn pinned_tls_config(fingerprint: &str) -> Result<rustls::ClientConfig, RemoteError> {
+    let digest = normalize_fingerprint(fingerprint)?
+        .split(':')
+        .map(|pair| u8::from_str_radix(pair, 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|e| RemoteError::InvalidAddress(e.to_string()))?;
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let verifier = PinnedCertificate {
+        digest,
+        provider: provider.clone(),
+    };
+    let config = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|e| RemoteError::ConnectError(e.to_string()))?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+    Ok(config)
+}
+
+// Trusts exactly one certificate, whatever name or issuer it has. Quartz
+// servers use self-signed certificates that no CA would vouch for.
+#[derive(Debug)]
+struct PinnedCertificate {
+    digest: Vec<u8>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedCertificate {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if Sha256::digest(end_entity.as_ref()).as_slice() == self.digest.as_slice() {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General(FINGERPRINT_MISMATCH.into()))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+fn download_error(e: std::io::Error) -> RemoteError {
+    RemoteError::DownloadError(e.to_string())
+}
+
+// reqwest keeps the interesting part, like a refused connection or a TLS
+// failure, in the error's sources
+pub fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        message.push_str(": ");
+        message.push_str(&error.to_string());
+        source = error.source();
+    }
+    message
+}
@@ -0,0 +1,468 @@
+//Uses
+use crate::auth::{generate_token, unix_time, SharedPolicy};
+use crate::listing::{normalize_path, ShareMap, STAGING_DIR};
+use crate::manifest::encode_uri_component;
+use crate::types::{ConflictPolicy, LinkedPath, UploadSession, UploadedFile};
+use crate::uploads::{
+    audit_upload, conflict_policy, method, place, reserve_quota, resolve_entry, writable,
+    writable_entry, write_request, WriteError, WriteRequest,
+};
+use futures_util::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex as StdMutex;
+use std::time::{Duration, SystemTime};
+use tokio::io::AsyncWriteExt;
+use warp::filters::BoxedFilter;
+use warp::http::{header, Method, StatusCode};
+use warp::hyper::body::Buf;
+use warp::{Filter, Reply};
+
+// Uploads nobody touched for this long are removed with their partial file
+const SESSION_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+const OFFSET_HEADER: &str = "upload-offset";
+const LENGTH_HEADER: &str = "upload-length";
+const CHECKSUM_HEADER: &str = "upload-checksum";
+
+// Partial files of sessions that are receiving a chunk right now, so two
+// connections can't append to the same upload at once
+lazy_static::lazy_static! {
+    static ref ACTIVE_SESSIONS: StdMutex<HashSet<PathBuf>> = StdMutex::new(HashSet::new());
+}
+
+// What `<id>.json` next to the partial file remembers about an upload, so
+// sessions survive restarts of the server
+#[derive(Serialize, Deserialize)]
+struct StoredSession {
+    path: String,
+    size: u64,
+    sha256: Option<String>,
+    conflict: ConflictPolicy,
+    // Only the grant that started an upload may continue it
+    grant_id: Option<String>,
+    created_at: u64,
+}
+
+#[derive(Deserialize)]
+struct CreateBody {
+    size: u64,
+    sha256: Option<String>,
+}
+
+struct Session {
+    id: String,
+    staging: PathBuf,
+    stored: StoredSession,
+}
+
+impl Session {
+    fn part_path(&self) -> PathBuf {
+        self.staging.join(format!("{}.part", self.id))
+    }
+
+    fn meta_path(&self) -> PathBuf {
+        self.staging.join(format!("{}.json", self.id))
+    }
+
+    fn describe(&self, offset: u64) -> UploadSession {
+        UploadSession {
+            id: self.id.clone(),
+            path: self.stored.path.clone(),
+            size: self.stored.size,
+            offset,
+            sha256: self.stored.sha256.clone(),
+        }
+    }
+
+    fn remove(&self) {
+        let _ = fs::remove_file(self.part_path());
+        let _ = fs::remove_file(self.meta_path());
+    }
+}
+
+// Held while a chunk is written to a session
+struct SessionGuard(PathBuf);
+
+impl SessionGuard {
+    fn acquire(part: PathBuf) -> Option<Self> {
+        ACTIVE_SESSIONS
+            .lock()
+            .unwrap()
+            .insert(part.clone())
+            .then_some(SessionGuard(part))
+    }
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        ACTIVE_SESSIONS.lock().unwrap().remove(&self.0);
+    }
+}
+
+enum ChunkOutcome {
+    // More chunks expected, the next one starts at this offset
+    Stored(u64),
+    // The chunk didn't start where the upload stands, nothing was written
+    OffsetMismatch(u64),
+    Complete(UploadedFile),
+}
+
+// Offset based resumable uploads for large files over unreliable links:
+// - `POST /api/v1/uploads/<share>/<path>?conflict=` with `{"size": n,
+//   "sha256": "<hex>"}` starts an upload and answers with its session URL
+// - `PATCH <session>` with `Upload-Offset: n` appends a chunk. A chunk cut
+//   off by the connection keeps what arrived unless it came with
+//   `Upload-Checksum: sha256 <hex>`, then it is only kept if it matches.
+// - `HEAD`/`GET <session>` tell where to resume, `DELETE <session>` cancels
+// Chunks are staged inside the share and the finished file is checked
+// against `sha256` before it is moved into place.
+pub fn resumable_routes(
+    shares: ShareMap,
+    access: SharedPolicy,
+    network: String,
+) -> BoxedFilter<(warp::reply::Response,)> {
+    let session =
+        warp::path!("api" / "v1" / "uploads" / ..).and(write_request(shares, access, network));
+
+    let create = method(Method::POST)
+        .and(session.clone())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::body::json::<CreateBody>())
+        .then(create_session);
+    let status = method(Method::GET)
+        .or(method(Method::HEAD))
+        .unify()
+        .and(session.clone())
+        .then(session_status);
+    let append = method(Method::PATCH)
+        .and(session.clone())
+        .and(warp::header::optional::<u64>(OFFSET_HEADER))
+        .and(warp::header::optional::<String>(CHECKSUM_HEADER))
+        .and(warp::body::stream())
+        .then(append_chunk);
+    let cancel = method(Method::DELETE).and(session).then(cancel_session);
+
+    create
+        .or(status)
+        .unify()
+        .or(append)
+        .unify()
+        .or(cancel)
+        .unify()
+        .boxed()
+}
+
+async fn create_session(
+    request: WriteRequest,
+    query: HashMap<String, String>,
+    body: CreateBody,
+) -> warp::reply::Response {
+    let result = async {
+        let conflict = conflict_policy(&query)?;
+        let entry = writable_entry(&request)?;
+        let sha256 = body.sha256.as_deref().map(parse_sha256).transpose()?;
+        let mut reservation = reserve_quota(&request.share, &entry, conflict).await?;
+        reservation.reserve_total(body.size)?;
+
+        let stored = StoredSession {
+            path: entry.relative(&entry.name),
+            size: body.size,
+            sha256,
+            conflict,
+            grant_id: request.access.grant_id.clone(),
+            created_at: unix_time(),
+        };
+        let share = request.share.clone();
+        let session = tauri::async_runtime::spawn_blocking(move || start_session(&share, stored))
+            .await
+            .map_err(|e| WriteError::Io(io::Error::other(e.to_string())))??;
+        // The whole upload counts right away, so sessions started at the same
+        // time can't promise each other the same free space
+        reservation.commit(body.size);
+        Ok::<_, WriteError>(session)
+    }
+    .await;
+
+    match result {
+        Ok(session) => {
+            let location = format!(
+                "/api/v1/uploads/{}/{}",
+                encode_uri_component(&request.share.name),
+                session.id
+            );
+            let reply = warp::reply::with_status(warp::reply::json(&session), StatusCode::CREATED);
+            warp::reply::with_header(reply, header::LOCATION, location).into_response()
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+fn start_session(share: &LinkedPath, stored: StoredSession) -> Result<UploadSession, WriteError> {
+    let staging = fs::canonicalize(&share.path)?.join(STAGING_DIR);
+    fs::create_dir_all(&staging)?;
+    remove_stale_sessions(&staging);
+
+    let session = Session {
+        id: generate_token(),
+        staging,
+        stored,
+    };
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(session.part_path())?;
+    fs::write(
+        session.meta_path(),
+        serde_json::to_vec(&session.stored).map_err(io::Error::other)?,
+    )?;
+    Ok(session.describe(0))
+}
+
+// Judged by the last write to the partial file, so slow uploads that keep
+// making progress are left alone
+fn remove_stale_sessions(staging: &Path) {
+    let Ok(entries) = fs::read_dir(staging) else {
+        return;
+    };
+    let now = SystemTime::now();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().is_none_or(|extension| extension != "part") {
+            continue;
+        }
+        let idle = entry
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok());
+        if idle.is_some_and(|idle| idle > SESSION_TTL) {
+            let _ = fs::remove_file(&path);
+            let _ = fs::remove_file(path.with_extension("json"));
+        }
+    }
+}
+
+async fn session_status(request: WriteRequest) -> warp::reply::Response {
+    let result = load_session(&request).and_then(|session| {
+        let offset = fs::metadata(session.part_path())?.len();
+        Ok(session.describe(offset))
+    });
+    match result {
+        Ok(session) => {
+            let mut response = warp::reply::json(&session).into_response();
+            let headers = response.headers_mut();
+            headers.insert(OFFSET_HEADER, session.offset.into());
+            headers.insert(LENGTH_HEADER, session.size.into());
+            headers.insert(
+                header::CACHE_CONTROL,
+                header::HeaderValue::from_static("no-store"),
+            );
+            response
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+async fn append_chunk<S, B>(
+    request: WriteRequest,
+    offset: Option<u64>,
+    checksum: Option<String>,
+    body: S,
+) -> warp::reply::Response
+where
+    S: Stream<Item = Result<B, warp::Error>>,
+    B: Buf,
+{
+    let result = async {
+        let session = load_session(&request)?;
+        let offset = offset
+            .ok_or_else(|| WriteError::InvalidRequest("Upload-Offset header is required".into()))?;
+        let checksum = checksum.as_deref().map(parse_checksum).transpose()?;
+        let _guard = SessionGuard::acquire(session.part_path()).ok_or_else(|| {
+            WriteError::Conflict("Another chunk of this upload is being written".into())
+        })?;
+        let outcome = write_chunk(&session, offset, checksum, body).await?;
+        let ChunkOutcome::Stored(offset) = outcome else {
+            return Ok(outcome);
+        };
+        if offset < session.stored.size {
+            return Ok(outcome);
+        }
+
+        let path = session.stored.path.clone();
+        let share = request.share.clone();
+        let finished =
+            tauri::async_runtime::spawn_blocking(move || finish_upload(&share, &session))
+                .await
+                .map_err(|e| WriteError::Io(io::Error::other(e.to_string())))?;
+        audit_upload(&request, &path, finished.as_ref());
+        finished.map(ChunkOutcome::Complete)
+    }
+    .await;
+
+    match result {
+        Ok(ChunkOutcome::Stored(offset)) => {
+            let reply = warp::reply::with_status("", StatusCode::NO_CONTENT);
+            warp::reply::with_header(reply, OFFSET_HEADER, offset).into_response()
+        }
+        Ok(ChunkOutcome::OffsetMismatch(offset)) => {
+            let message = format!("Upload is at offset {}", offset);
+            let reply = warp::reply::with_status(message, StatusCode::CONFLICT);
+            warp::reply::with_header(reply, OFFSET_HEADER, offset).into_response()
+        }
+        Ok(ChunkOutcome::Complete(file)) => {
+            let reply = warp::reply::with_status(warp::reply::json(&file), StatusCode::CREATED);
+            warp::reply::with_header(reply, OFFSET_HEADER, file.size).into_response()
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+async fn write_chunk<S, B>(
+    session: &Session,
+    offset: u64,
+    checksum: Option<String>,
+    body: S,
+) -> Result<ChunkOutcome, WriteError>
+where
+    S: Stream<Item = Result<B, warp::Error>>,
+    B: Buf,
+{
+    let mut file = tokio::fs::OpenOptions::new()
+        .append(true)
+        .open(session.part_path())
+        .await?;
+    let current = file.metadata().await?.len();
+    if offset != current {
+        return Ok(ChunkOutcome::OffsetMismatch(current));
+    }
+
+    let limit = session.stored.size - current;
+    let mut hasher = Sha256::new();
+    let mut written = 0u64;
+    let mut interrupted = false;
+    let mut body = std::pin::pin!(body);
+    let streamed = async {
+        while let Some(chunk) = body.next().await {
+            let mut chunk = chunk.map_err(|e| {
+                interrupted = true;
+                WriteError::InvalidRequest(format!("Upload interrupted: {}", e))
+            })?;
+            if written + chunk.remaining() as u64 > limit {
+                return Err(WriteError::InvalidRequest(
+                    "Chunk goes past the size of the upload".into(),
+                ));
+            }
+            while chunk.has_remaining() {
+                let bytes = chunk.chunk();
+                hasher.update(bytes);
+                file.write_all(bytes).await?;
+                written += bytes.len() as u64;
+                let len = bytes.len();
+                chunk.advance(len);
+            }
+        }
+        Ok(())
+    }
+    .await;
+
+    let keep = match (&streamed, &checksum) {
+        (Ok(()), Some(expected)) => format!("{:x}", hasher.finalize()) == *expected,
+        (Ok(()), None) => true,
+        // Without a checksum a cut off chunk still counts, the client resumes
+        // after the last byte that arrived
+        (Err(_), None) => interrupted,
+        (Err(_), Some(_)) => false,
+    };
+    if !keep {
+        file.set_len(current).await?;
+        streamed?;
+        return Err(WriteError::ChecksumMismatch);
+    }
+    // Whatever is kept has to be on disk before the offset is reported
+    file.sync_data().await?;
+    streamed?;
+    Ok(ChunkOutcome::Stored(current + written))
+}
+
+fn finish_upload(share: &LinkedPath, session: &Session) -> Result<UploadedFile, WriteError> {
+    let part = session.part_path();
+    if let Some(expected) = &session.stored.sha256 {
+        let mut hasher = Sha256::new();
+        io::copy(&mut fs::File::open(&part)?, &mut hasher)?;
+        if format!("{:x}", hasher.finalize()) != *expected {
+            // Resuming can't repair it, the client has to start over
+            session.remove();
+            return Err(WriteError::ChecksumMismatch);
+        }
+    }
+
+    let entry = resolve_entry(share, &session.stored.path)?;
+    let name = place(&part, &entry.dir, &entry.name, session.stored.conflict)?;
+    let _ = fs::remove_file(session.meta_path());
+    Ok(UploadedFile {
+        path: entry.relative(&name),
+        size: session.stored.size,
+    })
+}
+
+async fn cancel_session(request: WriteRequest) -> warp::reply::Response {
+    let result = load_session(&request).and_then(|session| {
+        let _guard = SessionGuard::acquire(session.part_path()).ok_or_else(|| {
+            WriteError::Conflict("A chunk of this upload is being written".into())
+        })?;
+        session.remove();
+        Ok(())
+    });
+    match result {
+        Ok(()) => warp::reply::with_status("", StatusCode::NO_CONTENT).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+// Sessions of other grants look like they don't exist
+fn load_session(request: &WriteRequest) -> Result<Session, WriteError> {
+    writable(&request.share)?;
+    let id = normalize_path(&request.relative);
+    if id.len() != 64 || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(WriteError::NotFound);
+    }
+    let staging = fs::canonicalize(&request.share.path)?.join(STAGING_DIR);
+    let data = fs::read(staging.join(format!("{}.json", id)))?;
+    let stored: StoredSession = serde_json::from_slice(&data).map_err(|_| WriteError::NotFound)?;
+    if stored.grant_id != request.access.grant_id {
+        return Err(WriteError::NotFound);
+    }
+    Ok(Session {
+        id,
+        staging,
+        stored,
+    })
+}
+
+pub fn parse_sha256(value: &str) -> Result<String, WriteError> {
+    let value = value.trim().to_ascii_lowercase();
+    if value.len() == 64 && value.bytes().all(|b| b.is_ascii_hexdigit()) {
+        Ok(value)
+    } else {
+        Err(WriteError::InvalidRequest(
+            "sha256 must be 64 hex digits".into(),
+        ))
+    }
+}
+
+// `Upload-Checksum: sha256 <hex>`
+fn parse_checksum(value: &str) -> Result<String, WriteError> {
+    match value.trim().split_once(' ') {
+        Some((algorithm, digest)) if algorithm.eq_ignore_ascii_case("sha256") => {
+            parse_sha256(digest)
+        }
+        _ => Err(WriteError::InvalidRequest(
+            "Upload-Checksum must be 'sha256 <hex>'".into(),
+        )),
+    }
+}
@@ -0,0 +1,563 @@
+//Uses
+use crate::auth::{authorize, Access, SharedPolicy};
+use crate::catalog::{subscribe, with_catalog};
+use crate::config::{network_data_dir, write_atomic, ConfigStore};
+use crate::listing::{is_plain_name, ShareMap};
+use crate::types::{
+    EntryKind, FileError, IndexedFile, LinkedPath, SearchHit, SearchMatch, SearchResults, TextIndex,
+};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::Mutex as StdMutex;
+use tauri::{AppHandle, Emitter};
+use tokio::sync::Notify;
+use warp::filters::BoxedFilter;
+use warp::http::StatusCode;
+use warp::{Filter, Rejection, Reply};
+
+const TEXT_INDEX_FILE_NAME: &str = "text.json";
+const DEFAULT_LIMIT: usize = 50;
+const MAX_LIMIT: usize = 500;
+// Bigger files are left out of the full-text index
+const MAX_TEXT_FILE_SIZE: u64 = 256 * 1024 * 1024;
+// Only this much of a file's text is indexed
+const MAX_TEXT_LEN: usize = 16 * 1024 * 1024;
+const MIN_WORD_LEN: usize = 2;
+const MAX_WORD_LEN: usize = 40;
+// The index is written back every this many files while a linked path is
+// being indexed
+const SAVE_EVERY: usize = 100;
+const PLAIN_TEXT_EXTENSIONS: &[&str] = &[
+    "txt", "md", "markdown", "rst", "org", "tex", "csv", "tsv", "log", "json", "srt",
+];
+const MARKUP_EXTENSIONS: &[&str] = &["html", "htm", "xhtml", "xml"];
+
+// Full-text indexes by linked path name, loaded as they're needed
+lazy_static::lazy_static! {
+    static ref TEXT_INDEXES: StdMutex<HashMap<String, TextIndex>> =
+        StdMutex::new(HashMap::new());
+    // Wakes the indexer when full-text search is turned on or off
+    static ref SETTINGS_CHANGED: Notify = Notify::new();
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    q: String,
+    // Only search this share
+    share: Option<String>,
+    // Also match file contents, in shares with full-text search enabled
+    #[serde(default)]
+    content: bool,
+    limit: Option<usize>,
+}
+
+#[derive(Clone, Copy)]
+enum TextFormat {
+    Plain,
+    Markup,
+    Pdf,
+    Epub,
+}
+
+// Finds files and folders whose name fuzzily matches `query` across all
+// linked paths, or the ones listed. With `content`, files containing every
+// word of the query count as well.
+#[tauri::command]
+pub async fn search_files(
+    query: String,
+    linked_paths: Option<Vec<String>>,
+    content: Option<bool>,
+    limit: Option<usize>,
+) -> Result<SearchResults, FileError> {
+    let store = ConfigStore::load()?;
+    let shares: Vec<LinkedPath> = store
+        .linked_paths()
+        .iter()
+        .filter(|share| {
+            linked_paths
+                .as_ref()
+                .is_none_or(|names| names.contains(&share.name))
+        })
+        .cloned()
+        .collect();
+    tauri::async_runtime::spawn_blocking(move || {
+        search(&shares, &query, content.unwrap_or(false), limit)
+    })
+    .await
+    .map_err(|e| FileError::FileOpenError(io::Error::other(e)))
+}
+
+// Indexes the text of a linked path's files so searches with `content` find
+// them, or drops its index
+#[tauri::command]
+pub fn set_full_text_search(
+    app: AppHandle,
+    path_name: String,
+    enabled: bool,
+    revision: u64,
+) -> Result<String, FileError> {
+    ConfigStore::update(Some(revision), |store| {
+        store.update_linked_path(&path_name, |linked_path| {
+            linked_path.full_text = enabled;
+        })
+    })?;
+    SETTINGS_CHANGED.notify_one();
+
+    if let Err(e) = app.emit("linked_paths_changed", ()) {
+        eprintln!("Failed to emit event to frontend: {}", e);
+    }
+    Ok("Full-text search updated".to_string())
+}
+
+// `GET /api/v1/search?q=&share=&content=true&limit=`, over the shares the
+// request may read
+pub fn search_route(
+    shares: ShareMap,
+    access: SharedPolicy,
+) -> BoxedFilter<(warp::reply::Response,)> {
+    warp::get()
+        .and(warp::path!("api" / "v1" / "search"))
+        .and(authorize(access))
+        .and(warp::query::<SearchQuery>())
+        .and_then(move |access: Access, query: SearchQuery| {
+            let shares = shares.clone();
+            async move {
+                let visible: Vec<LinkedPath> = shares
+                    .values()
+                    .filter(|share| {
+                        access.allows(&share.name)
+                            && query.share.as_ref().is_none_or(|name| name == &share.name)
+                    })
+                    .cloned()
+                    .collect();
+                let results = tauri::async_runtime::spawn_blocking(move || {
+                    search(&visible, &query.q, query.content, query.limit)
+                })
+                .await;
+                match results {
+                    Ok(results) => Ok::<_, Rejection>(warp::reply::json(&results).into_response()),
+                    Err(_) => Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response()),
+                }
+            }
+        })
+        .boxed()
+}
+
+// Searches the catalogs, and full-text indexes with `content`, of `shares`.
+// Hidden files are only found in shares that show them.
+pub fn search(
+    shares: &[LinkedPath],
+    query: &str,
+    content: bool,
+    limit: Option<usize>,
+) -> SearchResults {
+    let query = query.to_lowercase();
+    let terms: Vec<&str> = query.split_whitespace().collect();
+    let query_words: Vec<String> = if content { words(&query) } else { Vec::new() };
+    let mut hits = Vec::new();
+    if terms.is_empty() {
+        return SearchResults { total: 0, hits };
+    }
+
+    for share in shares {
+        let containing = (!query_words.is_empty())
+            .then(|| files_containing(&share.name, &query_words))
+            .flatten()
+            .unwrap_or_default();
+        with_catalog(&share.name, |catalog| {
+            for (relative, entry) in &catalog.entries {
+                if !visible(share, relative) {
+                    continue;
+                }
+                let name = relative.rsplit('/').next().unwrap_or(relative);
+                let name_score = name_score(&terms, name);
+                let in_content = containing.contains(relative.as_str());
+                let (score, matched) = match (name_score, in_content) {
+                    (Some(score), false) => (score, SearchMatch::Name),
+                    (Some(score), true) => (score + 50, SearchMatch::Both),
+                    (None, true) => (20, SearchMatch::Content),
+                    (None, false) => continue,
+                };
+                hits.push(SearchHit {
+                    share: share.name.clone(),
+                    path: relative.clone(),
+                    kind: entry.kind,
+                    size: entry.size,
+                    modified: entry.modified,
+                    score,
+                    matched,
+                });
+            }
+        });
+    }
+
+    hits.sort_by(|a, b| {
+        b.score
+            .cmp(&a.score)
+            .then(a.path.len().cmp(&b.path.len()))
+            .then_with(|| a.share.cmp(&b.share))
+            .then_with(|| a.path.cmp(&b.path))
+    });
+    let total = hits.len();
+    hits.truncate(limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT));
+    SearchResults { total, hits }
+}
+
+// Names served by the share's server, everything else is left out
+fn visible(share: &LinkedPath, relative: &str) -> bool {
+    relative
+        .split('/')
+        .all(|segment| is_plain_name(segment) && (share.show_hidden || !segment.starts_with('.')))
+}
+
+// Every term has to match, the scores add up
+fn name_score(terms: &[&str], name: &str) -> Option<u32> {
+    let name = name.to_lowercase();
+    terms.iter().map(|term| fuzzy_score(term, &name)).sum()
+}
+
+// How well `term` matches `name`, both lowercase. None unless the term's
+// characters all appear in the name in order. Runs of consecutive
+// characters and matches at the start of words score higher, the term
+// appearing as is highest.
+fn fuzzy_score(term: &str, name: &str) -> Option<u32> {
+    let term_len = term.chars().count() as u32;
+    if let Some(at) = name.find(term) {
+        let word_start = name[..at]
+            .chars()
+            .next_back()
+            .is_none_or(|c| !c.is_alphanumeric());
+        return Some(100 + term_len * 10 + if word_start { 40 } else { 0 });
+    }
+
+    let chars: Vec<char> = name.chars().collect();
+    let mut score = 0;
+    let mut next = 0;
+    let mut previous: Option<usize> = None;
+    for c in term.chars() {
+        let found = next + chars[next..].iter().position(|x| *x == c)?;
+        score += 1;
+        if previous.is_some_and(|previous| previous + 1 == found) {
+            score += 5;
+        }
+        if found == 0 || !chars[found - 1].is_alphanumeric() {
+            score += 8;
+        }
+        previous = Some(found);
+        next = found + 1;
+    }
+    Some(score)
+}
+
+// Lowercased words as the full-text index stores them
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| (MIN_WORD_LEN..=MAX_WORD_LEN).contains(&word.chars().count()))
+        .map(str::to_lowercase)
+        .collect()
+}
+
+// Paths of the files containing every one of `words`, None if the linked
+// path has no full-text index
+fn files_containing(linked_path: &str, words: &[String]) -> Option<HashSet<String>> {
+    let indexes = TEXT_INDEXES.lock().unwrap();
+    let index = indexes.get(linked_path)?;
+    let mut ids: Option<HashSet<u32>> = None;
+    for word in words {
+        let with_word: HashSet<u32> = index
+            .words
+            .get(word)
+            .map(|ids| ids.iter().copied().collect())
+            .unwrap_or_default();
+        ids = Some(match ids {
+            Some(ids) => ids.intersection(&with_word).copied().collect(),
+            None => with_word,
+        });
+    }
+    let ids = ids.unwrap_or_default();
+    Some(
+        index
+            .files
+            .iter()
+            .filter(|(_, file)| ids.contains(&file.id))
+            .map(|(relative, _)| relative.clone())
+            .collect(),
+    )
+}
+
+// Keeps the full-text index of every linked path that has it enabled in
+// step with its catalog, which the file watcher keeps up to date
+pub async fn run_text_indexer() {
+    let mut updates = subscribe();
+    loop {
+        if let Err(e) = tauri::async_runtime::spawn_blocking(update_text_indexes).await {
+            eprintln!("Failed to update full-text indexes: {}", e);
+        }
+        tokio::select! {
+            _ = updates.changed() => {}
+            _ = SETTINGS_CHANGED.notified() => {}
+        }
+    }
+}
+
+fn text_index_path(linked_path: &str) -> std::path::PathBuf {
+    network_data_dir("catalogs", linked_path).join(TEXT_INDEX_FILE_NAME)
+}
+
+fn update_text_indexes() {
+    let store = match ConfigStore::load() {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("Failed to load linked paths for full-text search: {}", e);
+            return;
+        }
+    };
+    let enabled: Vec<&LinkedPath> = store
+        .linked_paths()
+        .iter()
+        .filter(|share| share.full_text)
+        .collect();
+
+    // Unlinked, or with full-text search turned off
+    TEXT_INDEXES
+        .lock()
+        .unwrap()
+        .retain(|name, _| enabled.iter().any(|share| &share.name == name));
+    for share in store.linked_paths().iter().filter(|share| !share.full_text) {
+        if let Err(e) = fs::remove_file(text_index_path(&share.name)) {
+            if e.kind() != io::ErrorKind::NotFound {
+                eprintln!("Failed to remove full-text index of {}: {}", share.name, e);
+            }
+        }
+    }
+
+    for share in enabled {
+        update_text_index(share);
+    }
+}
+
+// Reads the text of files that are new or changed since they were indexed,
+// and forgets the ones that are gone
+fn update_text_index(share: &LinkedPath) {
+    // Nothing to compare against until the catalog exists
+    let Some(wanted) = with_catalog(&share.name, |catalog| {
+        catalog
+            .entries
+            .iter()
+            .filter(|(relative, entry)| {
+                entry.kind == EntryKind::File
+                    && entry.size <= MAX_TEXT_FILE_SIZE
+                    && text_format(relative).is_some()
+            })
+            .map(|(relative, entry)| (relative.clone(), (entry.size, entry.modified)))
+            .collect::<HashMap<String, (u64, Option<u64>)>>()
+    }) else {
+        return;
+    };
+
+    let missing: Vec<(String, (u64, Option<u64>))> = {
+        let mut indexes = TEXT_INDEXES.lock().unwrap();
+        let index = indexes
+            .entry(share.name.clone())
+            .or_insert_with(|| load_text_index(&share.name));
+        let stale: Vec<String> = index
+            .files
+            .iter()
+            .filter(|(relative, file)| wanted.get(*relative) != Some(&(file.size, file.modified)))
+            .map(|(relative, _)| relative.clone())
+            .collect();
+        index.remove(&stale);
+        let missing: Vec<_> = wanted
+            .into_iter()
+            .filter(|(relative, _)| !index.files.contains_key(relative))
+            .collect();
+        if !stale.is_empty() && missing.is_empty() {
+            save_text_index(&share.name, index);
+        }
+        missing
+    };
+
+    for (i, (relative, (size, modified))) in missing.iter().enumerate() {
+        let path = relative
+            .split('/')
+            .fold(share.path.clone(), |path, segment| path.join(segment));
+        let format = text_format(relative).unwrap_or(TextFormat::Plain);
+        // Files that can't be read are recorded without words, and tried
+        // again once they change
+        let text = extract_text(&path, format).unwrap_or_else(|e| {
+            eprintln!("Failed to read text of {}: {}", path.display(), e);
+            String::new()
+        });
+
+        let mut indexes = TEXT_INDEXES.lock().unwrap();
+        // Turned off or unlinked meanwhile
+        let Some(index) = indexes.get_mut(&share.name) else {
+            return;
+        };
+        index.add(relative, *size, *modified, &text);
+        if (i + 1) % SAVE_EVERY == 0 || i + 1 == missing.len() {
+            save_text_index(&share.name, index);
+        }
+    }
+}
+
+fn load_text_index(linked_path: &str) -> TextIndex {
+    let data = match fs::read(text_index_path(linked_path)) {
+        Ok(data) => data,
+        Err(e) => {
+            if e.kind() != io::ErrorKind::NotFound {
+                eprintln!("Failed to read full-text index of {}: {}", linked_path, e);
+            }
+            return TextIndex::default();
+        }
+    };
+    serde_json::from_slice(&data).unwrap_or_else(|e| {
+        eprintln!("Failed to parse full-text index of {}: {}", linked_path, e);
+        TextIndex::default()
+    })
+}
+
+fn save_text_index(linked_path: &str, index: &TextIndex) {
+    let path = text_index_path(linked_path);
+    let result = serde_json::to_vec(index)
+        .map_err(FileError::from)
+        .and_then(|data| {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            write_atomic(&path, &data)
+        });
+    if let Err(e) = result {
+        eprintln!("Failed to save full-text index of {}: {}", linked_path, e);
+    }
+}
+
+impl TextIndex {
+    fn add(&mut self, relative: &str, size: u64, modified: Option<u64>, text: &str) {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.files
+            .insert(relative.to_string(), IndexedFile { id, size, modified });
+        let unique: HashSet<String> = words(text).into_iter().collect();
+        for word in unique {
+            // Ids only grow, so the lists stay sorted
+            self.words.entry(word).or_default().push(id);
+        }
+    }
+
+    fn remove(&mut self, relatives: &[String]) {
+        let ids: HashSet<u32> = relatives
+            .iter()
+            .filter_map(|relative| self.files.remove(relative))
+            .map(|file| file.id)
+            .collect();
+        if ids.is_empty() {
+            return;
+        }
+        self.words.retain(|_, files| {
+            files.retain(|id| !ids.contains(id));
+            !files.is_empty()
+        });
+    }
+}
+
+fn text_format(relative: &str) -> Option<TextFormat> {
+    let extension = Path::new(relative)
+        .extension()?
+        .to_string_lossy()
+        .to_lowercase();
+    match extension.as_str() {
+        "pdf" => Some(TextFormat::Pdf),
+        "epub" => Some(TextFormat::Epub),
+        extension if PLAIN_TEXT_EXTENSIONS.contains(&extension) => Some(TextFormat::Plain),
+        extension if MARKUP_EXTENSIONS.contains(&extension) => Some(TextFormat::Markup),
+        _ => None,
+    }
+}
+
+fn extract_text(path: &Path, format: TextFormat) -> io::Result<String> {
+    match format {
+        TextFormat::Plain => read_text(fs::File::open(path)?),
+        TextFormat::Markup => Ok(strip_markup(&read_text(fs::File::open(path)?)?)),
+        TextFormat::Pdf => {
+            let data = fs::read(path)?;
+            // pdf-extract panics on some malformed files
+            match std::panic::catch_unwind(|| pdf_extract::extract_text_from_mem(&data)) {
+                Ok(Ok(mut text)) => {
+                    truncate(&mut text);
+                    Ok(text)
+                }
+                Ok(Err(e)) => Err(io::Error::other(e.to_string())),
+                Err(_) => Err(io::Error::other("unreadable PDF")),
+            }
+        }
+        TextFormat::Epub => epub_text(path),
+    }
+}
+
+fn read_text(reader: impl Read) -> io::Result<String> {
+    let mut data = Vec::new();
+    reader.take(MAX_TEXT_LEN as u64).read_to_end(&mut data)?;
+    Ok(String::from_utf8_lossy(&data).into_owned())
+}
+
+// EPUBs are zip files of XHTML chapters
+fn epub_text(path: &Path) -> io::Result<String> {
+    let mut archive = zip::ZipArchive::new(fs::File::open(path)?).map_err(io::Error::other)?;
+    let mut text = String::new();
+    for i in 0..archive.len() {
+        if text.len() >= MAX_TEXT_LEN {
+            break;
+        }
+        let file = archive.by_index(i).map_err(io::Error::other)?;
+        let is_chapter = matches!(text_format(file.name()), Some(TextFormat::Markup));
+        if !is_chapter {
+            continue;
+        }
+        text.push_str(&strip_markup(&read_text(file)?));
+        text.push(' ');
+    }
+    truncate(&mut text);
+    Ok(text)
+}
+
+fn truncate(text: &mut String) {
+    if text.len() > MAX_TEXT_LEN {
+        let mut end = MAX_TEXT_LEN;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+    }
+}
+
+// Text of an HTML or XML document, with tags and entities left out
+fn strip_markup(markup: &str) -> String {
+    let mut text = String::with_capacity(markup.len());
+    let mut in_tag = false;
+    let mut in_entity = false;
+    for c in markup.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                text.push(' ');
+            }
+            '&' if !in_tag => in_entity = true,
+            ';' if in_entity => {
+                in_entity = false;
+                text.push(' ');
+            }
+            _ if in_entity && !c.is_alphanumeric() && c != '#' => {
+                in_entity = false;
+                text.push(c);
+            }
+            _ if !in_tag && !in_entity => text.push(c),
+            _ => {}
+        }
+    }
+    text
+}
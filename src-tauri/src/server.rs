@@ -1,15 +1,60 @@
-use crate::local_dir::PUBLIC_LINKEDPATH_FILE_PATH;
-use crate::types::{LinkedPath, ServerMode, ServerState};
-use std::fs::File;
-use std::io::prelude::*;
+use crate::archive::archive_route;
+use crate::auth::{authenticate, handle_rejection, policy_handle, SharedPolicy};
+use crate::config::ConfigStore;
+use crate::directory_index::directory_index_route;
+use crate::files::file_route;
+use crate::listing::{listing_route, share_map, tree_route};
+use crate::local_dir::DEFAULT_PORT;
+use crate::manifest::manifest_route;
+use crate::port_mapping::{self, PortMapping};
+use crate::resumable::resumable_routes;
+use crate::search::search_route;
+use crate::sync::sync_routes;
+use crate::tls::{load_identity, TlsIdentity};
+use crate::tor::{publish_onion_service, OnionService, TorControlConfig};
+use crate::types::{
+    LinkedPath, Network, RunningServer, ServerError, ServerMode, ServerState, ServerStatus,
+    ServerStatusEvent,
+};
+use crate::uploads::write_routes;
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
 use std::sync::Arc;
-use tauri::State;
+use tauri::{AppHandle, Emitter, State};
 use tokio::sync::mpsc::{self, Receiver};
 use tokio::sync::Mutex;
+use warp::filters::BoxedFilter;
 use warp::Filter;
 
+// Key of the server started through the legacy start/stop commands, which
+// aren't tied to a saved network
+const ADHOC_SERVER: &str = "";
+// How many ports after the configured one are tried before asking the OS for any free port
+const PORT_FALLBACK_ATTEMPTS: u16 = 10;
+
+type ServerFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+// Everything `file_server` needs to serve one network
+pub struct ServerConfig {
+    // Recorded in the audit log, empty for ad hoc servers
+    pub network: String,
+    pub mode: ServerMode,
+    pub linked_paths: Vec<LinkedPath>,
+    pub addr: SocketAddr,
+    pub tls: Option<TlsIdentity>,
+    // Checked on every request, grant changes apply while running
+    pub access: SharedPolicy,
+    // Render HTML pages for directories without an index.html
+    pub directory_index: bool,
+    // Publishes the server as an onion service through this Tor instance
+    pub tor_control: Option<TorControlConfig>,
+}
+
 #[tauri::command]
 pub async fn start_file_server_command(
+    app: AppHandle,
     server_mode: ServerMode,
     linked_paths: Vec<LinkedPath>,
     state: State<'_, Arc<Mutex<ServerState>>>,
@@ -17,78 +62,552 @@ pub async fn start_file_server_command(
     if linked_paths.is_empty() {
         return Ok("Choose linked paths to share".into());
     }
-    let mut server_state = state.lock().await;
-
-    // If the server is already running, return an error
-    if server_state.shutdown_tx.is_some() {
-        return Err("Server is already running.".into());
-    }
-    let (shutdown_tx, shutdown_rx) = mpsc::channel(100);
-    server_state.shutdown_tx = Some(shutdown_tx);
+    let server_config = ServerConfig {
+        network: ADHOC_SERVER.to_string(),
+        mode: server_mode,
+        linked_paths,
+        addr: SocketAddr::from(([127, 0, 0, 1], DEFAULT_PORT)),
+        tls: None,
+        access: SharedPolicy::default(),
+        directory_index: true,
+        tor_control: None,
+    };
+    start_server(
+        app,
+        state.inner().clone(),
+        ADHOC_SERVER.to_string(),
+        server_config,
+    )
+    .await
+    .map_err(|e| e.to_string())?;
 
-    tauri::async_runtime::spawn(async move {
-        file_server(server_mode, linked_paths, shutdown_rx).await;
-    });
     Ok("Server started!".into())
 }
 
 #[tauri::command]
 pub async fn stop_file_server_command(
+    app: AppHandle,
     state: State<'_, Arc<Mutex<ServerState>>>,
 ) -> Result<String, String> {
+    stop_server(&app, state.inner(), ADHOC_SERVER)
+        .await
+        .map_err(|_| "Server is not running.".to_string())?;
+
+    Ok("Server stopped.".into())
+}
+
+#[tauri::command]
+pub async fn start_network_server(
+    app: AppHandle,
+    network_name: String,
+    state: State<'_, Arc<Mutex<ServerState>>>,
+) -> Result<String, ServerError> {
+    let server_config = network_server_config(&network_name)?;
+    let bound_addr = start_server(app, state.inner().clone(), network_name, server_config).await?;
+
+    Ok(format!("Server running on {}", bound_addr))
+}
+
+#[tauri::command]
+pub async fn stop_network_server(
+    app: AppHandle,
+    network_name: String,
+    state: State<'_, Arc<Mutex<ServerState>>>,
+) -> Result<String, ServerError> {
+    stop_server(&app, state.inner(), &network_name).await?;
+
+    Ok("Server stopped".into())
+}
+
+#[tauri::command]
+pub async fn restart_network_server(
+    app: AppHandle,
+    network_name: String,
+    state: State<'_, Arc<Mutex<ServerState>>>,
+) -> Result<String, ServerError> {
+    // Re-read the network so a restart picks up config changes
+    let server_config = network_server_config(&network_name)?;
+    match stop_server(&app, state.inner(), &network_name).await {
+        Ok(()) | Err(ServerError::NotRunning(_)) => {}
+        Err(e) => return Err(e),
+    }
+    let bound_addr = start_server(app, state.inner().clone(), network_name, server_config).await?;
+
+    Ok(format!("Server running on {}", bound_addr))
+}
+
+#[tauri::command]
+pub async fn get_server_statuses(
+    state: State<'_, Arc<Mutex<ServerState>>>,
+) -> Result<HashMap<String, ServerStatus>, ServerError> {
+    let server_state = state.lock().await;
+    let statuses = server_state
+        .statuses
+        .iter()
+        .filter(|(name, _)| name.as_str() != ADHOC_SERVER)
+        .map(|(name, status)| (name.clone(), status.clone()))
+        .collect();
+
+    Ok(statuses)
+}
+
+// Fingerprint peers should pin when joining the network, None if the
+// network is not served over TLS
+#[tauri::command]
+pub fn get_certificate_fingerprint(network_name: String) -> Result<Option<String>, ServerError> {
+    let server_config = network_server_config(&network_name)?;
+    server_config
+        .tls
+        .map(|identity| identity.fingerprint())
+        .transpose()
+}
+
+fn network_server_config(network_name: &str) -> Result<ServerConfig, ServerError> {
+    let store = ConfigStore::load()?;
+    let network = store
+        .networks()
+        .iter()
+        .find(|network| network.name() == network_name)
+        .ok_or_else(|| ServerError::NetworkNotFound(network_name.to_string()))?;
+    let access = policy_handle(network);
+    let directory_index = network.directory_index();
+
+    match network {
+        Network::LocalNetwork {
+            name,
+            linked_paths,
+            port,
+            bind_address,
+            tls,
+            ..
+        } => Ok(ServerConfig {
+            network: name.clone(),
+            mode: ServerMode::LocalHost,
+            linked_paths: linked_paths.clone(),
+            addr: SocketAddr::new(bind_address.ip(), *port),
+            tls: load_identity(name, tls)?,
+            access,
+            directory_index,
+            tor_control: None,
+        }),
+        Network::InternetNetwork {
+            name,
+            linked_paths,
+            port,
+            access_token,
+            tls,
+            ..
+        } => {
+            if access_token.is_empty() {
+                return Err(ServerError::MissingAccessToken(name.clone()));
+            }
+            Ok(ServerConfig {
+                network: name.clone(),
+                mode: ServerMode::Internet,
+                linked_paths: linked_paths.clone(),
+                // Reachable from outside once the router forwards the port
+                addr: SocketAddr::from(([0, 0, 0, 0], *port)),
+                tls: load_identity(name, tls)?,
+                access,
+                directory_index,
+                tor_control: None,
+            })
+        }
+        Network::DarkWebNetwork {
+            name,
+            linked_paths,
+            tor_control_port,
+            tor_control_password,
+            ..
+        } => Ok(ServerConfig {
+            network: name.clone(),
+            mode: ServerMode::DarkWeb,
+            linked_paths: linked_paths.clone(),
+            // Only Tor connects to the server, the onion service forwards to it
+            addr: SocketAddr::from(([127, 0, 0, 1], 0)),
+            tls: None,
+            access,
+            directory_index,
+            tor_control: Some(TorControlConfig {
+                addr: SocketAddr::from(([127, 0, 0, 1], *tor_control_port)),
+                password: tor_control_password.clone(),
+            }),
+        }),
+    }
+}
+
+fn set_status(app: &AppHandle, server_state: &mut ServerState, name: &str, status: ServerStatus) {
+    server_state
+        .statuses
+        .insert(name.to_string(), status.clone());
+    if name == ADHOC_SERVER {
+        return;
+    }
+    let event = ServerStatusEvent {
+        network: name.to_string(),
+        status,
+    };
+    if let Err(e) = app.emit("server_status_changed", event) {
+        eprintln!("Failed to emit event to frontend: {}", e);
+    }
+}
+
+async fn start_server(
+    app: AppHandle,
+    state: Arc<Mutex<ServerState>>,
+    name: String,
+    mut server_config: ServerConfig,
+) -> Result<SocketAddr, ServerError> {
     let mut server_state = state.lock().await;
+    if server_state.servers.contains_key(&name) || server_state.starting.contains_key(&name) {
+        return Err(ServerError::AlreadyRunning(name));
+    }
+
+    let id = server_state.next_server_id;
+    server_state.next_server_id += 1;
+    let (shutdown_tx, shutdown_rx) = mpsc::channel(1);
+    set_status(&app, &mut server_state, &name, ServerStatus::Starting);
+
+    // Bind before spawning so bind errors are returned to the caller
+    let map_port = matches!(server_config.mode, ServerMode::Internet);
+    let tor_control = server_config.tor_control.take();
+    let (bound_addr, server_future) = match file_server(server_config, shutdown_rx) {
+        Ok(bound) => bound,
+        Err(e) => {
+            eprintln!("Server '{}' failed: {}", name, e);
+            let status = ServerStatus::Failed {
+                error: e.to_string(),
+            };
+            set_status(&app, &mut server_state, &name, status);
+            return Err(e);
+        }
+    };
+    println!("Server '{}' listening on {}", name, bound_addr);
+
+    // Publishing can take a while, other servers mustn't wait for it
+    server_state.starting.insert(name.clone(), id);
+    drop(server_state);
 
-    if let Some(shutdown_tx) = server_state.shutdown_tx.take() {
-        shutdown_tx
-            .send(())
+    // Dark web servers are only running once Tor accepted the onion service
+    let published = match tor_control {
+        Some(tor_control) => publish_onion_service(&name, &tor_control, bound_addr)
             .await
-            .expect("Failed to send shutdown signal");
-        Ok("Server stopped.".into())
+            .map(Some),
+        None => Ok(None),
+    };
+
+    let mut server_state = state.lock().await;
+    // Stopped in the meantime
+    if server_state.starting.get(&name) != Some(&id) {
+        drop(server_state);
+        if let Ok(Some(onion_service)) = published {
+            onion_service.remove().await;
+        }
+        return Err(ServerError::NotRunning(name));
+    }
+    server_state.starting.remove(&name);
+    let onion_service = match published {
+        Ok(onion_service) => onion_service,
+        Err(e) => {
+            eprintln!("Server '{}' failed: {}", name, e);
+            let status = ServerStatus::Failed {
+                error: e.to_string(),
+            };
+            set_status(&app, &mut server_state, &name, status);
+            return Err(e.into());
+        }
+    };
+    let public_address = onion_service.as_ref().map(OnionService::address);
+    if let Some(address) = &public_address {
+        println!("Server '{}' published as {}", name, address);
+        if let Err(e) = ConfigStore::update(None, |store| {
+            store.set_network_address(&name, address.clone())
+        }) {
+            eprintln!("Failed to store address of '{}': {}", name, e);
+        }
+    }
+    let status = ServerStatus::Running {
+        address: bound_addr.to_string(),
+        public_address,
+    };
+    set_status(&app, &mut server_state, &name, status);
+
+    let server_future = if let Some(onion_service) = onion_service {
+        Box::pin(serve_with_onion_service(onion_service, server_future))
+    } else if map_port {
+        Box::pin(serve_with_port_mapping(
+            app.clone(),
+            state.clone(),
+            name.clone(),
+            bound_addr,
+            server_future,
+        ))
     } else {
-        Err("Server is not running.".into())
+        server_future
+    };
+    let task = tauri::async_runtime::spawn(supervise_server(
+        app,
+        state.clone(),
+        id,
+        name.clone(),
+        server_future,
+    ));
+    server_state.servers.insert(
+        name,
+        RunningServer {
+            id,
+            shutdown_tx,
+            task,
+        },
+    );
+
+    Ok(bound_addr)
+}
+
+// Waits until the server task has finished, so a following start can reuse
+// the port. A server that is still starting is cancelled.
+async fn stop_server(
+    app: &AppHandle,
+    state: &Arc<Mutex<ServerState>>,
+    name: &str,
+) -> Result<(), ServerError> {
+    let mut server_state = state.lock().await;
+    let server = match server_state.servers.remove(name) {
+        Some(server) => server,
+        None if server_state.starting.remove(name).is_some() => {
+            set_status(app, &mut server_state, name, ServerStatus::Stopped);
+            return Ok(());
+        }
+        None => return Err(ServerError::NotRunning(name.to_string())),
+    };
+    drop(server_state);
+
+    // The task may already have exited after a failure
+    let _ = server.shutdown_tx.send(()).await;
+    if let Err(e) = server.task.await {
+        eprintln!("Server task for '{}' failed: {}", name, e);
     }
+
+    Ok(())
 }
 
-pub async fn file_server(
-    server_mode: ServerMode,
-    linked_paths: Vec<LinkedPath>,
-    mut shutdown_rx: Receiver<()>,
+// Serves until shutdown and then marks the server as stopped, or as failed
+// if it ended without being asked to
+async fn supervise_server(
+    app: AppHandle,
+    state: Arc<Mutex<ServerState>>,
+    id: u64,
+    name: String,
+    server_future: ServerFuture,
+) {
+    server_future.await;
+
+    let mut server_state = state.lock().await;
+    let status = match server_state.servers.get(&name) {
+        Some(server) if server.id == id => {
+            server_state.servers.remove(&name);
+            eprintln!("Server '{}' stopped unexpectedly", name);
+            ServerStatus::Failed {
+                error: "server stopped unexpectedly".to_string(),
+            }
+        }
+        // `stop_server` takes the server out before shutting it down
+        None if !server_state.starting.contains_key(&name) => ServerStatus::Stopped,
+        // A newer server has taken the name since
+        _ => return,
+    };
+    set_status(&app, &mut server_state, &name, status);
+}
+
+// Holds the Tor control connection open while serving and takes the onion
+// service down afterwards
+async fn serve_with_onion_service(onion_service: OnionService, server_future: ServerFuture) {
+    server_future.await;
+    onion_service.remove().await;
+}
+
+// Keeps a router port mapping alive for as long as the server runs and
+// records the external address on the network
+async fn serve_with_port_mapping(
+    app: AppHandle,
+    state: Arc<Mutex<ServerState>>,
+    name: String,
+    bound_addr: SocketAddr,
+    mut server_future: ServerFuture,
 ) {
-    let mut file =
-        File::create(PUBLIC_LINKEDPATH_FILE_PATH).expect("Failed to create private_paths file");
-    {
-        let linked_paths = serde_json::to_string_pretty::<Vec<LinkedPath>>(&linked_paths)
-            .expect("Failed to serialize linked paths");
-        file.write_all(linked_paths.as_bytes())
-            .expect("Failed to write to private_paths file");
+    // Keep serving while the gateway is being discovered
+    let mapped = tokio::select! {
+        _ = &mut server_future => return,
+        mapped = port_mapping::map_port(bound_addr.port()) => mapped,
+    };
+    let mut mapping: Option<PortMapping> = match mapped {
+        Ok((mapping, external_addr)) => {
+            println!("Server '{}' reachable on {}", name, external_addr);
+            let address = external_addr.to_string();
+            if let Err(e) = ConfigStore::update(None, |store| {
+                store.set_network_address(&name, address.clone())
+            }) {
+                eprintln!("Failed to store address of '{}': {}", name, e);
+            }
+            let status = ServerStatus::Running {
+                address: bound_addr.to_string(),
+                public_address: Some(address),
+            };
+            set_status(&app, &mut *state.lock().await, &name, status);
+            Some(mapping)
+        }
+        Err(e) => {
+            eprintln!(
+                "Server '{}' is not reachable from the internet: {}",
+                name, e
+            );
+            None
+        }
+    };
+
+    let mut renew_interval = tokio::time::interval_at(
+        tokio::time::Instant::now() + port_mapping::RENEW_INTERVAL,
+        port_mapping::RENEW_INTERVAL,
+    );
+    loop {
+        tokio::select! {
+            _ = &mut server_future => break,
+            _ = renew_interval.tick(), if mapping.is_some() => {
+                if let Some(mapping) = mapping.as_mut() {
+                    if let Err(e) = mapping.renew().await {
+                        eprintln!("Server '{}': {}", name, e);
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(mapping) = mapping.take() {
+        mapping.remove().await;
     }
+}
 
-    let default_route = warp::get()
-        .and(warp::path::end())
-        .and(warp::fs::file(PUBLIC_LINKEDPATH_FILE_PATH));
-    let mut combined_fs_routes = default_route.boxed();
-    for linked_path in linked_paths {
-        // Create a route for each Node
-        let route = warp::path(linked_path.name.clone())
-            .and(warp::fs::dir(linked_path.path.clone()))
-            .boxed();
-
-        // Combine all routes using the `or` combinator
-        combined_fs_routes = route.or(combined_fs_routes).unify().boxed();
+// Returns `addr` if its port is free, otherwise one of the next few ports,
+// otherwise lets the OS pick one
+fn find_free_addr(addr: SocketAddr) -> Result<SocketAddr, ServerError> {
+    let bind_error = |e: std::io::Error| ServerError::BindError(format!("{}: {}", addr, e));
+    for offset in 0..=PORT_FALLBACK_ATTEMPTS {
+        let Some(port) = addr.port().checked_add(offset) else {
+            break;
+        };
+        let candidate = SocketAddr::new(addr.ip(), port);
+        match std::net::TcpListener::bind(candidate) {
+            Ok(_) => return Ok(candidate),
+            Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {
+                println!("Port {} is taken", port);
+            }
+            Err(e) => return Err(bind_error(e)),
+        }
     }
 
-    let routes = combined_fs_routes;
-    match server_mode {
-        ServerMode::LocalHost => {
-            let (_, server_future) = warp::serve(routes).bind_with_graceful_shutdown(
-                ([127, 0, 0, 1], 3030),
-                async move {
-                    shutdown_rx.recv().await;
-                },
-            );
-            server_future.await;
+    Ok(SocketAddr::new(addr.ip(), 0))
+}
+
+// Binds the server and returns the address it listens on together with the
+// future that serves requests until `shutdown_rx` fires
+pub fn file_server(
+    server_config: ServerConfig,
+    mut shutdown_rx: Receiver<()>,
+) -> Result<(SocketAddr, ServerFuture), ServerError> {
+    let ServerConfig {
+        network,
+        mode,
+        linked_paths,
+        addr,
+        tls,
+        access,
+        directory_index,
+        ..
+    } = server_config;
+    match mode {
+        ServerMode::LocalHost => {}
+        ServerMode::Internet => {
+            // Never expose shares to the internet unencrypted or unauthenticated
+            if tls.is_none() || access.read().unwrap().is_open() {
+                return Err(ServerError::InsecureInternetServer);
+            }
+        }
+        ServerMode::DarkWeb => {
+            // Anything but loopback would bypass Tor
+            if !addr.ip().is_loopback() {
+                return Err(ServerError::ExposedDarkWebServer);
+            }
+        }
+    }
+
+    let routes = server_routes(network, linked_paths, access, directory_index);
+
+    let addr = find_free_addr(addr)?;
+    let shutdown_signal = async move {
+        shutdown_rx.recv().await;
+    };
+    let bind_error = |e: warp::Error| ServerError::BindError(format!("{}: {}", addr, e));
+    match tls {
+        Some(tls) => {
+            let (bound_addr, server_future) = warp::serve(routes)
+                .tls()
+                .cert(tls.cert_pem)
+                .key(tls.key_pem)
+                .try_bind_with_graceful_shutdown(addr, shutdown_signal)
+                .map_err(bind_error)?;
+            Ok((bound_addr, Box::pin(server_future)))
         }
-        ServerMode::Internet => {}
-        ServerMode::DarkWeb => {}
+        None => {
+            let (bound_addr, server_future) = warp::serve(routes)
+                .try_bind_with_graceful_shutdown(addr, shutdown_signal)
+                .map_err(bind_error)?;
+            Ok((bound_addr, Box::pin(server_future)))
+        }
+    }
+}
+
+// Every route a network's server answers, with rejections turned into responses
+pub fn server_routes(
+    network: String,
+    linked_paths: Vec<LinkedPath>,
+    access: SharedPolicy,
+    directory_index: bool,
+) -> BoxedFilter<(warp::reply::Response,)> {
+    let shares = share_map(linked_paths.clone());
+    let authenticated = authenticate(access.clone());
+    let mut combined_fs_routes = manifest_route(linked_paths, access.clone())
+        .or(listing_route(shares.clone(), access.clone()))
+        .unify()
+        .or(search_route(shares.clone(), access.clone()))
+        .unify()
+        .or(tree_route(shares.clone(), access.clone()))
+        .unify()
+        .or(archive_route(shares.clone(), access.clone()))
+        .unify()
+        .or(resumable_routes(
+            shares.clone(),
+            access.clone(),
+            network.clone(),
+        ))
+        .unify()
+        .or(sync_routes(shares.clone(), access.clone(), network.clone()))
+        .unify()
+        .or(write_routes(shares.clone(), access.clone(), network))
+        .unify()
+        .or(file_route(shares.clone(), access.clone()))
+        .unify()
+        .boxed();
+    // Tried last, directories holding an index.html are served as files
+    if directory_index {
+        let index_route = directory_index_route(shares, access);
+        combined_fs_routes = combined_fs_routes.or(index_route).unify().boxed();
     }
+
+    // Passwords are verified once here rather than in every route tried
+    authenticated
+        .and(combined_fs_routes)
+        .recover(handle_rejection)
+        .unify()
+        .map(warp::Reply::into_response)
+        .boxed()
 }
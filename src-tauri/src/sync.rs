@@ -0,0 +1,1481 @@
+//Uses
+use crate::auth::{generate_grant_id, unix_time, SharedPolicy};
+use crate::config::{config_dir, write_atomic, ConfigStore};
+use crate::listing::{
+    file_sha256, is_plain_name, is_reserved_name, modified_secs, normalize_path, ShareMap,
+    STAGING_DIR, SYNC_DIR,
+};
+use crate::local_dir::FsChangeSender;
+use crate::remote::{client, error_chain, remote_network, resource_url, send, share_path};
+use crate::resumable::parse_sha256;
+use crate::types::{
+    AuditAction, ConflictPolicy, FileError, FileVersion, LinkedPath, RemoteError, RemoteNetwork,
+    SyncFolder, SyncIndex, SyncStatus, UploadedFile, VersionVector,
+};
+use crate::uploads::{
+    audit, audit_upload, method, reserve_quota, writable, write_request, Entry, WriteError,
+    WriteRequest,
+};
+use futures_util::{Stream, StreamExt};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, UNIX_EPOCH};
+use tauri::{AppHandle, Emitter, Manager};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tokio::sync::{Mutex, Notify};
+use warp::filters::BoxedFilter;
+use warp::http::{Method, StatusCode};
+use warp::hyper::body::Buf;
+use warp::{Filter, Reply};
+
+// How often sync folders with an interval are checked for being due
+const SCHEDULER_TICK: Duration = Duration::from_secs(60);
+// Trashed files are removed for good after this long
+const TRASH_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+const INDEX_FILE_NAME: &str = "index.json";
+const TRASH_DIR: &str = "trash";
+const DEVICE_ID_FILE_NAME: &str = "device_id";
+// JSON version vector the peer last saw for the file here
+const BASE_HEADER: &str = "quartz-sync-base";
+// JSON FileVersion the peer sends
+const VERSION_HEADER: &str = "quartz-sync-version";
+
+lazy_static::lazy_static! {
+    static ref DEVICE_ID: StdMutex<Option<String>> = StdMutex::new(None);
+    // Last run of every sync folder since the app started, by id
+    static ref SYNC_STATUSES: StdMutex<HashMap<String, SyncStatus>> =
+        StdMutex::new(HashMap::new());
+    // One sync at a time per folder on disk, whether this app started it or
+    // a peer syncs with a share of it
+    static ref FOLDER_LOCKS: StdMutex<HashMap<PathBuf, Arc<Mutex<()>>>> =
+        StdMutex::new(HashMap::new());
+    // Wakes the scheduler up early, e.g. for new sync folders
+    static ref CHANGES: Notify = Notify::new();
+}
+
+// Starts keeping a linked path in step with a share of a joined network
+#[tauri::command]
+pub fn create_sync_folder(
+    app: AppHandle,
+    remote_network: String,
+    share: String,
+    linked_path: String,
+    interval_minutes: Option<u64>,
+    revision: u64,
+) -> Result<SyncFolder, FileError> {
+    if interval_minutes == Some(0) {
+        return Err(FileError::InvalidConfigError(
+            "sync interval must be at least a minute".into(),
+        ));
+    }
+    let folder = SyncFolder {
+        id: generate_grant_id(),
+        remote_network,
+        share,
+        linked_path,
+        interval_minutes,
+    };
+    ConfigStore::update(Some(revision), |store| {
+        store.add_sync_folder(folder.clone())
+    })?;
+    // The scheduler syncs new folders right away
+    CHANGES.notify_one();
+
+    if let Err(e) = app.emit("linked_paths_changed", ()) {
+        eprintln!("Failed to emit event to frontend: {}", e);
+    }
+    Ok(folder)
+}
+
+#[tauri::command]
+pub fn list_sync_folders() -> Result<Vec<SyncFolder>, FileError> {
+    let store = ConfigStore::load()?;
+    Ok(store.sync_folders().to_vec())
+}
+
+// Files and the folder's index stay where they are
+#[tauri::command]
+pub fn remove_sync_folder(
+    app: AppHandle,
+    sync_folder_id: String,
+    revision: u64,
+) -> Result<String, FileError> {
+    let removed = ConfigStore::update(Some(revision), |store| {
+        Ok(store.remove_sync_folder(&sync_folder_id))
+    })?;
+    if !removed {
+        return Ok("Sync folder not found".to_string());
+    }
+    SYNC_STATUSES.lock().unwrap().remove(&sync_folder_id);
+
+    if let Err(e) = app.emit("linked_paths_changed", ()) {
+        eprintln!("Failed to emit event to frontend: {}", e);
+    }
+    Ok("Sync folder removed successfully".to_string())
+}
+
+#[tauri::command]
+pub async fn sync_now(app: AppHandle, sync_folder_id: String) -> Result<SyncStatus, RemoteError> {
+    let store = ConfigStore::load()?;
+    let folder = store
+        .sync_folders()
+        .iter()
+        .find(|folder| folder.id == sync_folder_id)
+        .cloned()
+        .ok_or(RemoteError::SyncFolderNotFound(sync_folder_id))?;
+    sync(&app, &folder).await
+}
+
+#[tauri::command]
+pub fn get_sync_statuses() -> HashMap<String, SyncStatus> {
+    SYNC_STATUSES.lock().unwrap().clone()
+}
+
+// Runs for the lifetime of the app. Folders sync once at startup, after
+// the file watcher reports changes inside them and when their interval is
+// up. Changes met while a folder is syncing wait for the next wake up.
+pub async fn run_sync_scheduler(app: AppHandle) {
+    let mut changes = app.state::<FsChangeSender>().subscribe();
+    let mut watching = true;
+    let mut ticks = tokio::time::interval(SCHEDULER_TICK);
+    // Linked paths that changed, by name
+    let mut changed: HashSet<String> = HashSet::new();
+    let mut missed_changes = false;
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => {}
+            _ = CHANGES.notified() => {}
+            change = changes.recv(), if watching => match change {
+                Ok(change) => {
+                    changed.insert(change.linked_path);
+                }
+                Err(RecvError::Lagged(_)) => missed_changes = true,
+                Err(RecvError::Closed) => watching = false,
+            },
+        }
+        // Whatever else arrived with it is handled in the same pass
+        loop {
+            match changes.try_recv() {
+                Ok(change) => {
+                    changed.insert(change.linked_path);
+                }
+                Err(TryRecvError::Lagged(_)) => missed_changes = true,
+                Err(_) => break,
+            }
+        }
+        let store = match ConfigStore::load() {
+            Ok(store) => store,
+            Err(e) => {
+                eprintln!("Failed to load sync folders: {}", e);
+                continue;
+            }
+        };
+
+        let now = unix_time();
+        let mut postponed = HashSet::new();
+        for folder in store.sync_folders() {
+            let touched = missed_changes || changed.contains(&folder.linked_path);
+            let status = SYNC_STATUSES
+                .lock()
+                .unwrap()
+                .get(&folder.id)
+                .cloned()
+                .unwrap_or_default();
+            if status.running {
+                if touched {
+                    postponed.insert(folder.linked_path.clone());
+                }
+                continue;
+            }
+            let due = match (status.last_started, folder.interval_minutes) {
+                (None, _) => true,
+                (Some(started), Some(minutes)) => now >= started + minutes * 60,
+                (Some(_), None) => false,
+            };
+            if !due && !touched {
+                continue;
+            }
+            let app = app.clone();
+            let folder = folder.clone();
+            tauri::async_runtime::spawn(async move {
+                if let Err(e) = sync(&app, &folder).await {
+                    eprintln!("Sync of {} failed: {}", folder.id, e);
+                }
+            });
+        }
+        changed = postponed;
+        missed_changes = false;
+    }
+}
+
+// Identifies this app in version vectors, created on first use
+pub fn device_id() -> io::Result<String> {
+    let mut cached = DEVICE_ID.lock().unwrap();
+    if let Some(id) = cached.as_ref() {
+        return Ok(id.clone());
+    }
+    let path = config_dir().join(DEVICE_ID_FILE_NAME);
+    let id = match fs::read_to_string(&path) {
+        Ok(id) if !id.trim().is_empty() => id.trim().to_string(),
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {
+            let id = generate_grant_id();
+            fs::write(&path, &id)?;
+            id
+        }
+    };
+    *cached = Some(id.clone());
+    Ok(id)
+}
+
+fn folder_lock(root: &Path) -> Arc<Mutex<()>> {
+    FOLDER_LOCKS
+        .lock()
+        .unwrap()
+        .entry(root.to_path_buf())
+        .or_default()
+        .clone()
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Order {
+    Equal,
+    // The first version has seen all changes of the second and more
+    Newer,
+    Older,
+    // Each has changes the other hasn't seen
+    Concurrent,
+}
+
+fn compare(a: &VersionVector, b: &VersionVector) -> Order {
+    let mut newer = false;
+    let mut older = false;
+    for device in a.keys().chain(b.keys()) {
+        let (x, y) = (
+            a.get(device).copied().unwrap_or(0),
+            b.get(device).copied().unwrap_or(0),
+        );
+        newer |= x > y;
+        older |= x < y;
+    }
+    match (newer, older) {
+        (false, false) => Order::Equal,
+        (true, false) => Order::Newer,
+        (false, true) => Order::Older,
+        (true, true) => Order::Concurrent,
+    }
+}
+
+// Has seen every change either version has
+fn merge(a: &VersionVector, b: &VersionVector) -> VersionVector {
+    let mut merged = a.clone();
+    for (device, &count) in b {
+        let entry = merged.entry(device.clone()).or_insert(0);
+        *entry = (*entry).max(count);
+    }
+    merged
+}
+
+fn bump(version: &VersionVector, device: &str) -> VersionVector {
+    let mut bumped = version.clone();
+    *bumped.entry(device.to_string()).or_insert(0) += 1;
+    bumped
+}
+
+fn tombstone(version: VersionVector) -> FileVersion {
+    FileVersion {
+        version,
+        size: 0,
+        modified: None,
+        sha256: None,
+        deleted: true,
+    }
+}
+
+fn live(entry: Option<&FileVersion>) -> Option<&FileVersion> {
+    entry.filter(|entry| !entry.deleted)
+}
+
+// What a sync does for a path, from both sides' index entries
+#[derive(Debug)]
+enum Action<'a> {
+    Nothing,
+    // One side has seen every change of the other, it's sent or fetched
+    Push,
+    Pull,
+    // Each side deleted the file, the merged version records one deletion
+    MergeDeletions,
+    // Concurrent changes where the local file loses nothing: the remote
+    // side deleted it, or has the same contents
+    KeepLocal,
+    // A remote change against a local deletion
+    PullMerged(&'a FileVersion),
+    // Both sides changed the contents
+    Conflict(&'a FileVersion, &'a FileVersion),
+}
+
+fn decide<'a>(local: Option<&'a FileVersion>, remote: Option<&'a FileVersion>) -> Action<'a> {
+    let empty = VersionVector::new();
+    let local_version = local.map_or(&empty, |entry| &entry.version);
+    let remote_version = remote.map_or(&empty, |entry| &entry.version);
+    match compare(local_version, remote_version) {
+        Order::Equal => Action::Nothing,
+        // A deletion the other side never had the file for
+        Order::Newer if remote.is_none() && local.is_some_and(|e| e.deleted) => Action::Nothing,
+        Order::Older if local.is_none() && remote.is_some_and(|e| e.deleted) => Action::Nothing,
+        Order::Newer => Action::Push,
+        Order::Older => Action::Pull,
+        Order::Concurrent => match (live(local), live(remote)) {
+            // Nothing to lose, the merged version settles it
+            (None, None) => Action::MergeDeletions,
+            // Changes win over deletions, no data is lost
+            (Some(_), None) => Action::KeepLocal,
+            (None, Some(remote_file)) => Action::PullMerged(remote_file),
+            (Some(local_file), Some(remote_file)) if local_file.sha256 == remote_file.sha256 => {
+                Action::KeepLocal
+            }
+            (Some(local_file), Some(remote_file)) => Action::Conflict(local_file, remote_file),
+        },
+    }
+}
+
+// Whether the local side of a conflict keeps the name: the later change
+// does, device ids break ties so both sides pick the same winner
+fn local_wins(
+    local: &FileVersion,
+    remote: &FileVersion,
+    device: &str,
+    remote_device: &str,
+) -> bool {
+    (local.modified, device) > (remote.modified, remote_device)
+}
+
+// A folder on disk that syncs, with the index of its files. Only used
+// while the folder's lock is held. Symlinks are never synced or followed,
+// hidden files only if the linked path shows them.
+struct Folder {
+    share: LinkedPath,
+    root: PathBuf,
+    device: String,
+    files: BTreeMap<String, FileVersion>,
+}
+
+impl Folder {
+    fn open(share: &LinkedPath) -> io::Result<Folder> {
+        let root = fs::canonicalize(&share.path)?;
+        let files = match fs::read(root.join(SYNC_DIR).join(INDEX_FILE_NAME)) {
+            Ok(data) => serde_json::from_slice::<SyncIndex>(&data)?.files,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Folder {
+            share: share.clone(),
+            root,
+            device: device_id()?,
+            files,
+        })
+    }
+
+    fn index(&self) -> SyncIndex {
+        SyncIndex {
+            device: self.device.clone(),
+            files: self.files.clone(),
+        }
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let dir = self.root.join(SYNC_DIR);
+        fs::create_dir_all(&dir)?;
+        write_atomic(
+            &dir.join(INDEX_FILE_NAME),
+            &serde_json::to_vec(&self.index())?,
+        )
+        .map_err(|e| io::Error::other(e.to_string()))
+    }
+
+    fn syncable_name(&self, name: &str) -> bool {
+        is_plain_name(name)
+            && !is_reserved_name(name)
+            && (self.share.show_hidden || !name.starts_with('.'))
+    }
+
+    fn syncable(&self, relative: &str) -> bool {
+        relative
+            .split('/')
+            .all(|segment| self.syncable_name(segment))
+    }
+
+    fn version_of(&self, relative: &str) -> VersionVector {
+        self.files
+            .get(relative)
+            .map(|entry| entry.version.clone())
+            .unwrap_or_default()
+    }
+
+    // Metadata of a file in the folder, None if there is none. Fails for
+    // paths through symlinks and paths that are folders.
+    fn file_metadata(&self, relative: &str) -> io::Result<Option<fs::Metadata>> {
+        if !self.syncable(relative) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("'{}' can't be synced", relative),
+            ));
+        }
+        let segments: Vec<&str> = relative.split('/').collect();
+        let mut path = self.root.clone();
+        for (i, segment) in segments.iter().enumerate() {
+            path.push(segment);
+            let metadata = match fs::symlink_metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            let last = i + 1 == segments.len();
+            if metadata.file_type().is_symlink() || metadata.is_dir() == last {
+                return Err(io::Error::other(format!(
+                    "'{}' is in the way of a synced file",
+                    segments[..=i].join("/")
+                )));
+            }
+            if last {
+                return Ok(Some(metadata));
+            }
+        }
+        Ok(None)
+    }
+
+    // Walks the whole folder and records what changed since the last scan.
+    // A folder that can't be read fails the scan, rather than have its
+    // files count as deleted.
+    fn scan(&mut self) -> io::Result<()> {
+        let mut seen = HashSet::new();
+        let root = self.root.clone();
+        self.scan_dir(&root, "", &mut seen)?;
+
+        let missing: Vec<String> = self
+            .files
+            .iter()
+            .filter(|(relative, entry)| {
+                !entry.deleted && !seen.contains(*relative) && self.syncable(relative)
+            })
+            .map(|(relative, _)| relative.clone())
+            .collect();
+        for relative in missing {
+            let version = bump(&self.version_of(&relative), &self.device);
+            self.files.insert(relative, tombstone(version));
+        }
+        self.purge_trash();
+        Ok(())
+    }
+
+    fn scan_dir(&mut self, dir: &Path, prefix: &str, seen: &mut HashSet<String>) -> io::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            if !self.syncable_name(&name) {
+                continue;
+            }
+            let relative = if prefix.is_empty() {
+                name
+            } else {
+                format!("{}/{}", prefix, name)
+            };
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                self.scan_dir(&entry.path(), &relative, seen)?;
+            } else if file_type.is_file() {
+                self.update(&relative, &entry.path(), &entry.metadata()?)?;
+                seen.insert(relative);
+            }
+        }
+        Ok(())
+    }
+
+    // Records a file's current state, as a new version if its content changed
+    fn update(&mut self, relative: &str, path: &Path, metadata: &fs::Metadata) -> io::Result<()> {
+        let size = metadata.len();
+        let modified = modified_secs(metadata);
+        let known = live(self.files.get(relative));
+        if known.is_some_and(|entry| entry.size == size && entry.modified == modified) {
+            return Ok(());
+        }
+        let sha256 = file_sha256(path, metadata)?;
+        let version = match self.files.get(relative) {
+            Some(entry) if !entry.deleted && entry.sha256.as_ref() == Some(&sha256) => {
+                entry.version.clone()
+            }
+            _ => bump(&self.version_of(relative), &self.device),
+        };
+        self.files.insert(
+            relative.to_string(),
+            FileVersion {
+                version,
+                size,
+                modified,
+                sha256: Some(sha256),
+                deleted: false,
+            },
+        );
+        Ok(())
+    }
+
+    // `scan` for a single file
+    fn refresh(&mut self, relative: &str) -> io::Result<()> {
+        match self.file_metadata(relative)? {
+            Some(metadata) => {
+                let path = self.root.join(relative);
+                self.update(relative, &path, &metadata)
+            }
+            None => {
+                if live(self.files.get(relative)).is_some() {
+                    let version = bump(&self.version_of(relative), &self.device);
+                    self.files.insert(relative.to_string(), tombstone(version));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // Fails if the file changed since its version was read
+    fn check_unchanged(&mut self, relative: &str, expected: &VersionVector) -> io::Result<()> {
+        self.refresh(relative)?;
+        if self.version_of(relative) != *expected {
+            return Err(io::Error::other(format!(
+                "'{}' changed during the sync",
+                relative
+            )));
+        }
+        Ok(())
+    }
+
+    // Stores `version` for what the file now holds
+    fn set_version(&mut self, relative: &str, version: VersionVector) -> io::Result<()> {
+        let entry = match self.file_metadata(relative)? {
+            Some(metadata) => FileVersion {
+                version,
+                size: metadata.len(),
+                modified: modified_secs(&metadata),
+                sha256: Some(file_sha256(&self.root.join(relative), &metadata)?),
+                deleted: false,
+            },
+            None => tombstone(version),
+        };
+        self.files.insert(relative.to_string(), entry);
+        Ok(())
+    }
+
+    // Moves a complete file into place, creating the folders it needs
+    fn install(&self, staged: &Path, relative: &str) -> io::Result<()> {
+        self.file_metadata(relative)?;
+        let target = self.root.join(relative);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(staged, target)
+    }
+
+    // Moves a file to `<root>/.quartz-sync/trash/<unix time>/<path>`
+    fn trash(&self, relative: &str) -> io::Result<()> {
+        if self.file_metadata(relative)?.is_none() {
+            return Ok(());
+        }
+        let target = self
+            .root
+            .join(SYNC_DIR)
+            .join(TRASH_DIR)
+            .join(unix_time().to_string())
+            .join(relative);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(self.root.join(relative), target)
+    }
+
+    fn purge_trash(&self) {
+        let Ok(batches) = fs::read_dir(self.root.join(SYNC_DIR).join(TRASH_DIR)) else {
+            return;
+        };
+        let cutoff = unix_time().saturating_sub(TRASH_TTL.as_secs());
+        for batch in batches.flatten() {
+            let expired = batch
+                .file_name()
+                .to_str()
+                .and_then(|name| name.parse::<u64>().ok())
+                .is_some_and(|trashed_at| trashed_at < cutoff);
+            if expired {
+                if let Err(e) = fs::remove_dir_all(batch.path()) {
+                    eprintln!("Failed to empty trash {}: {}", batch.path().display(), e);
+                }
+            }
+        }
+    }
+}
+
+// Two-way sync with another Quartz, for writable shares:
+// - `GET /api/v1/sync/<share>` answers the share's SyncIndex
+// - `PUT /api/v1/sync/<share>/<path>` stores a version of a file, the body
+//   is its content unless the share already has that content
+// - `DELETE /api/v1/sync/<share>/<path>` moves the file to the trash
+// Writes carry the FileVersion in `Quartz-Sync-Version` and the version the
+// peer last saw here in `Quartz-Sync-Base`. If the file changed since,
+// nothing is written and the answer is 409.
+pub fn sync_routes(
+    shares: ShareMap,
+    access: SharedPolicy,
+    network: String,
+) -> BoxedFilter<(warp::reply::Response,)> {
+    let request =
+        warp::path!("api" / "v1" / "sync" / ..).and(write_request(shares, access, network));
+    let versions = warp::header::optional::<String>(BASE_HEADER)
+        .and(warp::header::optional::<String>(VERSION_HEADER));
+
+    let index = method(Method::GET).and(request.clone()).then(serve_index);
+    let store = method(Method::PUT)
+        .and(request.clone())
+        .and(versions)
+        .and(warp::body::stream())
+        .then(receive_file);
+    let delete = method(Method::DELETE)
+        .and(request)
+        .and(versions)
+        .then(receive_deletion);
+
+    index.or(store).unify().or(delete).unify().boxed()
+}
+
+async fn serve_index(request: WriteRequest) -> warp::reply::Response {
+    let result = async {
+        if !normalize_path(&request.relative).is_empty() {
+            return Err(WriteError::NotFound);
+        }
+        let share = request.share.clone();
+        locked(&share, |folder| {
+            folder.scan()?;
+            folder.save()?;
+            Ok(folder.index())
+        })
+        .await
+    }
+    .await;
+
+    match result {
+        Ok(index) => warp::reply::json(&index).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+// Runs `f` on the share's folder unless a sync of it is under way already
+async fn locked<T: Send + 'static>(
+    share: &LinkedPath,
+    f: impl FnOnce(&mut Folder) -> Result<T, WriteError> + Send + 'static,
+) -> Result<T, WriteError> {
+    writable(share)?;
+    let root = fs::canonicalize(&share.path)?;
+    let Ok(_guard) = folder_lock(&root).try_lock_owned() else {
+        return Err(WriteError::Conflict(
+            "Folder is syncing, try again later".into(),
+        ));
+    };
+    let share = share.clone();
+    tauri::async_runtime::spawn_blocking(move || f(&mut Folder::open(&share)?))
+        .await
+        .map_err(|e| WriteError::Io(io::Error::other(e.to_string())))?
+}
+
+fn parse_versions(
+    base: Option<String>,
+    version: Option<String>,
+) -> Result<(VersionVector, FileVersion), WriteError> {
+    let (Some(base), Some(version)) = (base, version) else {
+        return Err(WriteError::InvalidRequest(format!(
+            "{} and {} headers are required",
+            BASE_HEADER, VERSION_HEADER
+        )));
+    };
+    let invalid =
+        |e: serde_json::Error| WriteError::InvalidRequest(format!("Invalid version: {}", e));
+    let base: VersionVector = serde_json::from_str(&base).map_err(invalid)?;
+    let mut version: FileVersion = serde_json::from_str(&version).map_err(invalid)?;
+    if compare(&version.version, &base) != Order::Newer {
+        return Err(WriteError::InvalidRequest(
+            "Version must be newer than the base".into(),
+        ));
+    }
+    version.sha256 = version.sha256.as_deref().map(parse_sha256).transpose()?;
+    Ok((base, version))
+}
+
+// Fails with a conflict if the file changed since the peer read the index
+fn check_base(folder: &mut Folder, relative: &str, base: &VersionVector) -> Result<(), WriteError> {
+    folder.refresh(relative)?;
+    if folder.version_of(relative) != *base {
+        return Err(WriteError::Conflict(format!(
+            "'{}' changed since the index was read",
+            relative
+        )));
+    }
+    Ok(())
+}
+
+async fn receive_file(
+    request: WriteRequest,
+    base: Option<String>,
+    version: Option<String>,
+    body: impl Stream<Item = Result<impl Buf, warp::Error>> + Unpin + Send,
+) -> warp::reply::Response {
+    let relative = normalize_path(&request.relative);
+    let result = async {
+        let (base, version) = parse_versions(base, version)?;
+        let sha256 = version
+            .sha256
+            .clone()
+            .filter(|_| !version.deleted)
+            .ok_or_else(|| WriteError::InvalidRequest("Version has no content".into()))?;
+        writable(&request.share)?;
+        let root = fs::canonicalize(&request.share.path)?;
+        // The received file replaces whatever is stored at its path
+        let (parent, name) = relative.rsplit_once('/').unwrap_or(("", &relative));
+        let entry = Entry {
+            dir: root.join(parent),
+            parent: parent.to_string(),
+            name: name.to_string(),
+        };
+        let mut reservation =
+            reserve_quota(&request.share, &entry, ConflictPolicy::Overwrite).await?;
+        reservation.reserve_total(version.size)?;
+
+        let staged = root
+            .join(STAGING_DIR)
+            .join(format!("sync-{}.part", generate_grant_id()));
+        let received = stage_body(&staged, body, version.size).await;
+        let result = match received {
+            Ok(received_sha256) => {
+                let staged = staged.clone();
+                let relative = relative.clone();
+                locked(&request.share, move |folder| {
+                    check_base(folder, &relative, &base)?;
+                    let current = live(folder.files.get(&relative)).and_then(|e| e.sha256.clone());
+                    if current.as_ref() != Some(&sha256) {
+                        if received_sha256 != sha256 {
+                            return Err(WriteError::ChecksumMismatch);
+                        }
+                        set_modified(&staged, version.modified)?;
+                        folder.install(&staged, &relative)?;
+                    }
+                    folder.set_version(&relative, version.version)?;
+                    folder.save()?;
+                    Ok(UploadedFile {
+                        path: relative,
+                        size: version.size,
+                    })
+                })
+                .await
+            }
+            Err(e) => Err(e),
+        };
+        let _ = tokio::fs::remove_file(&staged).await;
+        if result.is_ok() {
+            reservation.commit(version.size);
+        }
+        result
+    }
+    .await;
+
+    audit_upload(&request, &relative, result.as_ref());
+    match result {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+// Writes the body to `staged` and returns its hex SHA-256. Bodies past
+// `size` are refused.
+async fn stage_body(
+    staged: &Path,
+    mut body: impl Stream<Item = Result<impl Buf, warp::Error>> + Unpin + Send,
+    size: u64,
+) -> Result<String, WriteError> {
+    if let Some(parent) = staged.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let mut file = tokio::fs::File::create(staged).await?;
+    let mut hasher = Sha256::new();
+    let mut received = 0u64;
+    while let Some(chunk) = body.next().await {
+        let mut chunk = chunk.map_err(|e| WriteError::Io(io::Error::other(e.to_string())))?;
+        while chunk.has_remaining() {
+            let bytes = chunk.chunk();
+            received += bytes.len() as u64;
+            if received > size {
+                return Err(WriteError::InvalidRequest(
+                    "Body is larger than the version's size".into(),
+                ));
+            }
+            file.write_all(bytes).await?;
+            hasher.update(bytes);
+            let len = bytes.len();
+            chunk.advance(len);
+        }
+    }
+    file.sync_all().await?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+async fn receive_deletion(
+    request: WriteRequest,
+    base: Option<String>,
+    version: Option<String>,
+) -> warp::reply::Response {
+    let relative = normalize_path(&request.relative);
+    let result = async {
+        let (base, version) = parse_versions(base, version)?;
+        if !version.deleted {
+            return Err(WriteError::InvalidRequest(
+                "Version isn't a deletion".into(),
+            ));
+        }
+        let relative = relative.clone();
+        locked(&request.share, move |folder| {
+            check_base(folder, &relative, &base)?;
+            folder.trash(&relative)?;
+            folder.files.insert(relative, tombstone(version.version));
+            folder.save()?;
+            Ok(())
+        })
+        .await
+    }
+    .await;
+
+    audit(&request, AuditAction::Delete, result.as_ref(), |_| {
+        (None, None)
+    });
+    match result {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+fn set_modified(path: &Path, modified: Option<u64>) -> io::Result<()> {
+    if let Some(secs) = modified {
+        fs::File::options()
+            .write(true)
+            .open(path)?
+            .set_modified(UNIX_EPOCH + Duration::from_secs(secs))?;
+    }
+    Ok(())
+}
+
+async fn sync(app: &AppHandle, folder: &SyncFolder) -> Result<SyncStatus, RemoteError> {
+    {
+        let mut statuses = SYNC_STATUSES.lock().unwrap();
+        let status = statuses.entry(folder.id.clone()).or_default();
+        if status.running {
+            return Err(RemoteError::SyncFolderBusy(folder.id.clone()));
+        }
+        *status = SyncStatus {
+            running: true,
+            last_started: Some(unix_time()),
+            ..status.clone()
+        };
+    }
+    emit_status_changed(app);
+
+    let mut counts = SyncStatus::default();
+    let result = run(folder, &mut counts).await;
+    let status = {
+        let mut statuses = SYNC_STATUSES.lock().unwrap();
+        let status = statuses.entry(folder.id.clone()).or_default();
+        *status = SyncStatus {
+            running: false,
+            last_started: status.last_started,
+            last_finished: Some(unix_time()),
+            error: result.as_ref().err().map(|e| e.to_string()),
+            ..counts
+        };
+        status.clone()
+    };
+    emit_status_changed(app);
+    result.map(|()| status)
+}
+
+fn emit_status_changed(app: &AppHandle) {
+    if let Err(e) = app.emit("sync_status_changed", ()) {
+        eprintln!("Failed to emit event to frontend: {}", e);
+    }
+}
+
+// One side of a sync run: the remote share plus this app's folder
+struct Session {
+    network: RemoteNetwork,
+    share: String,
+    client: reqwest::Client,
+    root: PathBuf,
+    device: String,
+    folder: Arc<StdMutex<Folder>>,
+    remote_device: String,
+}
+
+async fn run(config: &SyncFolder, counts: &mut SyncStatus) -> Result<(), RemoteError> {
+    let network = remote_network(&config.remote_network)?;
+    let linked_path = ConfigStore::load()?
+        .linked_paths()
+        .iter()
+        .find(|path| path.name == config.linked_path)
+        .cloned()
+        .ok_or_else(|| FileError::NotFoundError(config.linked_path.clone()))?;
+    let root = fs::canonicalize(&linked_path.path).map_err(sync_error)?;
+    let _guard = folder_lock(&root).lock_owned().await;
+
+    let folder = tauri::async_runtime::spawn_blocking(move || {
+        let mut folder = Folder::open(&linked_path)?;
+        folder.scan()?;
+        folder.save()?;
+        Ok(folder)
+    })
+    .await
+    .map_err(|e| RemoteError::SyncError(e.to_string()))?
+    .map_err(sync_error)?;
+
+    let client = client(&network)?;
+    let url = resource_url(
+        &network,
+        &format!("api/v1/sync/{}", share_path(&config.share, None)),
+    )?;
+    let remote: SyncIndex = send(&network, client.get(url))
+        .await?
+        .json()
+        .await
+        .map_err(|e| RemoteError::InvalidResponse(error_chain(&e)))?;
+    // Both sides would count their changes under the same id
+    if remote.device == folder.device {
+        return Err(RemoteError::SyncError(
+            "the share is served by this app, sync folders need another device".into(),
+        ));
+    }
+
+    let paths: BTreeSet<String> = folder
+        .files
+        .keys()
+        .chain(remote.files.keys())
+        .filter(|path| folder.syncable(path))
+        .cloned()
+        .collect();
+    let local = folder.files.clone();
+    let session = Session {
+        network,
+        share: config.share.clone(),
+        client,
+        root: folder.root.clone(),
+        device: folder.device.clone(),
+        folder: Arc::new(StdMutex::new(folder)),
+        remote_device: remote.device,
+    };
+
+    let mut failures = Vec::new();
+    for path in &paths {
+        let result = session
+            .sync_path(
+                path,
+                local.get(path),
+                remote.files.get(path),
+                &remote.files,
+                counts,
+            )
+            .await;
+        if let Err(e) = result {
+            eprintln!("Failed to sync {}: {}", path, e);
+            failures.push(format!("{}: {}", path, e));
+        }
+    }
+    session.blocking(|folder| folder.save()).await?;
+
+    match failures.first() {
+        None => Ok(()),
+        Some(first) => Err(RemoteError::SyncError(format!(
+            "{} of {} files failed, first {}",
+            failures.len(),
+            paths.len(),
+            first
+        ))),
+    }
+}
+
+impl Session {
+    async fn blocking<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Folder) -> io::Result<T> + Send + 'static,
+    ) -> Result<T, RemoteError> {
+        let folder = self.folder.clone();
+        tauri::async_runtime::spawn_blocking(move || f(&mut folder.lock().unwrap()))
+            .await
+            .map_err(|e| RemoteError::SyncError(e.to_string()))?
+            .map_err(sync_error)
+    }
+
+    fn entry(&self, path: &str) -> Option<FileVersion> {
+        self.folder.lock().unwrap().files.get(path).cloned()
+    }
+
+    async fn sync_path(
+        &self,
+        path: &str,
+        local: Option<&FileVersion>,
+        remote: Option<&FileVersion>,
+        remote_files: &BTreeMap<String, FileVersion>,
+        counts: &mut SyncStatus,
+    ) -> Result<(), RemoteError> {
+        let empty = VersionVector::new();
+        let local_version = local.map_or(&empty, |entry| &entry.version);
+        let remote_version = remote.map_or(&empty, |entry| &entry.version);
+        let merged = merge(local_version, remote_version);
+        match decide(local, remote) {
+            Action::Nothing => Ok(()),
+            Action::Push => {
+                let local = local.cloned().unwrap_or_else(|| tombstone(empty.clone()));
+                self.push(path, &local, remote, counts).await
+            }
+            Action::Pull => {
+                let remote = remote.cloned().unwrap_or_else(|| tombstone(empty.clone()));
+                self.pull(path, local_version, &remote, remote.version.clone(), counts)
+                    .await
+            }
+            Action::MergeDeletions => {
+                let (relative, expected, version) =
+                    (path.to_string(), local_version.clone(), merged.clone());
+                self.blocking(move |folder| {
+                    folder.check_unchanged(&relative, &expected)?;
+                    folder.files.insert(relative, tombstone(version));
+                    Ok(())
+                })
+                .await?;
+                self.push(path, &tombstone(merged), remote, counts).await
+            }
+            Action::KeepLocal => {
+                self.keep_local(path, local_version, merged).await?;
+                self.push_current(path, remote, counts).await
+            }
+            Action::PullMerged(remote_file) => {
+                self.pull(path, local_version, remote_file, merged, counts)
+                    .await?;
+                self.push_current(path, remote, counts).await
+            }
+            Action::Conflict(local_file, remote_file) => {
+                counts.conflicts += 1;
+                self.resolve_conflict(path, local_file, remote_file, merged, remote_files, counts)
+                    .await
+            }
+        }
+    }
+
+    // Both sides changed the file. The later change keeps the name, the
+    // other one is kept next to it as `<name>.sync-conflict-<time>-<device>`
+    // and synced as a file of its own.
+    async fn resolve_conflict(
+        &self,
+        path: &str,
+        local: &FileVersion,
+        remote: &FileVersion,
+        merged: VersionVector,
+        remote_files: &BTreeMap<String, FileVersion>,
+        counts: &mut SyncStatus,
+    ) -> Result<(), RemoteError> {
+        let local_wins = local_wins(local, remote, &self.device, &self.remote_device);
+        let loser = if local_wins {
+            &self.remote_device
+        } else {
+            &self.device
+        };
+        let copy = conflict_name(path, loser);
+
+        if local_wins {
+            let staged = self.download(path, remote).await?;
+            let result = async {
+                self.store_new(&staged, &copy).await?;
+                self.keep_local(path, &local.version, merged).await
+            }
+            .await;
+            let _ = tokio::fs::remove_file(&staged).await;
+            result?;
+            counts.files_received += 1;
+        } else {
+            let staged = self.download(path, remote).await?;
+            let (from, to, expected) = (path.to_string(), copy.clone(), local.version.clone());
+            let renamed = self
+                .blocking(move |folder| {
+                    folder.check_unchanged(&from, &expected)?;
+                    if folder.file_metadata(&to)?.is_some() {
+                        return Err(io::Error::from(io::ErrorKind::AlreadyExists));
+                    }
+                    fs::rename(folder.root.join(&from), folder.root.join(&to))?;
+                    let version = bump(&folder.version_of(&to), &folder.device);
+                    folder.set_version(&to, version)?;
+                    folder.refresh(&from)
+                })
+                .await;
+            let result = match renamed {
+                Ok(()) => self.install(&staged, path, merged).await,
+                Err(e) => Err(e),
+            };
+            let _ = tokio::fs::remove_file(&staged).await;
+            result?;
+            counts.files_received += 1;
+        }
+
+        self.push_current(path, remote_files.get(path), counts)
+            .await?;
+        self.push_current(&copy, remote_files.get(&copy), counts)
+            .await
+    }
+
+    // Sends what the folder now has for `path`
+    async fn push_current(
+        &self,
+        path: &str,
+        remote: Option<&FileVersion>,
+        counts: &mut SyncStatus,
+    ) -> Result<(), RemoteError> {
+        let entry = self.entry(path).ok_or_else(|| {
+            RemoteError::SyncError(format!("'{}' is missing from the index", path))
+        })?;
+        self.push(path, &entry, remote, counts).await
+    }
+
+    async fn push(
+        &self,
+        path: &str,
+        local: &FileVersion,
+        remote: Option<&FileVersion>,
+        counts: &mut SyncStatus,
+    ) -> Result<(), RemoteError> {
+        let base = remote
+            .map(|entry| entry.version.clone())
+            .unwrap_or_default();
+        let url = resource_url(
+            &self.network,
+            &format!("api/v1/sync/{}", share_path(&self.share, Some(path))),
+        )?;
+        let request = if local.deleted {
+            self.client.delete(url)
+        } else if live(remote).is_some_and(|remote| remote.sha256 == local.sha256) {
+            self.client.put(url).body(Vec::new())
+        } else {
+            let file = tokio::fs::File::open(self.root.join(path))
+                .await
+                .map_err(sync_error)?;
+            self.client.put(url).body(file)
+        };
+        let request = request
+            .header(
+                BASE_HEADER,
+                serde_json::to_string(&base).unwrap_or_default(),
+            )
+            .header(
+                VERSION_HEADER,
+                serde_json::to_string(local).unwrap_or_default(),
+            );
+        send(&self.network, request).await?;
+        counts.files_sent += 1;
+        Ok(())
+    }
+
+    // Takes the remote side's file, or deletion, stored locally as `version`
+    async fn pull(
+        &self,
+        path: &str,
+        expected: &VersionVector,
+        remote: &FileVersion,
+        version: VersionVector,
+        counts: &mut SyncStatus,
+    ) -> Result<(), RemoteError> {
+        let (relative, expected_version) = (path.to_string(), expected.clone());
+        self.blocking(move |folder| folder.check_unchanged(&relative, &expected_version))
+            .await?;
+
+        let current = live(self.entry(path).as_ref()).and_then(|entry| entry.sha256.clone());
+        if remote.deleted {
+            let relative = path.to_string();
+            self.blocking(move |folder| {
+                folder.trash(&relative)?;
+                folder.files.insert(relative, tombstone(version));
+                Ok(())
+            })
+            .await?;
+        } else if current.is_some() && current == remote.sha256 {
+            let relative = path.to_string();
+            self.blocking(move |folder| folder.set_version(&relative, version))
+                .await?;
+            return Ok(());
+        } else {
+            let staged = self.download(path, remote).await?;
+            let (relative, expected) = (path.to_string(), expected.clone());
+            let result = async {
+                self.blocking(move |folder| folder.check_unchanged(&relative, &expected))
+                    .await?;
+                self.install(&staged, path, version).await
+            }
+            .await;
+            let _ = tokio::fs::remove_file(&staged).await;
+            result?;
+        }
+        counts.files_received += 1;
+        Ok(())
+    }
+
+    // Gives the local file the merged version, as long as it didn't change
+    async fn keep_local(
+        &self,
+        path: &str,
+        expected: &VersionVector,
+        merged: VersionVector,
+    ) -> Result<(), RemoteError> {
+        let (relative, expected) = (path.to_string(), expected.clone());
+        self.blocking(move |folder| {
+            folder.check_unchanged(&relative, &expected)?;
+            folder.set_version(&relative, merged)
+        })
+        .await
+    }
+
+    async fn install(
+        &self,
+        staged: &Path,
+        path: &str,
+        version: VersionVector,
+    ) -> Result<(), RemoteError> {
+        let (staged, relative) = (staged.to_path_buf(), path.to_string());
+        self.blocking(move |folder| {
+            folder.install(&staged, &relative)?;
+            folder.set_version(&relative, version)
+        })
+        .await
+    }
+
+    // Places a file under a name that must still be free, as a new file
+    async fn store_new(&self, staged: &Path, path: &str) -> Result<(), RemoteError> {
+        let (staged, relative) = (staged.to_path_buf(), path.to_string());
+        self.blocking(move |folder| {
+            if folder.file_metadata(&relative)?.is_some() {
+                return Err(io::Error::from(io::ErrorKind::AlreadyExists));
+            }
+            folder.install(&staged, &relative)?;
+            let version = bump(&folder.version_of(&relative), &folder.device);
+            folder.set_version(&relative, version)
+        })
+        .await
+    }
+
+    // Downloads into the folder's staging directory and checks the content
+    // against the remote index
+    async fn download(&self, path: &str, remote: &FileVersion) -> Result<PathBuf, RemoteError> {
+        let url = resource_url(&self.network, &share_path(&self.share, Some(path)))?;
+        let response = send(&self.network, self.client.get(url)).await?;
+        let staging = self.root.join(STAGING_DIR);
+        tokio::fs::create_dir_all(&staging)
+            .await
+            .map_err(sync_error)?;
+        let staged = staging.join(format!("sync-{}.part", generate_grant_id()));
+
+        let result = async {
+            let mut file = tokio::fs::File::create(&staged).await.map_err(sync_error)?;
+            let mut hasher = Sha256::new();
+            let mut body = response.bytes_stream();
+            while let Some(chunk) = body.next().await {
+                let chunk = chunk.map_err(|e| RemoteError::DownloadError(error_chain(&e)))?;
+                file.write_all(&chunk).await.map_err(sync_error)?;
+                hasher.update(&chunk);
+            }
+            if Some(format!("{:x}", hasher.finalize())) != remote.sha256 {
+                return Err(RemoteError::DownloadError(
+                    "file changed on the remote side during the sync".into(),
+                ));
+            }
+            file.sync_all().await.map_err(sync_error)?;
+            drop(file);
+            set_modified(&staged, remote.modified).map_err(sync_error)
+        }
+        .await;
+
+        match result {
+            Ok(()) => Ok(staged),
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&staged).await;
+                Err(e)
+            }
+        }
+    }
+}
+
+// "notes.txt" -> "notes.sync-conflict-1700000000-0a1b2c3d.txt"
+fn conflict_name(path: &str, device: &str) -> String {
+    let (parent, name) = match path.rsplit_once('/') {
+        Some((parent, name)) => (format!("{}/", parent), name),
+        None => (String::new(), path),
+    };
+    let device: String = device.chars().take(8).collect();
+    let suffix = format!("sync-conflict-{}-{}", unix_time(), device);
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => {
+            format!("{}{}.{}.{}", parent, stem, suffix, extension)
+        }
+        _ => format!("{}{}.{}", parent, name, suffix),
+    }
+}
+
+fn sync_error(e: io::Error) -> RemoteError {
+    RemoteError::SyncError(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        bump, compare, conflict_name, decide, local_wins, merge, tombstone, Action, Order,
+    };
+    use crate::types::{FileVersion, VersionVector};
+
+    fn version(counts: &[(&str, u64)]) -> VersionVector {
+        counts
+            .iter()
+            .map(|(device, count)| (device.to_string(), *count))
+            .collect()
+    }
+
+    fn file(counts: &[(&str, u64)], sha256: &str, modified: u64) -> FileVersion {
+        FileVersion {
+            version: version(counts),
+            size: 1,
+            modified: Some(modified),
+            sha256: Some(sha256.to_string()),
+            deleted: false,
+        }
+    }
+
+    #[test]
+    fn compare_orders_version_vectors() {
+        let a1 = version(&[("a", 1)]);
+        let a2 = version(&[("a", 2)]);
+        let a1b1 = version(&[("a", 1), ("b", 1)]);
+        let b1 = version(&[("b", 1)]);
+        assert_eq!(compare(&a1, &a1), Order::Equal);
+        assert_eq!(
+            compare(&VersionVector::new(), &VersionVector::new()),
+            Order::Equal
+        );
+        // Missing devices count as zero
+        assert_eq!(compare(&a1, &version(&[("a", 1), ("b", 0)])), Order::Equal);
+        assert_eq!(compare(&a2, &a1), Order::Newer);
+        assert_eq!(compare(&a1, &a1b1), Order::Older);
+        assert_eq!(compare(&a1, &VersionVector::new()), Order::Newer);
+        assert_eq!(compare(&a2, &a1b1), Order::Concurrent);
+        assert_eq!(compare(&a1, &b1), Order::Concurrent);
+    }
+
+    #[test]
+    fn merge_keeps_the_highest_count_of_each_device() {
+        let merged = merge(
+            &version(&[("a", 3), ("b", 1)]),
+            &version(&[("b", 2), ("c", 1)]),
+        );
+        assert_eq!(merged, version(&[("a", 3), ("b", 2), ("c", 1)]));
+        let a = version(&[("a", 2)]);
+        let b = version(&[("a", 1), ("b", 4)]);
+        assert_eq!(compare(&merge(&a, &b), &a), Order::Newer);
+        assert_eq!(compare(&merge(&a, &b), &b), Order::Newer);
+        assert_eq!(bump(&merge(&a, &b), "a"), version(&[("a", 3), ("b", 4)]));
+    }
+
+    #[test]
+    fn conflict_name_goes_before_the_extension() {
+        let name = conflict_name("docs/notes.txt", "0a1b2c3d4e5f");
+        let middle = name
+            .strip_prefix("docs/notes.sync-conflict-")
+            .and_then(|rest| rest.strip_suffix("-0a1b2c3d.txt"))
+            .unwrap();
+        assert!(middle.parse::<u64>().is_ok());
+
+        assert!(conflict_name("README", "device").starts_with("README.sync-conflict-"));
+        // Dotfiles have no stem, the suffix goes at the end
+        let dotfile = conflict_name(".env", "device");
+        assert!(dotfile.starts_with(".env.sync-conflict-"));
+        assert!(dotfile.ends_with("-device"));
+    }
+
+    #[test]
+    fn ordered_versions_push_pull_or_do_nothing() {
+        let old = file(&[("a", 1)], "old", 10);
+        let new = file(&[("a", 2)], "new", 20);
+        assert!(matches!(decide(Some(&old), Some(&old)), Action::Nothing));
+        assert!(matches!(decide(Some(&new), Some(&old)), Action::Push));
+        assert!(matches!(decide(Some(&old), Some(&new)), Action::Pull));
+        assert!(matches!(decide(Some(&new), None), Action::Push));
+        assert!(matches!(decide(None, Some(&new)), Action::Pull));
+
+        // Deletions of files the other side never had aren't sent around
+        let deleted = tombstone(version(&[("a", 2)]));
+        assert!(matches!(decide(Some(&deleted), None), Action::Nothing));
+        assert!(matches!(decide(None, Some(&deleted)), Action::Nothing));
+        // But they reach sides that still have the file
+        assert!(matches!(decide(Some(&deleted), Some(&old)), Action::Push));
+        assert!(matches!(decide(Some(&old), Some(&deleted)), Action::Pull));
+    }
+
+    #[test]
+    fn concurrent_deletions_merge() {
+        let local = tombstone(version(&[("a", 2), ("b", 1)]));
+        let remote = tombstone(version(&[("a", 1), ("b", 2)]));
+        assert!(matches!(
+            decide(Some(&local), Some(&remote)),
+            Action::MergeDeletions
+        ));
+    }
+
+    #[test]
+    fn concurrent_edit_wins_over_deletion() {
+        let edited = file(&[("a", 2), ("b", 1)], "edited", 20);
+        let deleted = tombstone(version(&[("a", 1), ("b", 2)]));
+        assert!(matches!(
+            decide(Some(&edited), Some(&deleted)),
+            Action::KeepLocal
+        ));
+        match decide(Some(&deleted), Some(&edited)) {
+            Action::PullMerged(remote) => assert_eq!(remote, &edited),
+            other => panic!("expected the edit to be pulled, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn concurrent_edits_with_the_same_contents_are_no_conflict() {
+        let local = file(&[("a", 2), ("b", 1)], "same", 20);
+        let remote = file(&[("a", 1), ("b", 2)], "same", 30);
+        assert!(matches!(
+            decide(Some(&local), Some(&remote)),
+            Action::KeepLocal
+        ));
+    }
+
+    #[test]
+    fn concurrent_edits_conflict_and_the_later_one_keeps_the_name() {
+        let local = file(&[("a", 2), ("b", 1)], "local", 20);
+        let remote = file(&[("a", 1), ("b", 2)], "remote", 30);
+        match decide(Some(&local), Some(&remote)) {
+            Action::Conflict(local_file, remote_file) => {
+                assert_eq!(local_file, &local);
+                assert_eq!(remote_file, &remote);
+            }
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+
+        assert!(!local_wins(&local, &remote, "a", "b"));
+        assert!(local_wins(&remote, &local, "a", "b"));
+        // Same time, both sides still agree on the winner
+        let tied = file(&[("a", 1), ("b", 2)], "remote", 20);
+        assert!(local_wins(&local, &tied, "b", "a"));
+        assert!(!local_wins(&tied, &local, "a", "b"));
+    }
+}
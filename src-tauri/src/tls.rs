@@ -0,0 +1,114 @@
+//Uses
+use crate::config::{network_data_dir, write_atomic, write_private};
+use crate::types::{ServerError, TlsSetting};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+
+const CERT_FILE_NAME: &str = "cert.pem";
+const KEY_FILE_NAME: &str = "key.pem";
+
+// PEM encoded certificate chain and private key handed to warp's TLS builder
+pub struct TlsIdentity {
+    pub cert_pem: String,
+    pub key_pem: String,
+}
+
+// Resolves a network's TLS setting to the identity its server presents, or
+// None when the network is served over plain HTTP
+pub fn load_identity(
+    network_name: &str,
+    setting: &TlsSetting,
+) -> Result<Option<TlsIdentity>, ServerError> {
+    match setting {
+        TlsSetting::Disabled => Ok(None),
+        TlsSetting::SelfSigned => load_or_create_self_signed(network_name).map(Some),
+        TlsSetting::Provided {
+            cert_path,
+            key_path,
+        } => load_provided(cert_path, key_path).map(Some),
+    }
+}
+
+// Reads a user supplied certificate chain and key, checking that both are
+// PEM so a bad file is reported here rather than as a bind failure
+pub fn load_provided(cert_path: &Path, key_path: &Path) -> Result<TlsIdentity, ServerError> {
+    let read = |path: &Path| {
+        fs::read_to_string(path).map_err(|e| tls_error(format!("{}: {}", path.display(), e)))
+    };
+    let identity = TlsIdentity {
+        cert_pem: read(cert_path)?,
+        key_pem: read(key_path)?,
+    };
+
+    leaf_certificate(&identity.cert_pem)
+        .map_err(|e| tls_error(format!("{}: {}", cert_path.display(), e)))?;
+    let has_key = pem::parse_many(&identity.key_pem)
+        .map(|blocks| {
+            blocks
+                .iter()
+                .any(|block| block.tag().ends_with("PRIVATE KEY"))
+        })
+        .unwrap_or(false);
+    if !has_key {
+        return Err(tls_error(format!(
+            "{}: no private key found",
+            key_path.display()
+        )));
+    }
+
+    Ok(identity)
+}
+
+impl TlsIdentity {
+    // SHA-256 of the leaf certificate, formatted like browsers show it
+    // (`AB:CD:...`). Peers pin this instead of trusting a CA.
+    pub fn fingerprint(&self) -> Result<String, ServerError> {
+        let der = leaf_certificate(&self.cert_pem).map_err(tls_error)?;
+        let digest = Sha256::digest(der);
+        let hex: Vec<String> = digest.iter().map(|b| format!("{:02X}", b)).collect();
+        Ok(hex.join(":"))
+    }
+}
+
+// DER of the first certificate in a PEM chain, which is the server's own
+fn leaf_certificate(cert_pem: &str) -> Result<Vec<u8>, String> {
+    pem::parse_many(cert_pem)
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .find(|block| block.tag() == "CERTIFICATE")
+        .map(|block| block.into_contents())
+        .ok_or_else(|| "no certificate found".to_string())
+}
+
+// Loads the network's self-signed certificate, generating and persisting one
+// on first use so the identity stays stable across restarts
+fn load_or_create_self_signed(network_name: &str) -> Result<TlsIdentity, ServerError> {
+    let dir = network_data_dir("certs", network_name);
+    let cert_path = dir.join(CERT_FILE_NAME);
+    let key_path = dir.join(KEY_FILE_NAME);
+    if cert_path.is_file() && key_path.is_file() {
+        return Ok(TlsIdentity {
+            cert_pem: fs::read_to_string(&cert_path).map_err(tls_error)?,
+            key_pem: fs::read_to_string(&key_path).map_err(tls_error)?,
+        });
+    }
+
+    let certified_key =
+        rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).map_err(tls_error)?;
+    let identity = TlsIdentity {
+        cert_pem: certified_key.cert.pem(),
+        key_pem: certified_key.key_pair.serialize_pem(),
+    };
+
+    fs::create_dir_all(&dir).map_err(tls_error)?;
+    write_private(&key_path, identity.key_pem.as_bytes())?;
+    write_atomic(&cert_path, identity.cert_pem.as_bytes())?;
+    println!("Generated self-signed certificate for '{}'", network_name);
+
+    Ok(identity)
+}
+
+fn tls_error(e: impl std::fmt::Display) -> ServerError {
+    ServerError::TlsError(e.to_string())
+}
@@ -0,0 +1,446 @@
+//Uses
+use crate::config::{network_data_dir, write_private};
+use crate::types::TorError;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+
+pub const DEFAULT_CONTROL_PORT: u16 = 9051;
+// Where a local Tor daemon accepts SOCKS connections to onion services
+pub const DEFAULT_SOCKS_PORT: u16 = 9050;
+const KEY_FILE_NAME: &str = "private_key";
+// Port peers use in `http://<id>.onion:<port>/`
+const ONION_VIRTUAL_PORT: u16 = 80;
+
+pub struct TorControlConfig {
+    pub addr: SocketAddr,
+    pub password: Option<String>,
+}
+
+// A published v3 onion service. Tor removes it when the control connection
+// that created it closes, so this has to live as long as the server.
+pub struct OnionService {
+    control: TorControl,
+    service_id: String,
+}
+
+impl OnionService {
+    pub fn address(&self) -> String {
+        format!("{}.onion", self.service_id)
+    }
+
+    pub async fn remove(mut self) {
+        let command = format!("DEL_ONION {}", self.service_id);
+        if let Err(e) = self.control.command(&command).await {
+            eprintln!("Failed to remove onion service: {}", e);
+        }
+    }
+}
+
+// Publishes `local_addr` as an onion service, reusing the network's stored
+// key so the .onion address stays the same across restarts
+pub async fn publish_onion_service(
+    network_name: &str,
+    config: &TorControlConfig,
+    local_addr: SocketAddr,
+) -> Result<OnionService, TorError> {
+    let key_path = network_data_dir("onion", network_name).join(KEY_FILE_NAME);
+    add_onion(config, local_addr, &key_path).await
+}
+
+// Publishes with the key stored at `key_path`, or a new one that is then
+// stored there
+async fn add_onion(
+    config: &TorControlConfig,
+    local_addr: SocketAddr,
+    key_path: &Path,
+) -> Result<OnionService, TorError> {
+    let mut control = TorControl::connect(config.addr).await?;
+    control.authenticate(config.password.as_deref()).await?;
+
+    let key = match fs::read_to_string(key_path) {
+        Ok(key) => key.trim().to_string(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => "NEW:ED25519-V3".to_string(),
+        Err(e) => return Err(TorError::KeyError(e.to_string())),
+    };
+    let command = format!(
+        "ADD_ONION {} Port={},{}",
+        key, ONION_VIRTUAL_PORT, local_addr
+    );
+    let reply = control.command(&command).await?;
+
+    let service_id = reply_value(&reply, "ServiceID")
+        .ok_or_else(|| TorError::ProtocolError("ADD_ONION reply without ServiceID".into()))?
+        .to_string();
+    // Only returned for newly generated keys
+    if let Some(private_key) = reply_value(&reply, "PrivateKey") {
+        save_key(key_path, private_key)?;
+        println!("Generated onion key {}", key_path.display());
+    }
+
+    Ok(OnionService {
+        control,
+        service_id,
+    })
+}
+
+fn save_key(key_path: &Path, private_key: &str) -> Result<(), TorError> {
+    let key_error = |e: &dyn std::fmt::Display| TorError::KeyError(e.to_string());
+    if let Some(dir) = key_path.parent() {
+        fs::create_dir_all(dir).map_err(|e| key_error(&e))?;
+    }
+    write_private(key_path, private_key.as_bytes()).map_err(|e| key_error(&e))
+}
+
+fn reply_value<'a>(reply: &'a [String], key: &str) -> Option<&'a str> {
+    reply
+        .iter()
+        .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+}
+
+// Minimal client for Tor's control protocol (control-spec.txt)
+struct TorControl {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl TorControl {
+    async fn connect(addr: SocketAddr) -> Result<Self, TorError> {
+        let stream = TcpStream::connect(addr)
+            .await
+            .map_err(|e| TorError::ConnectError(format!("{}: {}", addr, e)))?;
+        let (reader, writer) = stream.into_split();
+        Ok(TorControl {
+            reader: BufReader::new(reader),
+            writer,
+        })
+    }
+
+    async fn authenticate(&mut self, password: Option<&str>) -> Result<(), TorError> {
+        let reply = self.command("PROTOCOLINFO 1").await?;
+        let auth_line = reply
+            .iter()
+            .find_map(|line| line.strip_prefix("AUTH "))
+            .ok_or_else(|| TorError::ProtocolError("PROTOCOLINFO reply without AUTH".into()))?;
+        let methods: Vec<&str> = auth_line
+            .split(' ')
+            .find_map(|field| field.strip_prefix("METHODS="))
+            .map(|methods| methods.split(',').collect())
+            .unwrap_or_default();
+        let cookie_file = auth_line
+            .split(' ')
+            .find_map(|field| field.strip_prefix("COOKIEFILE="))
+            .map(|path| path.trim_matches('"').replace("\\\\", "\\"));
+
+        let command = if methods.contains(&"NULL") {
+            "AUTHENTICATE".to_string()
+        } else if let (Some(password), true) = (password, methods.contains(&"HASHEDPASSWORD")) {
+            format!("AUTHENTICATE \"{}\"", escape_quoted(password))
+        } else if let (Some(cookie_file), true) = (cookie_file, methods.contains(&"COOKIE")) {
+            let cookie = fs::read(&cookie_file)
+                .map_err(|e| TorError::AuthError(format!("{}: {}", cookie_file, e)))?;
+            let cookie: String = cookie.iter().map(|b| format!("{:02x}", b)).collect();
+            format!("AUTHENTICATE {}", cookie)
+        } else {
+            return Err(TorError::AuthError(format!(
+                "no supported authentication method (offered: {})",
+                methods.join(", ")
+            )));
+        };
+
+        self.command(&command)
+            .await
+            .map_err(|e| TorError::AuthError(e.to_string()))?;
+        Ok(())
+    }
+
+    // Sends one command and returns the text of each reply line without its
+    // status code. Any status other than 250 is an error.
+    async fn command(&mut self, command: &str) -> Result<Vec<String>, TorError> {
+        let io_error = |e: std::io::Error| TorError::ConnectError(e.to_string());
+        self.writer
+            .write_all(format!("{}\r\n", command).as_bytes())
+            .await
+            .map_err(io_error)?;
+
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line).await.map_err(io_error)? == 0 {
+                return Err(TorError::ProtocolError("control connection closed".into()));
+            }
+            let line = line.trim_end_matches(['\r', '\n']);
+            if line.len() < 4 {
+                return Err(TorError::ProtocolError(format!(
+                    "malformed reply '{}'",
+                    line
+                )));
+            }
+            let (status, rest) = line.split_at(3);
+            let (separator, text) = rest.split_at(1);
+            if status != "250" {
+                return Err(TorError::CommandError(format!("{} {}", status, text)));
+            }
+            lines.push(text.to_string());
+            match separator {
+                " " => return Ok(lines),
+                // Data follows until a line with a single "."
+                "+" => loop {
+                    let mut data = String::new();
+                    if self.reader.read_line(&mut data).await.map_err(io_error)? == 0 {
+                        return Err(TorError::ProtocolError("control connection closed".into()));
+                    }
+                    if data.trim_end_matches(['\r', '\n']) == "." {
+                        break;
+                    }
+                },
+                _ => {}
+            }
+        }
+    }
+}
+
+fn escape_quoted(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex as StdMutex};
+    use tokio::net::TcpListener;
+
+    const SERVICE_ID: &str = "quartzqzwstfyyfhdbmrwgfqyoxhd2z4j7s7sw6zqn2wmy5lsuqxmyid";
+    const PRIVATE_KEY: &str = "ED25519-V3:c2VjcmV0IGtleSBieXRlcw==";
+    const LOCAL_ADDR: &str = "127.0.0.1:8080";
+
+    type Commands = Arc<StdMutex<Vec<String>>>;
+
+    // A control port that offers `auth` in PROTOCOLINFO and only accepts
+    // `accepted_auth`. With `reject_add_onion`, ADD_ONION fails like it does
+    // for malformed keys.
+    async fn fake_control_port(
+        auth: String,
+        accepted_auth: String,
+        reject_add_onion: bool,
+    ) -> (SocketAddr, Commands) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let commands: Commands = Arc::default();
+        let recorded = commands.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let (reader, mut writer) = stream.into_split();
+                let mut lines = BufReader::new(reader).lines();
+                let recorded = recorded.clone();
+                let (auth, accepted_auth) = (auth.clone(), accepted_auth.clone());
+                tokio::spawn(async move {
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        recorded.lock().unwrap().push(line.clone());
+                        let reply = if line == "PROTOCOLINFO 1" {
+                            format!(
+                                "250-PROTOCOLINFO 1\r\n250-AUTH {}\r\n250-VERSION Tor=\"0.4.8.10\"\r\n250 OK\r\n",
+                                auth
+                            )
+                        } else if line.starts_with("AUTHENTICATE") {
+                            if line == accepted_auth {
+                                "250 OK\r\n".to_string()
+                            } else {
+                                "515 Authentication failed: Password did not match HashedControlPassword value from configuration\r\n".to_string()
+                            }
+                        } else if line.starts_with("ADD_ONION") && reject_add_onion {
+                            "512 Bad arguments to ADD_ONION: Failed to decode ED25519-V3 key\r\n"
+                                .to_string()
+                        } else if line.starts_with("ADD_ONION NEW:ED25519-V3 ") {
+                            format!(
+                                "250-ServiceID={}\r\n250-PrivateKey={}\r\n250 OK\r\n",
+                                SERVICE_ID, PRIVATE_KEY
+                            )
+                        } else if line.starts_with("ADD_ONION ") {
+                            format!("250-ServiceID={}\r\n250 OK\r\n", SERVICE_ID)
+                        } else if line == "GETINFO config-text" {
+                            "250+config-text=\r\nControlPort 9051\r\nSocksPort 9050\r\n.\r\n250 OK\r\n"
+                                .to_string()
+                        } else if line.starts_with("DEL_ONION ") {
+                            "250 OK\r\n".to_string()
+                        } else {
+                            format!("510 Unrecognized command \"{}\"\r\n", line)
+                        };
+                        if writer.write_all(reply.as_bytes()).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        (addr, commands)
+    }
+
+    // An empty directory that is removed again at the end of the test
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("quartz-tor-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            TestDir(dir)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn control_config(addr: SocketAddr, password: Option<&str>) -> TorControlConfig {
+        TorControlConfig {
+            addr,
+            password: password.map(str::to_string),
+        }
+    }
+
+    #[tokio::test]
+    async fn authenticates_with_null() {
+        let (addr, commands) =
+            fake_control_port("METHODS=NULL".into(), "AUTHENTICATE".into(), false).await;
+        let mut control = TorControl::connect(addr).await.unwrap();
+        control.authenticate(Some("unused")).await.unwrap();
+        assert_eq!(
+            *commands.lock().unwrap(),
+            ["PROTOCOLINFO 1", "AUTHENTICATE"]
+        );
+    }
+
+    #[tokio::test]
+    async fn authenticates_with_hashed_password() {
+        let (addr, _) = fake_control_port(
+            "METHODS=HASHEDPASSWORD".into(),
+            r#"AUTHENTICATE "pa\"ss\\word""#.into(),
+            false,
+        )
+        .await;
+        let mut control = TorControl::connect(addr).await.unwrap();
+        control.authenticate(Some(r#"pa"ss\word"#)).await.unwrap();
+
+        // A wrong password comes back as Tor's 515
+        let mut control = TorControl::connect(addr).await.unwrap();
+        match control.authenticate(Some("wrong")).await {
+            Err(TorError::AuthError(message)) => assert!(message.contains("515")),
+            _ => panic!("expected the wrong password to be refused"),
+        }
+
+        // Without a password there's nothing to offer
+        let mut control = TorControl::connect(addr).await.unwrap();
+        assert!(matches!(
+            control.authenticate(None).await,
+            Err(TorError::AuthError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn authenticates_with_cookie() {
+        let dir = TestDir::new("cookie");
+        let cookie_path = dir.0.join("control_auth_cookie");
+        let cookie: Vec<u8> = (0..32).collect();
+        fs::write(&cookie_path, &cookie).unwrap();
+        let hex: String = cookie.iter().map(|b| format!("{:02x}", b)).collect();
+        let (addr, commands) = fake_control_port(
+            format!(
+                "METHODS=COOKIE,SAFECOOKIE COOKIEFILE=\"{}\"",
+                cookie_path.display()
+            ),
+            format!("AUTHENTICATE {}", hex),
+            false,
+        )
+        .await;
+
+        let mut control = TorControl::connect(addr).await.unwrap();
+        control.authenticate(None).await.unwrap();
+        assert_eq!(commands.lock().unwrap()[1], format!("AUTHENTICATE {}", hex));
+    }
+
+    #[tokio::test]
+    async fn creates_then_reuses_the_onion_key() {
+        let dir = TestDir::new("key");
+        let key_path = dir.0.join("onion").join(KEY_FILE_NAME);
+        let (addr, commands) =
+            fake_control_port("METHODS=NULL".into(), "AUTHENTICATE".into(), false).await;
+        let config = control_config(addr, None);
+        let local_addr: SocketAddr = LOCAL_ADDR.parse().unwrap();
+
+        let onion_service = add_onion(&config, local_addr, &key_path).await.unwrap();
+        assert_eq!(onion_service.address(), format!("{}.onion", SERVICE_ID));
+        assert_eq!(fs::read_to_string(&key_path).unwrap(), PRIVATE_KEY);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&key_path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        onion_service.remove().await;
+
+        // The second time the saved key goes back to Tor
+        let onion_service = add_onion(&config, local_addr, &key_path).await.unwrap();
+        assert_eq!(onion_service.address(), format!("{}.onion", SERVICE_ID));
+        assert_eq!(fs::read_to_string(&key_path).unwrap(), PRIVATE_KEY);
+        onion_service.remove().await;
+
+        let commands = commands.lock().unwrap();
+        let onion_commands: Vec<&String> = commands
+            .iter()
+            .filter(|command| command.contains("_ONION"))
+            .collect();
+        assert_eq!(
+            onion_commands,
+            [
+                &format!("ADD_ONION NEW:ED25519-V3 Port=80,{}", LOCAL_ADDR),
+                &format!("DEL_ONION {}", SERVICE_ID),
+                &format!("ADD_ONION {} Port=80,{}", PRIVATE_KEY, LOCAL_ADDR),
+                &format!("DEL_ONION {}", SERVICE_ID),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_data_replies() {
+        let (addr, _) =
+            fake_control_port("METHODS=NULL".into(), "AUTHENTICATE".into(), false).await;
+        let mut control = TorControl::connect(addr).await.unwrap();
+        control.authenticate(None).await.unwrap();
+
+        let reply = control.command("GETINFO config-text").await.unwrap();
+        assert_eq!(reply, ["config-text=", "OK"]);
+        // The data lines were consumed, the next reply lines up with its command
+        let reply = control.command("DEL_ONION x").await.unwrap();
+        assert_eq!(reply, ["OK"]);
+    }
+
+    #[tokio::test]
+    async fn reports_failed_commands() {
+        let dir = TestDir::new("error");
+        let key_path = dir.0.join(KEY_FILE_NAME);
+        let (addr, _) = fake_control_port("METHODS=NULL".into(), "AUTHENTICATE".into(), true).await;
+        let config = control_config(addr, None);
+
+        match add_onion(&config, LOCAL_ADDR.parse().unwrap(), &key_path).await {
+            Err(TorError::CommandError(message)) => {
+                assert!(message.starts_with("512 "), "{}", message)
+            }
+            _ => panic!("expected ADD_ONION to fail"),
+        }
+        assert!(!key_path.exists());
+
+        let mut control = TorControl::connect(addr).await.unwrap();
+        assert!(matches!(
+            control.command("SIGNAL BOGUS").await,
+            Err(TorError::CommandError(message)) if message.starts_with("510 ")
+        ));
+    }
+}
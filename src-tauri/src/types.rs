@@ -1,41 +1,766 @@
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
 use std::path::PathBuf;
 
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, Hash)]
 pub struct LinkedPath {
     pub name: String,
     pub path: PathBuf,
+    // Dotfiles, and hidden files on Windows, are only served when enabled
+    #[serde(default)]
+    pub show_hidden: bool,
+    #[serde(default)]
+    pub symlinks: SymlinkPolicy,
+    // Clients may upload, create folders, rename and delete when enabled
+    #[serde(default)]
+    pub writable: bool,
+    // Uploads are refused once the folder holds this many bytes
+    #[serde(default)]
+    pub quota_bytes: Option<u64>,
+    // Text, PDF and EPUB contents are indexed for search when enabled
+    #[serde(default)]
+    pub full_text: bool,
+}
+
+// What linking a directory that overlaps a linked path, by being the same,
+// inside it or around it, does
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum OverlapPolicy {
+    #[default]
+    Reject,
+    // Link it anyway and say what it overlaps
+    Warn,
+    // A directory inside a linked path isn't linked again. One around linked
+    // paths replaces those that no network, mirror or sync folder uses.
+    Merge,
+}
+
+// Something wrong with the stored config, reported by the health check
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ConfigIssue {
+    // `inner` is inside `outer` at `relative_path`, empty when both link the
+    // same directory
+    Overlap {
+        outer: String,
+        inner: String,
+        relative_path: String,
+    },
+    // The directory doesn't exist or can't be read
+    MissingPath {
+        linked_path: String,
+        path: PathBuf,
+    },
+}
+
+// Which symlinks inside a linked path are served
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub enum SymlinkPolicy {
+    // Links are followed as long as their target is inside the linked path
+    #[default]
+    WithinShare,
+    // Links are treated as if they weren't there
+    Never,
 }
 
 // Enum to represent the Network type
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "type", rename_all = "camelCase")] // Matches TypeScript structure
 pub enum Network {
     LocalNetwork {
         name: String,
         linked_paths: Vec<LinkedPath>,
         port: u16,
+        #[serde(default)]
+        bind_address: BindAddress,
+        #[serde(default)]
+        tls: TlsSetting,
+        #[serde(default)]
+        access_grants: Vec<AccessGrant>,
+        // HTML pages for directories, off for API-only networks
+        #[serde(default = "default_directory_index")]
+        directory_index: bool,
     },
     InternetNetwork {
         name: String,
         linked_paths: Vec<LinkedPath>,
+        // External address discovered through port mapping
         address: String,
+        #[serde(default = "default_port")]
+        port: u16,
+        // Required from every client, as a bearer token or `?token=`
+        #[serde(default)]
+        access_token: String,
+        // Internet servers refuse to start with TLS disabled
+        #[serde(default = "default_internet_tls")]
+        tls: TlsSetting,
+        #[serde(default)]
+        access_grants: Vec<AccessGrant>,
+        // HTML pages for directories, off for API-only networks
+        #[serde(default = "default_directory_index")]
+        directory_index: bool,
     },
     DarkWebNetwork {
         name: String,
         linked_paths: Vec<LinkedPath>,
+        // The network's .onion address, set once the service is published
         address: String,
+        #[serde(default = "default_tor_control_port")]
+        tor_control_port: u16,
+        #[serde(default)]
+        tor_control_password: Option<String>,
+        #[serde(default)]
+        access_grants: Vec<AccessGrant>,
+        // HTML pages for directories, off for API-only networks
+        #[serde(default = "default_directory_index")]
+        directory_index: bool,
     },
 }
+
+fn default_port() -> u16 {
+    crate::local_dir::DEFAULT_PORT
+}
+
+fn default_directory_index() -> bool {
+    true
+}
+
+fn default_internet_tls() -> TlsSetting {
+    TlsSetting::SelfSigned
+}
+
+fn default_tor_control_port() -> u16 {
+    crate::tor::DEFAULT_CONTROL_PORT
+}
+
+impl Network {
+    pub fn name(&self) -> &str {
+        match self {
+            Network::LocalNetwork { name, .. } => name,
+            Network::InternetNetwork { name, .. } => name,
+            Network::DarkWebNetwork { name, .. } => name,
+        }
+    }
+
+    pub fn linked_paths(&self) -> &[LinkedPath] {
+        match self {
+            Network::LocalNetwork { linked_paths, .. } => linked_paths,
+            Network::InternetNetwork { linked_paths, .. } => linked_paths,
+            Network::DarkWebNetwork { linked_paths, .. } => linked_paths,
+        }
+    }
+
+    pub fn directory_index(&self) -> bool {
+        match self {
+            Network::LocalNetwork {
+                directory_index, ..
+            } => *directory_index,
+            Network::InternetNetwork {
+                directory_index, ..
+            } => *directory_index,
+            Network::DarkWebNetwork {
+                directory_index, ..
+            } => *directory_index,
+        }
+    }
+
+    pub fn access_grants(&self) -> &[AccessGrant] {
+        match self {
+            Network::LocalNetwork { access_grants, .. } => access_grants,
+            Network::InternetNetwork { access_grants, .. } => access_grants,
+            Network::DarkWebNetwork { access_grants, .. } => access_grants,
+        }
+    }
+
+    pub fn linked_paths_mut(&mut self) -> &mut Vec<LinkedPath> {
+        match self {
+            Network::LocalNetwork { linked_paths, .. } => linked_paths,
+            Network::InternetNetwork { linked_paths, .. } => linked_paths,
+            Network::DarkWebNetwork { linked_paths, .. } => linked_paths,
+        }
+    }
+
+    pub fn access_grants_mut(&mut self) -> &mut Vec<AccessGrant> {
+        match self {
+            Network::LocalNetwork { access_grants, .. } => access_grants,
+            Network::InternetNetwork { access_grants, .. } => access_grants,
+            Network::DarkWebNetwork { access_grants, .. } => access_grants,
+        }
+    }
+}
+
+// A credential that lets clients read a network's shares. Networks without
+// grants (and without an access token) are open to anyone who can connect.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AccessGrant {
+    pub id: String,
+    pub label: String,
+    pub secret: GrantSecret,
+    // Names of the linked paths this grant can read, None for all of them
+    #[serde(default)]
+    pub allowed_paths: Option<Vec<String>>,
+    // Unix timestamp in seconds
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+    // Revoked grants are kept so the list shows who had access
+    #[serde(default)]
+    pub revoked: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum GrantSecret {
+    // Invite token, sent as a bearer token or `?token=`
+    Token {
+        token: String,
+    },
+    // HTTP Basic credentials, the password stored as an Argon2 PHC string
+    Password {
+        username: String,
+        password_hash: String,
+    },
+}
+// Which interfaces a local network's server listens on
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum BindAddress {
+    #[default]
+    Loopback,
+    Interface {
+        ip: IpAddr,
+    },
+    All,
+}
+
+impl BindAddress {
+    pub fn ip(&self) -> IpAddr {
+        match self {
+            BindAddress::Loopback => IpAddr::from([127, 0, 0, 1]),
+            BindAddress::Interface { ip } => *ip,
+            BindAddress::All => IpAddr::from([0, 0, 0, 0]),
+        }
+    }
+}
+
+// Where a network's server gets its certificate from
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum TlsSetting {
+    #[default]
+    Disabled,
+    // Generated on first start and kept in the config directory
+    SelfSigned,
+    // PEM files chosen by the user, read on every start
+    Provided {
+        cert_path: PathBuf,
+        key_path: PathBuf,
+    },
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct NetworkInterface {
+    pub name: String,
+    pub ip: IpAddr,
+    pub is_loopback: bool,
+}
+
+// Public description of a network, served at `/`. Never contains host paths.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NetworkManifest {
+    pub version: u32,
+    pub shares: Vec<ShareSummary>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ShareSummary {
+    // Derived from the name, so it survives restarts and config edits
+    pub id: String,
+    pub name: String,
+    // Total bytes of regular files, symlinks are not followed
+    pub size: u64,
+    pub file_count: u64,
+    pub capabilities: Vec<ShareCapability>,
+}
+
+// What clients can do with a share
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ShareCapability {
+    Read,
+    // Directory listings through `/api/v1/list`
+    List,
+    // Folder downloads with `?archive=zip|tar.gz`
+    Archive,
+    // Uploads, new folders, renames and deletes
+    Write,
+}
+
+// One page of a directory inside a share, from `/api/v1/list/<share>/<path>`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DirectoryListing {
+    pub share: String,
+    // Relative to the share root, "" for the root itself
+    pub path: String,
+    // Number of entries in the directory, not just on this page
+    pub total: usize,
+    pub offset: usize,
+    pub entries: Vec<DirEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DirEntry {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: EntryKind,
+    pub size: u64,
+    // Unix timestamp in seconds
+    pub modified: Option<u64>,
+    // Guessed from the extension, None for directories
+    pub mime: Option<String>,
+}
+
+// Everything below a directory of a share, from `/api/v1/tree/<share>/<path>`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ShareTree {
+    pub share: String,
+    // Relative to the share root, "" for the root itself
+    pub path: String,
+    // Folders come before their contents
+    pub entries: Vec<TreeEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TreeEntry {
+    // Relative to the requested directory
+    pub path: String,
+    #[serde(rename = "type")]
+    pub kind: EntryKind,
+    pub size: u64,
+    // Unix timestamp in seconds
+    pub modified: Option<u64>,
+    // Hex SHA-256 of files, only with `?hash=sha256`
+    pub sha256: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum EntryKind {
+    File,
+    Directory,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum SortKey {
+    #[default]
+    Name,
+    Size,
+    Modified,
+    Type,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+// What a write does when its target name is already taken
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ConflictPolicy {
+    // Picks a free name like "report (1).pdf"
+    #[default]
+    Rename,
+    Overwrite,
+    Fail,
+}
+
+// A file stored by an upload
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UploadedFile {
+    // Relative to the share root, differs from the requested path if renamed
+    pub path: String,
+    pub size: u64,
+}
+
+// A resumable upload, from `/api/v1/uploads/<share>/<id>`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UploadSession {
+    pub id: String,
+    // Where the file goes once complete, relative to the share root
+    pub path: String,
+    pub size: u64,
+    // Bytes received so far, the next chunk has to start here
+    pub offset: u64,
+    // Hex SHA-256 the complete file is checked against
+    pub sha256: Option<String>,
+}
+
+// One line of the audit log, written for every write request to a share
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuditEntry {
+    // Unix timestamp in seconds
+    pub timestamp: u64,
+    pub network: String,
+    pub share: String,
+    pub action: AuditAction,
+    // Relative to the share root
+    pub path: String,
+    // New path of renames, stored path of uploads
+    pub target: Option<String>,
+    pub size: Option<u64>,
+    // None for the network owner and open networks
+    pub grant_id: Option<String>,
+    pub client: Option<String>,
+    // Why the write was refused or failed, None if it succeeded
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum AuditAction {
+    Upload,
+    Mkdir,
+    Rename,
+    Delete,
+}
+
+// A network served by another machine that this app has joined
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RemoteNetwork {
+    pub name: String,
+    // Base URL of the remote server, e.g. `https://192.168.1.20:3030`
+    pub url: String,
+    // Invite token sent as a bearer token, None for open networks
+    #[serde(default)]
+    pub token: Option<String>,
+    // Pinned SHA-256 of the server certificate (`AB:CD:...`), trusted
+    // instead of a CA. None uses the system's usual certificate checks.
+    #[serde(default)]
+    pub fingerprint: Option<String>,
+    // Shares from the last manifest that was fetched
+    #[serde(default)]
+    pub shares: Vec<ShareSummary>,
+    // Unix timestamp in seconds
+    pub joined_at: u64,
+}
+
+// Keeps a local linked path a copy of a folder on a remote network's share.
+// Pull only, local changes inside the linked path get overwritten.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Mirror {
+    pub id: String,
+    pub remote_network: String,
+    pub share: String,
+    // Folder inside the share, "" for all of it
+    #[serde(default)]
+    pub remote_path: String,
+    // Name of the linked path the files are copied into
+    pub linked_path: String,
+    // Synced this often in the background, None for manual syncs only
+    #[serde(default)]
+    pub interval_minutes: Option<u64>,
+    #[serde(default)]
+    pub compare: MirrorCompare,
+    // Remove local files that are gone on the remote side, and let remote
+    // files and folders replace local ones of the other kind
+    #[serde(default)]
+    pub delete_extraneous: bool,
+}
+
+// How a mirror decides that a local file is already up to date
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum MirrorCompare {
+    // Same size and modification time
+    #[default]
+    SizeAndModified,
+    // Same size and SHA-256, slower but catches changes that kept the time
+    Hash,
+}
+
+// Outcome of a mirror's last sync, kept while the app runs
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct MirrorStatus {
+    pub running: bool,
+    // Unix timestamps in seconds
+    pub last_started: Option<u64>,
+    pub last_finished: Option<u64>,
+    pub files_downloaded: u64,
+    pub bytes_downloaded: u64,
+    pub files_deleted: u64,
+    pub error: Option<String>,
+}
+
+// Payload of the `mirror_progress` event
+#[derive(Serialize, Debug, Clone)]
+pub struct MirrorProgress {
+    pub mirror: String,
+    pub phase: MirrorPhase,
+    pub files_done: u64,
+    pub files_total: u64,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    // Path of the file being downloaded, relative to the linked path
+    pub current: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum MirrorPhase {
+    Scanning,
+    Downloading,
+    Deleting,
+    Finished,
+    Failed,
+}
+
+// Something inside a linked path changed on disk, sent to the frontend as
+// `fs_change` and to subscribers of the file watcher's channel
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct FsChangeEvent {
+    // Name of the linked path
+    pub linked_path: String,
+    // '/' separated, relative to the linked path
+    pub relative_path: String,
+    pub kind: FsChangeKind,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum FsChangeKind {
+    Created,
+    Modified,
+    Removed,
+}
+
+// Keeps a local linked path and a remote network's share in step both ways.
+// The remote share has to be writable for the grant the network was joined
+// with.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SyncFolder {
+    pub id: String,
+    pub remote_network: String,
+    pub share: String,
+    pub linked_path: String,
+    // Also synced this often, besides after local changes. None to only sync
+    // on changes and on request.
+    #[serde(default)]
+    pub interval_minutes: Option<u64>,
+}
+
+// How many changes each device made to a file, by device id. A version
+// that has every counter at least as high as another's has seen all of its
+// changes; if each has a counter the other lacks, both sides changed.
+pub type VersionVector = BTreeMap<String, u64>;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FileVersion {
+    pub version: VersionVector,
+    // What the file looked like when the version was recorded
+    pub size: u64,
+    pub modified: Option<u64>,
+    // Hex SHA-256, None once deleted
+    pub sha256: Option<String>,
+    // Deleted files stay in the index so the deletion reaches other devices
+    #[serde(default)]
+    pub deleted: bool,
+}
+
+// Every file a synced folder has, or had, by '/' separated path
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SyncIndex {
+    pub device: String,
+    pub files: BTreeMap<String, FileVersion>,
+}
+
+// Outcome of a sync folder's last run, kept while the app runs
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct SyncStatus {
+    pub running: bool,
+    // Unix timestamps in seconds
+    pub last_started: Option<u64>,
+    pub last_finished: Option<u64>,
+    pub files_sent: u64,
+    pub files_received: u64,
+    // Files changed on both sides, the losing side was kept as a copy
+    pub conflicts: u64,
+    pub error: Option<String>,
+}
+
+// Every file and folder under a linked path as of the last scan plus the
+// watcher's changes since. Kept in `<config dir>/catalogs/<linked path>`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Catalog {
+    // Directory the entries were read from
+    pub root: PathBuf,
+    // Unix timestamp in seconds of the last full scan
+    pub scanned_at: Option<u64>,
+    // By '/' separated path relative to the root
+    pub entries: BTreeMap<String, CatalogEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CatalogEntry {
+    #[serde(rename = "type")]
+    pub kind: EntryKind,
+    // 0 for folders
+    pub size: u64,
+    pub modified: Option<u64>,
+    // Inode number, None where the platform has none
+    pub file_id: Option<u64>,
+    // Hex SHA-256 of files that were hashed, dropped once the file changes
+    pub sha256: Option<String>,
+}
+
+// Files with the same content across all linked paths. Files reached
+// through overlapping linked paths and existing hard links count once.
+#[derive(Serialize, Debug, Clone)]
+pub struct DuplicateReport {
+    pub groups: Vec<DuplicateGroup>,
+    // Bytes that deduplicating every group would free
+    pub wasted_bytes: u64,
+    // Files not hashed yet, their duplicates aren't reported until they are
+    pub unhashed_files: u64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DuplicateGroup {
+    pub sha256: String,
+    // Of each file
+    pub size: u64,
+    pub files: Vec<DuplicateFile>,
+    pub wasted_bytes: u64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DuplicateFile {
+    pub linked_path: String,
+    pub path: String,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct DedupeResult {
+    // Files replaced by a hard link
+    pub linked: u64,
+    pub freed_bytes: u64,
+    // Files left as they were, with why
+    pub failed: Vec<String>,
+}
+
+// Files whose words were indexed for full-text search, with the words
+// pointing at them. Kept next to the linked path's catalog.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TextIndex {
+    pub next_id: u32,
+    // By '/' separated path relative to the linked path
+    pub files: BTreeMap<String, IndexedFile>,
+    // Lowercased word to the ids of the files it's in, ascending
+    pub words: HashMap<String, Vec<u32>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IndexedFile {
+    pub id: u32,
+    // What the file looked like when its text was read
+    pub size: u64,
+    pub modified: Option<u64>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SearchResults {
+    // Hits before `limit` was applied
+    pub total: usize,
+    pub hits: Vec<SearchHit>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SearchHit {
+    // Linked path, or share name on the server
+    pub share: String,
+    pub path: String,
+    #[serde(rename = "type")]
+    pub kind: EntryKind,
+    pub size: u64,
+    pub modified: Option<u64>,
+    // Higher is better, only comparable within one search
+    pub score: u32,
+    pub matched: SearchMatch,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum SearchMatch {
+    Name,
+    Content,
+    // The name matched and the contents have every word
+    Both,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CatalogStatus {
+    pub linked_path: String,
+    // A full scan is running, the entries are from before it
+    pub scanning: bool,
+    pub scanned_at: Option<u64>,
+    pub files: u64,
+    pub directories: u64,
+    pub bytes: u64,
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub enum ServerMode {
     LocalHost,
     Internet,
     DarkWeb,
 }
+// Running file servers keyed by network name, plus the last known status of
+// every server that was started
 #[derive(Default)]
 pub struct ServerState {
-    pub shutdown_tx: Option<tokio::sync::mpsc::Sender<()>>,
+    pub servers: HashMap<String, RunningServer>,
+    pub statuses: HashMap<String, ServerStatus>,
+    // Ids of servers that are still being started, by network name. Taking
+    // one out cancels that start.
+    pub starting: HashMap<String, u64>,
+    pub next_server_id: u64,
+}
+
+pub struct RunningServer {
+    // Tells a finished task apart from a newer server started under the same name
+    pub id: u64,
+    pub shutdown_tx: tokio::sync::mpsc::Sender<()>,
+    pub task: tauri::async_runtime::JoinHandle<()>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "state", rename_all = "camelCase")]
+pub enum ServerStatus {
+    Starting,
+    Running {
+        address: String,
+        // Set once a port mapping or onion service is in place
+        #[serde(skip_serializing_if = "Option::is_none")]
+        public_address: Option<String>,
+    },
+    Failed {
+        error: String,
+    },
+    Stopped,
+}
+
+// Payload of the `server_status_changed` event
+#[derive(Serialize, Debug, Clone)]
+pub struct ServerStatusEvent {
+    pub network: String,
+    pub status: ServerStatus,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -63,6 +788,19 @@ pub enum FileError {
     SerdeJsonError(#[from] serde_json::Error),
     #[error("missing 'linked_paths' field in the JSON")]
     MissingLinkedPathsError,
+    #[error("invalid config: {0}")]
+    InvalidConfigError(String),
+    #[error("config version {0} is newer than this version of Quartz supports")]
+    UnsupportedConfigVersionError(u32),
+    #[error("an entry named '{0}' already exists")]
+    DuplicateNameError(String),
+    #[error("'{0}' not found")]
+    NotFoundError(String),
+    #[error("config was changed elsewhere (expected revision {expected}, found {actual})")]
+    ConflictError { expected: u64, actual: u64 },
+    // A directory the overlap policy doesn't allow linking, with the reason
+    #[error("{0}")]
+    OverlapError(String),
 }
 
 impl serde::Serialize for FileError {
@@ -74,6 +812,102 @@ impl serde::Serialize for FileError {
     }
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum ServerError {
+    #[error(transparent)]
+    ConfigError(#[from] FileError),
+    #[error("network '{0}' not found")]
+    NetworkNotFound(String),
+    #[error("server for '{0}' is already running")]
+    AlreadyRunning(String),
+    #[error("server for '{0}' is not running")]
+    NotRunning(String),
+    #[error("failed to start server: {0}")]
+    BindError(String),
+    #[error("network '{0}' has no access token")]
+    MissingAccessToken(String),
+    #[error("internet servers require TLS and an access token")]
+    InsecureInternetServer,
+    #[error("dark web servers must only listen on loopback")]
+    ExposedDarkWebServer,
+    #[error("TLS error: {0}")]
+    TlsError(String),
+    #[error(transparent)]
+    TorError(#[from] TorError),
+}
+
+impl serde::Serialize for ServerError {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        serializer.serialize_str(self.to_string().as_ref())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TorError {
+    #[error("failed to reach Tor control port: {0}")]
+    ConnectError(String),
+    #[error("Tor authentication failed: {0}")]
+    AuthError(String),
+    #[error("Tor rejected command: {0}")]
+    CommandError(String),
+    #[error("unexpected reply from Tor: {0}")]
+    ProtocolError(String),
+    #[error("failed to store onion key: {0}")]
+    KeyError(String),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PortMappingError {
+    #[error("no port mapping available (UPnP: {upnp}; NAT-PMP: {natpmp})")]
+    Unavailable { upnp: String, natpmp: String },
+    #[error("failed to renew port mapping: {0}")]
+    RenewError(String),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RemoteError {
+    #[error(transparent)]
+    ConfigError(#[from] FileError),
+    #[error("remote network '{0}' not found")]
+    NetworkNotFound(String),
+    #[error("invalid address: {0}")]
+    InvalidAddress(String),
+    #[error("failed to reach the remote network: {0}")]
+    ConnectError(String),
+    #[error("server certificate doesn't match the pinned fingerprint")]
+    FingerprintMismatch,
+    #[error("remote network answered {status}: {message}")]
+    StatusError { status: u16, message: String },
+    #[error("invalid manifest: {0}")]
+    InvalidManifest(String),
+    #[error("unexpected response: {0}")]
+    InvalidResponse(String),
+    #[error("download failed: {0}")]
+    DownloadError(String),
+    #[error("mirror '{0}' not found")]
+    MirrorNotFound(String),
+    #[error("mirror '{0}' is already syncing")]
+    MirrorBusy(String),
+    #[error("sync failed: {0}")]
+    SyncError(String),
+    #[error("sync folder '{0}' not found")]
+    SyncFolderNotFound(String),
+    #[error("sync folder '{0}' is already syncing")]
+    SyncFolderBusy(String),
+}
+
+impl serde::Serialize for RemoteError {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        serializer.serialize_str(self.to_string().as_ref())
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum FileWatcherError {
     #[error("Failed to create debouncer")]
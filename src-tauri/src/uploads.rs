@@ -0,0 +1,865 @@
+//Uses
+use crate::audit;
+use crate::auth::{unix_time, Access, SharedPolicy};
+use crate::listing::{
+    authorized_share_path, entry_metadata, is_plain_name, is_reserved_name, normalize_path,
+    resolve_share_path, ShareMap,
+};
+use crate::types::{AuditAction, AuditEntry, ConflictPolicy, LinkedPath, UploadedFile};
+use futures_util::{Stream, StreamExt};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex as StdMutex;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use warp::filters::multipart::FormData;
+use warp::filters::BoxedFilter;
+use warp::http::{Method, StatusCode};
+use warp::hyper::body::Buf;
+use warp::{Filter, Rejection, Reply};
+
+// Free names tried for `ConflictPolicy::Rename` before giving up
+const MAX_RENAME_ATTEMPTS: u32 = 1000;
+
+// How long a share's measured usage is trusted before the folder is walked
+// again, deletes and changes made outside the server show up after that
+const USAGE_TTL: Duration = Duration::from_secs(30);
+
+// Usage of shares with a quota, by share folder. Writes reserve their bytes
+// here first, so concurrent uploads can't each fit into the same free space.
+lazy_static::lazy_static! {
+    static ref QUOTA_USAGE: StdMutex<HashMap<PathBuf, ShareUsage>> =
+        StdMutex::new(HashMap::new());
+}
+
+struct ShareUsage {
+    used: u64,
+    measured: Instant,
+    // Bytes claimed by writes still in progress
+    reserved: u64,
+}
+
+// Who writes where, recorded in the audit log
+#[derive(Clone)]
+pub struct WriteRequest {
+    pub network: String,
+    pub access: Access,
+    pub client: Option<SocketAddr>,
+    pub share: LinkedPath,
+    pub relative: String,
+}
+
+#[derive(Deserialize)]
+struct RenameBody {
+    // New path relative to the same share
+    to: String,
+}
+
+// Why a write was refused, decides the response status
+#[derive(Debug)]
+pub enum WriteError {
+    ReadOnly,
+    NotFound,
+    InvalidRequest(String),
+    Conflict(String),
+    QuotaExceeded,
+    // The data doesn't hash to what the client announced
+    ChecksumMismatch,
+    Io(io::Error),
+}
+
+impl From<io::Error> for WriteError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::NotFound => WriteError::NotFound,
+            io::ErrorKind::AlreadyExists => WriteError::Conflict("target already exists".into()),
+            _ => WriteError::Io(e),
+        }
+    }
+}
+
+impl WriteError {
+    pub fn message(&self) -> String {
+        match self {
+            WriteError::ReadOnly => "Share is read-only".into(),
+            WriteError::NotFound => "Not Found".into(),
+            WriteError::InvalidRequest(message) | WriteError::Conflict(message) => message.clone(),
+            WriteError::QuotaExceeded => "Share quota exceeded".into(),
+            WriteError::ChecksumMismatch => "Checksum mismatch".into(),
+            WriteError::Io(e) => format!("Write failed: {}", e),
+        }
+    }
+
+    pub fn into_response(self) -> warp::reply::Response {
+        let status = match &self {
+            WriteError::ReadOnly => StatusCode::FORBIDDEN,
+            WriteError::NotFound => StatusCode::NOT_FOUND,
+            WriteError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            WriteError::Conflict(_) => StatusCode::CONFLICT,
+            WriteError::QuotaExceeded => StatusCode::INSUFFICIENT_STORAGE,
+            WriteError::ChecksumMismatch => StatusCode::UNPROCESSABLE_ENTITY,
+            WriteError::Io(e) => {
+                eprintln!("Failed to write to share: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        };
+        warp::reply::with_status(self.message(), status).into_response()
+    }
+}
+
+// Like `warp::post()` and friends, but other methods are rejected as not
+// found. warp reports a 405 over a 404, which would hide the read routes'
+// 404s for paths that don't exist.
+pub fn method(method: Method) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::method()
+        .and_then(move |actual: Method| {
+            let matches = actual == method;
+            async move {
+                if matches {
+                    Ok(())
+                } else {
+                    Err(warp::reject::not_found())
+                }
+            }
+        })
+        .untuple_one()
+}
+
+// Matches `/<share>/<path>` like `share_path` and records who is asking
+pub fn write_request(
+    shares: ShareMap,
+    access: SharedPolicy,
+    network: String,
+) -> impl Filter<Extract = (WriteRequest,), Error = Rejection> + Clone {
+    warp::addr::remote()
+        .and(authorized_share_path(shares, access))
+        .map(
+            move |client: Option<SocketAddr>,
+                  access: Access,
+                  share: LinkedPath,
+                  relative: String| {
+                WriteRequest {
+                    network: network.clone(),
+                    access,
+                    client,
+                    share,
+                    relative,
+                }
+            },
+        )
+}
+
+// Write access to shares that allow it:
+// - `PUT /<share>/<path>` stores the raw request body as a file
+// - `POST /<share>/<dir>` stores every file of a multipart form in a folder
+// - `DELETE /<share>/<path>`, folders with content need `?recursive=true`
+// - `POST /api/v1/mkdir/<share>/<path>`
+// - `POST /api/v1/rename/<share>/<path>` with `{"to": "<new path>"}`
+// Uploads and renames take `?conflict=rename|overwrite|fail`. Every request
+// that gets past authentication ends up in the audit log.
+pub fn write_routes(
+    shares: ShareMap,
+    access: SharedPolicy,
+    network: String,
+) -> BoxedFilter<(warp::reply::Response,)> {
+    let request = write_request(shares, access, network);
+    let query = warp::query::<HashMap<String, String>>();
+
+    let mkdir = method(Method::POST)
+        .and(warp::path!("api" / "v1" / "mkdir" / ..))
+        .and(request.clone())
+        .then(make_dir);
+    let rename = method(Method::POST)
+        .and(warp::path!("api" / "v1" / "rename" / ..))
+        .and(request.clone())
+        .and(query)
+        .and(warp::body::json::<RenameBody>())
+        .then(rename_entry);
+    let put = method(Method::PUT)
+        .and(request.clone())
+        .and(query)
+        .and(warp::header::optional::<u64>("content-length"))
+        .and(warp::body::stream())
+        .then(put_file);
+    let form = method(Method::POST)
+        .and(request.clone())
+        .and(query)
+        .and(warp::multipart::form().max_length(None))
+        .then(upload_form);
+    let delete = method(Method::DELETE)
+        .and(request)
+        .and(query)
+        .then(delete_entry);
+
+    mkdir
+        .or(rename)
+        .unify()
+        .or(put)
+        .unify()
+        .or(form)
+        .unify()
+        .or(delete)
+        .unify()
+        .boxed()
+}
+
+async fn put_file<S, B>(
+    request: WriteRequest,
+    query: HashMap<String, String>,
+    content_length: Option<u64>,
+    body: S,
+) -> warp::reply::Response
+where
+    S: Stream<Item = Result<B, warp::Error>>,
+    B: Buf,
+{
+    let result = async {
+        let conflict = conflict_policy(&query)?;
+        let entry = writable_entry(&request)?;
+        store_upload(&request.share, &entry, conflict, content_length, body).await
+    }
+    .await;
+    audit_upload(&request, &request.relative, result.as_ref());
+    match result {
+        Ok(file) => created(&file),
+        Err(e) => e.into_response(),
+    }
+}
+
+async fn upload_form(
+    request: WriteRequest,
+    query: HashMap<String, String>,
+    mut form: FormData,
+) -> warp::reply::Response {
+    let setup = conflict_policy(&query).and_then(|conflict| {
+        writable(&request.share)?;
+        let dir = resolve_share_path(&request.share, &request.relative)
+            .filter(|dir| dir.is_dir())
+            .ok_or(WriteError::NotFound)?;
+        Ok((conflict, dir))
+    });
+    let (conflict, dir) = match setup {
+        Ok(setup) => setup,
+        Err(e) => {
+            audit_upload(&request, &request.relative, Err(&e));
+            return e.into_response();
+        }
+    };
+
+    let mut stored = Vec::new();
+    while let Some(part) = form.next().await {
+        let part = match part {
+            Ok(part) => part,
+            Err(e) => {
+                let e = WriteError::InvalidRequest(format!("Invalid form data: {}", e));
+                audit_upload(&request, &request.relative, Err(&e));
+                return e.into_response();
+            }
+        };
+        // Fields without a file name are form values, not files
+        let Some(filename) = part.filename().map(base_name) else {
+            continue;
+        };
+        let entry = Entry {
+            dir: dir.clone(),
+            parent: normalize_path(&request.relative),
+            name: filename.clone(),
+        };
+        let result = match check_name(&request.share, &filename) {
+            Ok(()) => store_upload(&request.share, &entry, conflict, None, part.stream()).await,
+            Err(e) => Err(e),
+        };
+        audit_upload(&request, &entry.relative(&filename), result.as_ref());
+        match result {
+            Ok(file) => stored.push(file),
+            Err(e) => return e.into_response(),
+        }
+    }
+    warp::reply::with_status(warp::reply::json(&stored), StatusCode::CREATED).into_response()
+}
+
+async fn make_dir(request: WriteRequest) -> warp::reply::Response {
+    let blocking_request = request.clone();
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let entry = writable_entry(&blocking_request)?;
+        fs::create_dir(entry.path())?;
+        Ok(entry.relative(&entry.name))
+    })
+    .await
+    .map_err(|e| WriteError::Io(io::Error::other(e.to_string())))
+    .and_then(|result| result);
+    audit(&request, AuditAction::Mkdir, result.as_ref(), |_| {
+        (None, None)
+    });
+    match result {
+        Ok(path) => created(&serde_json::json!({ "path": path })),
+        Err(e) => e.into_response(),
+    }
+}
+
+async fn rename_entry(
+    request: WriteRequest,
+    query: HashMap<String, String>,
+    body: RenameBody,
+) -> warp::reply::Response {
+    let result = async {
+        let conflict = conflict_policy(&query)?;
+        let source = existing_entry(&request)?;
+        let target = resolve_entry(&request.share, &body.to)?;
+        let source_path = source.path();
+        // Moving a folder below itself can't work
+        if target.dir.starts_with(&source_path) {
+            return Err(WriteError::Conflict(
+                "Cannot move a folder into itself".into(),
+            ));
+        }
+        let name = tauri::async_runtime::spawn_blocking(move || {
+            place(&source_path, &target.dir, &target.name, conflict).map(|name| (target, name))
+        })
+        .await
+        .map_err(|e| WriteError::Io(io::Error::other(e.to_string())))?;
+        let (target, name) = name?;
+        Ok(target.relative(&name))
+    }
+    .await;
+    audit(&request, AuditAction::Rename, result.as_ref(), |path| {
+        (Some(path.clone()), None)
+    });
+    match result {
+        Ok(path) => warp::reply::json(&serde_json::json!({ "path": path })).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+async fn delete_entry(
+    request: WriteRequest,
+    query: HashMap<String, String>,
+) -> warp::reply::Response {
+    let recursive = query.get("recursive").is_some_and(|value| value == "true");
+    let blocking_request = request.clone();
+    // Removing a large tree takes a while
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let entry = existing_entry(&blocking_request)?;
+        let path = entry.path();
+        let metadata = fs::symlink_metadata(&path)?;
+        if !metadata.is_dir() {
+            // Symlinks are removed, never what they point to
+            fs::remove_file(&path)?;
+        } else if recursive {
+            fs::remove_dir_all(&path)?;
+        } else if fs::read_dir(&path)?.next().is_some() {
+            return Err(WriteError::Conflict(
+                "Folder is not empty, use ?recursive=true".into(),
+            ));
+        } else {
+            fs::remove_dir(&path)?;
+        }
+        Ok(())
+    })
+    .await
+    .map_err(|e| WriteError::Io(io::Error::other(e.to_string())))
+    .and_then(|result| result);
+    audit(&request, AuditAction::Delete, result.as_ref(), |_| {
+        (None, None)
+    });
+    match result {
+        Ok(()) => warp::reply::with_status("", StatusCode::NO_CONTENT).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+// A path below a share split into its parent folder, which exists, and the
+// last segment
+pub struct Entry {
+    pub dir: PathBuf,
+    // Parent relative to the share root, "" for the root itself
+    pub parent: String,
+    pub name: String,
+}
+
+impl Entry {
+    pub fn path(&self) -> PathBuf {
+        self.dir.join(&self.name)
+    }
+
+    // Path relative to the share root of `name` next to this entry
+    pub fn relative(&self, name: &str) -> String {
+        if self.parent.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}/{}", self.parent, name)
+        }
+    }
+}
+
+// The share root itself can't be created, renamed or deleted
+pub fn resolve_entry(share: &LinkedPath, relative: &str) -> Result<Entry, WriteError> {
+    let relative = normalize_path(relative);
+    let (parent, name) = relative.rsplit_once('/').unwrap_or(("", &relative));
+    check_name(share, name)?;
+    let dir = resolve_share_path(share, parent)
+        .filter(|dir| dir.is_dir())
+        .ok_or(WriteError::NotFound)?;
+    Ok(Entry {
+        dir,
+        parent: parent.to_string(),
+        name: name.to_string(),
+    })
+}
+
+pub fn writable_entry(request: &WriteRequest) -> Result<Entry, WriteError> {
+    writable(&request.share)?;
+    resolve_entry(&request.share, &request.relative)
+}
+
+// Entries hidden from readers can't be renamed or deleted either
+fn existing_entry(request: &WriteRequest) -> Result<Entry, WriteError> {
+    let entry = writable_entry(request)?;
+    let root = fs::canonicalize(&request.share.path)?;
+    entry_metadata(&request.share, &root, &entry.path(), &entry.name)
+        .ok_or(WriteError::NotFound)?;
+    Ok(entry)
+}
+
+pub fn writable(share: &LinkedPath) -> Result<(), WriteError> {
+    if share.writable {
+        Ok(())
+    } else {
+        Err(WriteError::ReadOnly)
+    }
+}
+
+// New names follow the rules reads do, so nothing can be written that the
+// share wouldn't serve
+fn check_name(share: &LinkedPath, name: &str) -> Result<(), WriteError> {
+    if name.is_empty() || !is_plain_name(name) || is_reserved_name(name) {
+        return Err(WriteError::InvalidRequest(format!(
+            "Invalid name '{}'",
+            name
+        )));
+    }
+    if !share.show_hidden && name.starts_with('.') {
+        return Err(WriteError::InvalidRequest(
+            "Hidden files are not allowed in this share".into(),
+        ));
+    }
+    Ok(())
+}
+
+// Browsers send the bare name, some older ones the whole client side path
+fn base_name(filename: &str) -> String {
+    filename
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+pub fn conflict_policy(query: &HashMap<String, String>) -> Result<ConflictPolicy, WriteError> {
+    match query.get("conflict").map(String::as_str) {
+        None | Some("rename") => Ok(ConflictPolicy::Rename),
+        Some("overwrite") => Ok(ConflictPolicy::Overwrite),
+        Some("fail") => Ok(ConflictPolicy::Fail),
+        Some(other) => Err(WriteError::InvalidRequest(format!(
+            "Unknown conflict policy '{}', use rename, overwrite or fail",
+            other
+        ))),
+    }
+}
+
+// Streams the body into a temporary file next to the target and moves it
+// into place once complete, so nobody reads a half written file
+async fn store_upload<S, B>(
+    share: &LinkedPath,
+    entry: &Entry,
+    conflict: ConflictPolicy,
+    expected_len: Option<u64>,
+    body: S,
+) -> Result<UploadedFile, WriteError>
+where
+    S: Stream<Item = Result<B, warp::Error>>,
+    B: Buf,
+{
+    let mut reservation = reserve_quota(share, entry, conflict).await?;
+    if let Some(len) = expected_len {
+        reservation.reserve_total(len)?;
+    }
+
+    let temp = entry.dir.join(format!(
+        ".{}.{:08x}.part",
+        entry.name,
+        rand::random::<u32>()
+    ));
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temp)
+        .await?;
+    let mut body = std::pin::pin!(body);
+    let written = async {
+        let mut written = 0u64;
+        while let Some(chunk) = body.next().await {
+            let mut chunk = chunk
+                .map_err(|e| WriteError::InvalidRequest(format!("Upload interrupted: {}", e)))?;
+            written += chunk.remaining() as u64;
+            reservation.reserve_total(written)?;
+            file.write_all_buf(&mut chunk).await?;
+        }
+        file.sync_all().await?;
+        Ok(written)
+    }
+    .await;
+    drop(file);
+    let written = match written {
+        Ok(written) => written,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&temp).await;
+            return Err(e);
+        }
+    };
+
+    let (dir, name) = (entry.dir.clone(), entry.name.clone());
+    let placed = tauri::async_runtime::spawn_blocking(move || {
+        let placed = place(&temp, &dir, &name, conflict);
+        if placed.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+        placed
+    })
+    .await
+    .map_err(|e| WriteError::Io(io::Error::other(e.to_string())))??;
+    reservation.commit(written);
+    Ok(UploadedFile {
+        path: entry.relative(&placed),
+        size: written,
+    })
+}
+
+// Room a write holds in its share's quota until it's committed or dropped.
+// Shares without a quota accept everything.
+pub struct QuotaReservation {
+    root: Option<PathBuf>,
+    quota: u64,
+    // Size of the file the write replaces, it doesn't count against the quota
+    replaced: u64,
+    bytes: u64,
+}
+
+impl QuotaReservation {
+    // Grows the reservation to `total` bytes, failing when the share can't
+    // hold them next to what's stored and what other writes reserved
+    pub fn reserve_total(&mut self, total: u64) -> Result<(), WriteError> {
+        let Some(root) = &self.root else {
+            return Ok(());
+        };
+        if total <= self.bytes {
+            return Ok(());
+        }
+        let mut usages = QUOTA_USAGE.lock().unwrap();
+        let Some(usage) = usages.get_mut(root) else {
+            return Err(WriteError::QuotaExceeded);
+        };
+        let extra = total - self.bytes;
+        let claimed = usage
+            .used
+            .saturating_add(usage.reserved)
+            .saturating_add(extra);
+        if claimed > self.quota.saturating_add(self.replaced) {
+            return Err(WriteError::QuotaExceeded);
+        }
+        usage.reserved += extra;
+        self.bytes = total;
+        Ok(())
+    }
+
+    // Counts `stored` bytes as used once the write is in place, until the
+    // share is measured again
+    pub fn commit(mut self, stored: u64) {
+        if let Some(root) = &self.root {
+            if let Some(usage) = QUOTA_USAGE.lock().unwrap().get_mut(root) {
+                usage.used = (usage.used + stored).saturating_sub(self.replaced);
+            }
+        }
+        self.release();
+    }
+
+    fn release(&mut self) {
+        if let Some(root) = &self.root {
+            if let Some(usage) = QUOTA_USAGE.lock().unwrap().get_mut(root) {
+                usage.reserved = usage.reserved.saturating_sub(self.bytes);
+            }
+        }
+        self.bytes = 0;
+    }
+}
+
+impl Drop for QuotaReservation {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+// Starts an empty reservation for a write to `entry`. The share is only
+// walked when its usage is unknown or stale and no other write is reserving.
+pub async fn reserve_quota(
+    share: &LinkedPath,
+    entry: &Entry,
+    conflict: ConflictPolicy,
+) -> Result<QuotaReservation, WriteError> {
+    let Some(quota) = share.quota_bytes else {
+        return Ok(QuotaReservation {
+            root: None,
+            quota: 0,
+            replaced: 0,
+            bytes: 0,
+        });
+    };
+    let root = share.path.clone();
+    let stale = QUOTA_USAGE
+        .lock()
+        .unwrap()
+        .get(&root)
+        .is_none_or(|usage| usage.reserved == 0 && usage.measured.elapsed() > USAGE_TTL);
+    let target = entry.path();
+    let walk_root = root.clone();
+    let (used, replaced) = tauri::async_runtime::spawn_blocking(move || {
+        let replaced = match conflict {
+            ConflictPolicy::Overwrite => fs::symlink_metadata(&target)
+                .ok()
+                .filter(|metadata| metadata.is_file())
+                .map_or(0, |metadata| metadata.len()),
+            ConflictPolicy::Rename | ConflictPolicy::Fail => 0,
+        };
+        (stale.then(|| disk_usage(&walk_root)), replaced)
+    })
+    .await
+    .map_err(|e| WriteError::Io(io::Error::other(e.to_string())))?;
+
+    let mut usages = QUOTA_USAGE.lock().unwrap();
+    match (usages.get_mut(&root), used) {
+        // A write that started during the walk already counts in `reserved`
+        (Some(usage), Some(used)) => {
+            usage.used = used;
+            usage.measured = Instant::now();
+        }
+        (Some(_), None) => {}
+        (None, used) => {
+            usages.insert(
+                root.clone(),
+                ShareUsage {
+                    used: used.unwrap_or(0),
+                    measured: Instant::now(),
+                    reserved: 0,
+                },
+            );
+        }
+    }
+    Ok(QuotaReservation {
+        root: Some(root),
+        quota,
+        replaced,
+        bytes: 0,
+    })
+}
+
+// Everything stored in the folder, hidden files included, links not followed
+pub fn disk_usage(path: &Path) -> u64 {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if metadata.is_file() {
+        return metadata.len();
+    }
+    if !metadata.is_dir() {
+        return 0;
+    }
+    fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| disk_usage(&entry.path()))
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+// Moves `source` to `name` inside `dir` and returns the name it ended up
+// with. Folders are never overwritten.
+pub fn place(
+    source: &Path,
+    dir: &Path,
+    name: &str,
+    conflict: ConflictPolicy,
+) -> Result<String, WriteError> {
+    let target = dir.join(name);
+    match conflict {
+        ConflictPolicy::Overwrite => {
+            if fs::symlink_metadata(&target).is_ok_and(|metadata| metadata.is_dir()) {
+                return Err(WriteError::Conflict(format!("'{}' is a folder", name)));
+            }
+            fs::rename(source, &target)?;
+            Ok(name.to_string())
+        }
+        ConflictPolicy::Fail => match move_new(source, &target) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                Err(WriteError::Conflict(format!("'{}' already exists", name)))
+            }
+            result => result.map(|()| name.to_string()).map_err(WriteError::from),
+        },
+        ConflictPolicy::Rename => {
+            for attempt in 0..MAX_RENAME_ATTEMPTS {
+                let candidate = numbered_name(name, attempt);
+                match move_new(source, &dir.join(&candidate)) {
+                    Ok(()) => return Ok(candidate),
+                    Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            Err(WriteError::Conflict(format!("No free name for '{}'", name)))
+        }
+    }
+}
+
+// A plain rename would replace an existing file, a hard link fails instead.
+// Folders and file systems without hard links fall back to checking first.
+fn move_new(source: &Path, target: &Path) -> io::Result<()> {
+    let is_file = fs::symlink_metadata(source)?.is_file();
+    if is_file {
+        match fs::hard_link(source, target) {
+            Ok(()) => return fs::remove_file(source),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return Err(e),
+            Err(_) => {}
+        }
+    }
+    if fs::symlink_metadata(target).is_ok() {
+        return Err(io::ErrorKind::AlreadyExists.into());
+    }
+    fs::rename(source, target)
+}
+
+// "report.pdf", "report (1).pdf", "report (2).pdf", ...
+fn numbered_name(name: &str, attempt: u32) -> String {
+    if attempt == 0 {
+        return name.to_string();
+    }
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => {
+            format!("{} ({}).{}", stem, attempt, extension)
+        }
+        _ => format!("{} ({})", name, attempt),
+    }
+}
+
+pub fn created(body: &impl serde::Serialize) -> warp::reply::Response {
+    warp::reply::with_status(warp::reply::json(body), StatusCode::CREATED).into_response()
+}
+
+pub fn audit_upload(
+    request: &WriteRequest,
+    path: &str,
+    result: Result<&UploadedFile, &WriteError>,
+) {
+    let request = WriteRequest {
+        relative: path.to_string(),
+        ..request.clone()
+    };
+    audit(&request, AuditAction::Upload, result, |file| {
+        (Some(file.path.clone()), Some(file.size))
+    });
+}
+
+// `details` gives the target and size of successful writes
+pub fn audit<T>(
+    request: &WriteRequest,
+    action: AuditAction,
+    result: Result<&T, &WriteError>,
+    details: impl FnOnce(&T) -> (Option<String>, Option<u64>),
+) {
+    let (target, size, error) = match result {
+        Ok(value) => {
+            let (target, size) = details(value);
+            (target, size, None)
+        }
+        Err(e) => (None, None, Some(e.message())),
+    };
+    audit::record(&AuditEntry {
+        timestamp: unix_time(),
+        network: request.network.clone(),
+        share: request.share.name.clone(),
+        action,
+        path: normalize_path(&request.relative),
+        target,
+        size,
+        grant_id: request.access.grant_id.clone(),
+        client: request.client.map(|client| client.to_string()),
+        error,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{reserve_quota, Entry, WriteError};
+    use crate::types::{ConflictPolicy, LinkedPath, SymlinkPolicy};
+    use std::fs;
+
+    #[tokio::test]
+    async fn concurrent_writes_cannot_share_free_space() {
+        let dir = std::env::temp_dir().join(format!("quartz-quota-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("old.bin"), vec![0u8; 400]).unwrap();
+        let share = LinkedPath {
+            name: "quota".to_string(),
+            path: dir.clone(),
+            show_hidden: false,
+            symlinks: SymlinkPolicy::default(),
+            writable: true,
+            quota_bytes: Some(1000),
+            full_text: false,
+        };
+        let entry = |name: &str| Entry {
+            dir: dir.clone(),
+            parent: String::new(),
+            name: name.to_string(),
+        };
+
+        // 600 bytes are free, the second write only gets what the first left
+        let mut first = reserve_quota(&share, &entry("a.bin"), ConflictPolicy::Rename)
+            .await
+            .unwrap();
+        let mut second = reserve_quota(&share, &entry("b.bin"), ConflictPolicy::Rename)
+            .await
+            .unwrap();
+        first.reserve_total(500).unwrap();
+        assert!(matches!(
+            second.reserve_total(200),
+            Err(WriteError::QuotaExceeded)
+        ));
+        second.reserve_total(100).unwrap();
+
+        // A failed write gives its room back, a finished one keeps it
+        drop(second);
+        first.commit(500);
+        let mut third = reserve_quota(&share, &entry("c.bin"), ConflictPolicy::Rename)
+            .await
+            .unwrap();
+        third.reserve_total(100).unwrap();
+        assert!(matches!(
+            third.reserve_total(101),
+            Err(WriteError::QuotaExceeded)
+        ));
+        drop(third);
+
+        // Overwriting frees the replaced file's bytes
+        let mut overwrite = reserve_quota(&share, &entry("old.bin"), ConflictPolicy::Overwrite)
+            .await
+            .unwrap();
+        overwrite.reserve_total(500).unwrap();
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,4 +1,53 @@
-import { writable } from 'svelte/store'
+import { invoke } from '@tauri-apps/api/core'
+import { get, writable } from 'svelte/store'
 
 export const linked_paths = writable([] as LinkedPath[])
 export const networks = writable([] as Network[])
+// Config revision the data above was read at. Commands that change the
+// config get it back and fail instead of overwriting changes made elsewhere.
+export const config_revision = writable(0)
+
+export async function loadConfigRevision() {
+    config_revision.set(await invoke<number>('get_config_revision'))
+}
+
+// Read before the data, so the data is never older than the revision
+export async function loadLinkedPaths() {
+    await loadConfigRevision()
+    linked_paths.set(await invoke<LinkedPath[]>('get_linked_paths'))
+}
+
+export async function loadNetworks() {
+    await loadConfigRevision()
+    networks.set(await invoke<Network[]>('read_private_networks'))
+}
+
+function isConfigConflict(e: unknown) {
+    return `${e}`.startsWith('config was changed elsewhere')
+}
+
+// Runs a command that changes the config at the revision shown. When the
+// config changed elsewhere in the meantime, the data is reloaded and the
+// user is asked to check it and try again.
+export async function invokeMutation<T>(
+    command: string,
+    args: Record<string, unknown> = {}
+): Promise<T> {
+    try {
+        const result = await invoke<T>(command, {
+            ...args,
+            revision: get(config_revision),
+        })
+        // Every successful change moves the config one revision on
+        config_revision.update((revision) => revision + 1)
+        return result
+    } catch (e) {
+        if (isConfigConflict(e)) {
+            await Promise.all([loadLinkedPaths(), loadNetworks()]).catch((e) =>
+                console.error(e)
+            )
+            throw 'The configuration was changed elsewhere, check it and try again'
+        }
+        throw e
+    }
+}
@@ -1,25 +1,290 @@
+type SymlinkPolicy = 'withinShare' | 'never'
+
 interface LinkedPath {
     name: string
     path: string
+    show_hidden: boolean
+    symlinks: SymlinkPolicy
+    writable: boolean
+    quota_bytes: number | null
+    full_text: boolean
 }
 interface BaseNetwork {
     name: string
     linked_paths: LinkedPath[]
+    access_grants: AccessGrant[]
+    directory_index: boolean
 }
 
+type GrantSecret =
+    | { type: 'token'; token: string }
+    | { type: 'password'; username: string; password_hash: string }
+
+interface AccessGrant {
+    id: string
+    label: string
+    secret: GrantSecret
+    allowed_paths: string[] | null
+    expires_at: number | null
+    revoked: boolean
+}
+
+type BindAddress =
+    | { type: 'loopback' }
+    | { type: 'interface'; ip: string }
+    | { type: 'all' }
+
+type TlsSetting =
+    | { type: 'disabled' }
+    | { type: 'selfSigned' }
+    | { type: 'provided'; cert_path: string; key_path: string }
+
 interface LocalNetwork extends BaseNetwork {
     port: number
+    bind_address: BindAddress
+    tls: TlsSetting
 }
 
 interface InternetNetwork extends BaseNetwork {
     address: string
+    port: number
+    access_token: string
+    tls: TlsSetting
 }
 
 interface DarkWebNetwork extends BaseNetwork {
     address: string
+    tor_control_port: number
+    tor_control_password?: string
 }
 
 // Union of network types
 type Network = LocalNetwork | InternetNetwork | DarkWebNetwork
 
 type ServerMode = 'LocalHost' | 'Internet' | 'DarkWeb'
+
+type ServerStatus =
+    | { state: 'starting' }
+    | { state: 'running'; address: string; public_address?: string }
+    | { state: 'failed'; error: string }
+    | { state: 'stopped' }
+
+interface ServerStatusEvent {
+    network: string
+    status: ServerStatus
+}
+
+interface NetworkInterface {
+    name: string
+    ip: string
+    is_loopback: boolean
+}
+
+type ShareCapability = 'read' | 'list' | 'archive' | 'write'
+
+interface ShareSummary {
+    id: string
+    name: string
+    size: number
+    file_count: number
+    capabilities: ShareCapability[]
+}
+
+interface NetworkManifest {
+    version: number
+    shares: ShareSummary[]
+}
+
+interface RemoteNetwork {
+    name: string
+    url: string
+    token: string | null
+    fingerprint: string | null
+    shares: ShareSummary[]
+    joined_at: number
+}
+
+type EntryKind = 'file' | 'directory'
+
+interface DirEntry {
+    name: string
+    type: EntryKind
+    size: number
+    modified: number | null
+    mime: string | null
+}
+
+interface DirectoryListing {
+    share: string
+    path: string
+    total: number
+    offset: number
+    entries: DirEntry[]
+}
+
+interface TreeEntry {
+    path: string
+    type: EntryKind
+    size: number
+    modified: number | null
+    sha256: string | null
+}
+
+interface ShareTree {
+    share: string
+    path: string
+    entries: TreeEntry[]
+}
+
+type ConflictPolicy = 'rename' | 'overwrite' | 'fail'
+
+interface UploadedFile {
+    path: string
+    size: number
+}
+
+interface UploadSession {
+    id: string
+    path: string
+    size: number
+    offset: number
+    sha256: string | null
+}
+
+type AuditAction = 'upload' | 'mkdir' | 'rename' | 'delete'
+
+interface AuditEntry {
+    timestamp: number
+    network: string
+    share: string
+    action: AuditAction
+    path: string
+    target: string | null
+    size: number | null
+    grant_id: string | null
+    client: string | null
+    error: string | null
+}
+
+type MirrorCompare = 'sizeAndModified' | 'hash'
+
+interface Mirror {
+    id: string
+    remote_network: string
+    share: string
+    remote_path: string
+    linked_path: string
+    interval_minutes: number | null
+    compare: MirrorCompare
+    delete_extraneous: boolean
+}
+
+interface MirrorStatus {
+    running: boolean
+    last_started: number | null
+    last_finished: number | null
+    files_downloaded: number
+    bytes_downloaded: number
+    files_deleted: number
+    error: string | null
+}
+
+type MirrorPhase =
+    | 'scanning'
+    | 'downloading'
+    | 'deleting'
+    | 'finished'
+    | 'failed'
+
+interface MirrorProgress {
+    mirror: string
+    phase: MirrorPhase
+    files_done: number
+    files_total: number
+    bytes_done: number
+    bytes_total: number
+    current: string | null
+    error: string | null
+}
+
+type FsChangeKind = 'created' | 'modified' | 'removed'
+
+interface FsChangeEvent {
+    linked_path: string
+    relative_path: string
+    kind: FsChangeKind
+}
+
+interface SyncFolder {
+    id: string
+    remote_network: string
+    share: string
+    linked_path: string
+    interval_minutes: number | null
+}
+
+interface SyncStatus {
+    running: boolean
+    last_started: number | null
+    last_finished: number | null
+    files_sent: number
+    files_received: number
+    conflicts: number
+    error: string | null
+}
+
+interface CatalogStatus {
+    linked_path: string
+    scanning: boolean
+    scanned_at: number | null
+    files: number
+    directories: number
+    bytes: number
+}
+
+interface SearchResults {
+    total: number
+    hits: SearchHit[]
+}
+
+type SearchMatch = 'name' | 'content' | 'both'
+
+interface SearchHit {
+    share: string
+    path: string
+    type: EntryKind
+    size: number
+    modified: number | null
+    score: number
+    matched: SearchMatch
+}
+
+interface DuplicateReport {
+    groups: DuplicateGroup[]
+    wasted_bytes: number
+    unhashed_files: number
+}
+
+interface DuplicateGroup {
+    sha256: string
+    size: number
+    files: DuplicateFile[]
+    wasted_bytes: number
+}
+
+interface DuplicateFile {
+    linked_path: string
+    path: string
+}
+
+interface DedupeResult {
+    linked: number
+    freed_bytes: number
+    failed: string[]
+}
+
+type OverlapPolicy = 'reject' | 'warn' | 'merge'
+
+type ConfigIssue =
+    | { type: 'overlap'; outer: string; inner: string; relative_path: string }
+    | { type: 'missingPath'; linked_path: string; path: string }